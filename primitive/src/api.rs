@@ -1,25 +1,613 @@
 use {
+    crate::error::ProviderError,
     anyhow::Result,
     async_trait::async_trait,
+    futures_util::{
+        stream::{self, BoxStream},
+        StreamExt,
+    },
     serde::{Deserialize, Serialize},
+    std::fmt,
 };
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ChatCompletionRequest {
     pub model: String, // Format "backend:model_name"
     pub messages: Vec<Message>,
     pub stream: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// Sequences ending the answer; a single string is accepted.
+    #[serde(
+        default,
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub stop: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<ToolChoice>,
+    /// Gemini safety settings, overriding the provider's per category.
+    /// Ignored by other providers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub safety_settings: Option<Vec<SafetySetting>>,
+    /// Name of a Gemini context cache holding the start of the conversation,
+    /// which `messages` continue. Ignored by other providers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cached_content: Option<String>,
+    /// Gemini built-in tools to enable. Ignored by other providers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub builtin_tools: Option<Vec<BuiltinTool>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    /// Number of candidates to generate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    /// Return the log probability of each output token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    /// Number of most likely alternatives to return per token, with
+    /// `logprobs`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub role: String,
-    pub content: String,
+    pub content: MessageContent,
+    /// Tool calls requested by the assistant.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// Id of the tool call this message answers (role `tool`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl Message {
+    pub fn new(role: impl Into<String>, content: impl Into<MessageContent>) -> Self {
+        Self {
+            role: role.into(),
+            content: content.into(),
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+}
+
+/// Content of a message: plain text, or typed parts in OpenAI's
+/// content-part format.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+impl MessageContent {
+    /// The text of the content, with text parts joined and other parts
+    /// left out.
+    pub fn text(&self) -> String {
+        match self {
+            Self::Text(text) => text.clone(),
+            Self::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Self::Text(text) => text.is_empty(),
+            Self::Parts(parts) => parts.is_empty(),
+        }
+    }
+
+    /// The content as parts; plain text is a single text part.
+    pub fn into_parts(self) -> Vec<ContentPart> {
+        match self {
+            Self::Text(text) if text.is_empty() => vec![],
+            Self::Text(text) => vec![ContentPart::Text { text }],
+            Self::Parts(parts) => parts,
+        }
+    }
+
+    /// Number of parts that are not text.
+    pub fn media_parts(&self) -> usize {
+        match self {
+            Self::Text(_) => 0,
+            Self::Parts(parts) => parts
+                .iter()
+                .filter(|part| !matches!(part, ContentPart::Text { .. }))
+                .count(),
+        }
+    }
+}
+
+impl Default for MessageContent {
+    fn default() -> Self {
+        Self::Text(String::new())
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        Self::Text(text)
+    }
+}
+
+impl From<&str> for MessageContent {
+    fn from(text: &str) -> Self {
+        Self::Text(text.to_string())
+    }
+}
+
+impl PartialEq<&str> for MessageContent {
+    fn eq(&self, other: &&str) -> bool {
+        matches!(self, Self::Text(text) if text == other)
+    }
+}
+
+impl fmt::Display for MessageContent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+    InputAudio { input_audio: InputAudio },
+    File { file: FileRef },
+}
+
+impl ContentPart {
+    /// Short name of the part's type, for error messages.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Text { .. } => "text",
+            Self::ImageUrl { .. } => "image",
+            Self::InputAudio { .. } => "audio",
+            Self::File { .. } => "file",
+        }
+    }
+}
+
+/// An image given by URL, or inline as a `data:` URL.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageUrl {
+    pub url: String,
+    /// `auto`, `low` or `high`; only used by OpenAI.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// Base64-encoded audio.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputAudio {
+    pub data: String,
+    /// `wav`, `mp3`, ...
+    pub format: String,
+}
+
+/// A file uploaded to the provider (`file_id`), or inline as a `data:` URL
+/// (`file_data`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileRef {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_data: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+}
+
+/// Split a `data:<mime type>;base64,<data>` URL into its MIME type and
+/// base64 data.
+pub fn parse_data_url(url: &str) -> Option<(&str, &str)> {
+    let (mime_type, data) = url.strip_prefix("data:")?.split_once(";base64,")?;
+    Some((mime_type, data))
+}
+
+/// A tool the model may call, in OpenAI function format.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tool {
+    #[serde(rename = "type", default = "default_tool_type")]
+    pub kind: String,
+    pub function: FunctionDefinition,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionDefinition {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub parameters: serde_json::Value,
+}
+
+/// Whether the model must call a tool, in OpenAI format: `"none"`, `"auto"`,
+/// `"required"` or `{"type": "function", "function": {"name": ...}}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ToolChoice {
+    Mode(ToolChoiceMode),
+    /// Call this function.
+    Function {
+        #[serde(rename = "type", default = "default_tool_type")]
+        kind: String,
+        function: FunctionName,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolChoiceMode {
+    None,
+    Auto,
+    /// Call at least one tool.
+    Required,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionName {
+    pub name: String,
+}
+
+impl ToolChoice {
+    pub fn function(name: impl Into<String>) -> Self {
+        Self::Function {
+            kind: default_tool_type(),
+            function: FunctionName { name: name.into() },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCall {
+    #[serde(default)]
+    pub id: String,
+    #[serde(rename = "type", default = "default_tool_type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    /// JSON-encoded arguments.
+    pub arguments: String,
+}
+
+/// Format the answer must have, in OpenAI format.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    /// Any JSON object.
+    JsonObject,
+    /// JSON matching a JSON Schema.
+    JsonSchema {
+        json_schema: JsonSchemaFormat,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub schema: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+/// Blocking threshold of a Gemini harm category, e.g.
+/// `HARM_CATEGORY_HARASSMENT` / `BLOCK_ONLY_HIGH`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SafetySetting {
+    pub category: String,
+    pub threshold: String,
+}
+
+/// A plain text (non-chat) completion request, in OpenAI's `/v1/completions`
+/// format.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionRequest {
+    pub model: String,
+    #[serde(default)]
+    pub prompt: String,
+    /// Text after the completion, for fill-in-the-middle.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suffix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// Sequences ending the completion; a single string is accepted.
+    #[serde(
+        default,
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub stop: Option<Vec<String>>,
+    /// Send the prompt as is, without the model's prompt template. Ollama
+    /// only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<bool>,
+    /// `context` of an earlier Ollama response, to continue from it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<Vec<i64>>,
+}
+
+fn one_or_many<'de, D>(deserializer: D) -> std::result::Result<Option<Vec<String>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match Option::<OneOrMany>::deserialize(deserializer)? {
+        Some(OneOrMany::One(one)) => Some(vec![one]),
+        Some(OneOrMany::Many(many)) => Some(many),
+        None => None,
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionResponse {
+    pub text: String,
+    /// `stop` or `length`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    /// Ollama's encoding of the conversation, for a follow-up request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<Vec<i64>>,
+}
+
+/// Embeddings of the inputs of a request, in order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbeddingResponse {
+    pub embeddings: Vec<Vec<f32>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+/// Messages to cache with the provider, for requests to reference with
+/// `cached_content`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateCacheRequest {
+    /// `backend:model_name`; caches only serve requests to this model.
+    pub model: String,
+    pub messages: Vec<Message>,
+    /// Lifetime of the cache in seconds, the provider's default if missing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_seconds: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+}
+
+/// A cache of messages kept by the provider.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContextCache {
+    /// Provider resource name, e.g. `cachedContents/abc123`.
+    pub name: String,
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    /// RFC 3339 timestamp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expire_time: Option<String>,
+    /// Tokens held by the cache.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_count: Option<u32>,
+}
+
+/// A model reported by a backend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelInfo {
+    /// Backend-local model name.
+    pub id: String,
+    /// Prompt tokens the model accepts, at most.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_token_limit: Option<u32>,
+    /// Tokens the model generates, at most.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_token_limit: Option<u32>,
+    /// Provider methods the model supports, e.g. Gemini's `generateContent`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub generation_methods: Vec<String>,
+}
+
+impl ModelInfo {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            input_token_limit: None,
+            output_token_limit: None,
+            generation_methods: vec![],
+        }
+    }
+}
+
+/// A tool run by the provider itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuiltinTool {
+    /// Ground answers in Google Search results.
+    GoogleSearch,
+    /// Let the model write and run Python code.
+    CodeExecution,
+}
+
+fn default_tool_type() -> String {
+    "function".into()
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+    /// Part of `prompt_tokens` served from the provider's prompt cache.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cached_tokens: Option<u32>,
+}
+
+impl Usage {
+    pub fn new(prompt_tokens: u32, completion_tokens: u32) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+            cached_tokens: None,
+        }
+    }
+}
+
+/// Adds the usage of another call, e.g. a retry.
+impl std::ops::AddAssign<&Usage> for Usage {
+    fn add_assign(&mut self, other: &Usage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+        self.cached_tokens = match (self.cached_tokens, other.cached_tokens) {
+            (None, None) => None,
+            (a, b) => Some(a.unwrap_or(0) + b.unwrap_or(0)),
+        };
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatCompletionResponse {
     pub message: Message,
+    /// OpenAI-style reason: `stop`, `length`, `tool_calls` or `content_filter`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<Logprobs>,
+    /// Provider-specific data by provider, e.g. `{"gemini": {"citations": [...]}}`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    /// Every candidate when several were generated. `message`,
+    /// `finish_reason` and `logprobs` are those of the first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub choices: Vec<Choice>,
+}
+
+impl ChatCompletionResponse {
+    pub fn new(message: Message) -> Self {
+        Self {
+            message,
+            finish_reason: None,
+            logprobs: None,
+            extensions: None,
+            usage: None,
+            choices: vec![],
+        }
+    }
+
+    /// Response made of `choices`, or `None` if there are none.
+    pub fn from_choices(mut choices: Vec<Choice>, usage: Option<Usage>) -> Option<Self> {
+        let first = choices.first()?.clone();
+        if choices.len() == 1 {
+            choices.clear();
+        }
+
+        Some(Self {
+            message: first.message,
+            finish_reason: first.finish_reason,
+            logprobs: first.logprobs,
+            extensions: first.extensions,
+            usage,
+            choices,
+        })
+    }
+}
+
+/// A piece of a streamed chat completion.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChatCompletionDelta {
+    /// Text generated since the previous piece.
+    #[serde(default)]
+    pub content: String,
+    /// Calls whose arguments are complete.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// Set once generation stopped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+/// A whole answer as a single piece.
+impl From<ChatCompletionResponse> for ChatCompletionDelta {
+    fn from(response: ChatCompletionResponse) -> Self {
+        Self {
+            content: response.message.content.text(),
+            tool_calls: response.message.tool_calls.unwrap_or_default(),
+            finish_reason: response.finish_reason,
+            usage: response.usage,
+        }
+    }
+}
+
+/// A candidate answer, in OpenAI format.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Choice {
+    pub index: u32,
+    pub message: Message,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<Logprobs>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<serde_json::Value>,
+}
+
+/// Log probabilities of the output tokens, in OpenAI format.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Logprobs {
+    #[serde(default)]
+    pub content: Vec<TokenLogprob>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<Vec<u8>>,
+    /// The most likely tokens at this position, with `top_logprobs`.
+    #[serde(default)]
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<Vec<u8>>,
 }
 
 #[async_trait]
@@ -30,20 +618,153 @@ pub trait UnifiedLlmApi: Send + Sync {
     }
 
     /// Get the list of available models from the backend.
-    async fn get_models(&self) -> Result<Vec<String>> {
+    async fn get_models(&self) -> Result<Vec<ModelInfo>> {
         Ok(vec![])
     }
 
     /// Request chat completion with a specific model.
+    ///
+    /// `model` is the backend-local model name; `request.model` still holds
+    /// the original `backend:model_name` identifier.
     async fn chat_completion(
         &self,
         model: &str,
-        messages: Vec<Message>,
-        stream: Option<bool>,
+        request: ChatCompletionRequest,
     ) -> Result<ChatCompletionResponse>;
 
-    /// Request LLM embed with a specific model.
-    async fn embed(&self) -> Result<()> {
-        Ok(())
+    /// Stream the answer to `request` as it is generated.
+    ///
+    /// By default the answer of `chat_completion` is sent as one piece, for
+    /// backends that cannot stream.
+    async fn chat_completion_stream(
+        &self,
+        model: &str,
+        request: ChatCompletionRequest,
+    ) -> Result<BoxStream<'static, Result<ChatCompletionDelta>>> {
+        let response = self.chat_completion(model, request).await?;
+        Ok(stream::iter([Ok(ChatCompletionDelta::from(response))]).boxed())
+    }
+
+    /// Whether the backend generates `n` candidates itself. Otherwise the
+    /// gateway makes `n` calls in parallel.
+    fn supports_n(&self) -> bool {
+        false
+    }
+
+    /// Count the prompt tokens of `request` with the provider's tokenizer.
+    ///
+    /// Returns `None` when the provider cannot count tokens, in which case
+    /// callers fall back to `tokens::count_request`.
+    async fn count_tokens(
+        &self,
+        _model: &str,
+        _request: &ChatCompletionRequest,
+    ) -> Result<Option<u32>> {
+        Ok(None)
+    }
+
+    /// Whether the backend can cache messages (`create_cache`, ...).
+    fn supports_caching(&self) -> bool {
+        false
+    }
+
+    /// Cache the messages of `request` for `model`.
+    async fn create_cache(
+        &self,
+        _model: &str,
+        _request: CreateCacheRequest,
+    ) -> Result<ContextCache> {
+        Err(ProviderError::InvalidRequest("the provider has no context caching".into()).into())
+    }
+
+    /// The backend's context caches.
+    async fn list_caches(&self) -> Result<Vec<ContextCache>> {
+        Ok(vec![])
+    }
+
+    /// Delete a context cache, by name or by the last segment of its name.
+    async fn delete_cache(&self, _name: &str) -> Result<()> {
+        Err(ProviderError::InvalidRequest("the provider has no context caching".into()).into())
+    }
+
+    /// Complete `request.prompt` as plain text.
+    async fn completion(
+        &self,
+        _model: &str,
+        _request: CompletionRequest,
+    ) -> Result<CompletionResponse> {
+        Err(ProviderError::InvalidRequest("the provider has no text completion".into()).into())
+    }
+
+    /// Management of the backend's models, if it has any.
+    fn model_manager(&self) -> Option<&dyn ModelManager> {
+        None
+    }
+
+    /// Embed each of `input` with a specific model.
+    async fn embed(&self, _model: &str, _input: Vec<String>) -> Result<EmbeddingResponse> {
+        Err(ProviderError::InvalidRequest("the provider has no embeddings".into()).into())
     }
 }
+
+/// Management of the models a backend serves, e.g. an Ollama server's.
+#[async_trait]
+pub trait ModelManager: Send + Sync {
+    /// Download `model`, reporting progress until it is done.
+    async fn pull_model(&self, model: &str) -> Result<BoxStream<'static, Result<PullProgress>>>;
+
+    async fn delete_model(&self, model: &str) -> Result<()>;
+
+    async fn show_model(&self, model: &str) -> Result<ModelDetails>;
+
+    /// Models loaded in memory.
+    async fn running_models(&self) -> Result<Vec<RunningModel>>;
+
+    /// Load `model` and keep it in memory for `keep_alive` (e.g. `10m`, or
+    /// `-1` for ever); `0` unloads it.
+    async fn keep_alive(&self, model: &str, keep_alive: &str) -> Result<()>;
+}
+
+/// A step of a model download.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PullProgress {
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    /// Bytes of the layer being downloaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelDetails {
+    #[serde(default)]
+    pub modelfile: String,
+    /// Default generation parameters, one per line.
+    #[serde(default)]
+    pub parameters: String,
+    #[serde(default)]
+    pub template: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<u64>,
+    /// Family, parameter size, quantization, ...
+    #[serde(default)]
+    pub details: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunningModel {
+    pub name: String,
+    /// Bytes of memory used, of which `size_vram` on the GPU.
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub size_vram: u64,
+    /// When the model is unloaded, as an RFC 3339 timestamp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<u64>,
+}
@@ -1,20 +1,57 @@
 #![allow(dead_code)]
 
+mod interpolate;
+mod validate;
+
+pub use validate::{check_url, schema};
+
 use {
-    crate::error::ConfigError,
-    serde::Deserialize,
-    std::{net::SocketAddr, path::PathBuf},
+    crate::{
+        api::Usage,
+        error::{ConfigError, ConfigIssue},
+        registry::ProviderRegistry,
+    },
+    schemars::JsonSchema,
+    serde::{de::DeserializeOwned, Deserialize},
+    std::{
+        collections::{BTreeMap, HashMap},
+        net::{SocketAddr, ToSocketAddrs},
+        path::PathBuf,
+    },
 };
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct TopkioConfig {
     pub server: ServerConfig,
     pub rate_limit: Option<RateLimitConfig>,
     pub logging: LoggingConfig,
     pub providers: ProvidersConfig,
+    /// Enables the `/admin` API when set.
+    pub admin: Option<AdminConfig>,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    /// Enables per-team virtual API keys when set.
+    pub virtual_keys: Option<VirtualKeysConfig>,
+    /// Records every request for the `/v1/usage` report when set.
+    pub usage: Option<UsageConfig>,
+    /// Prices per `backend:model` (or `backend:*`), used to compute the
+    /// cost of each request.
+    #[serde(default)]
+    pub pricing: BTreeMap<String, ModelPrice>,
+    #[serde(default)]
+    pub context: ContextConfig,
+    #[serde(default)]
+    pub structured_output: StructuredOutputConfig,
+    /// Model aliases, e.g. `fast = "gemini:gemini-2.0-flash"`.
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+    /// Stops sending requests to failing backends when set.
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
@@ -26,9 +63,13 @@ pub struct ServerConfig {
     pub graceful_shutdown_seconds: u64,
     #[serde(default = "default_enabled")]
     pub enable_custom_shutdown: bool,
+    /// Reload the config when the file changes (SIGHUP always reloads).
+    #[serde(default = "default_enabled")]
+    pub watch_config: bool,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct RateLimitConfig {
     #[serde(default = "default_enabled")]
     pub enabled: bool,
@@ -37,7 +78,8 @@ pub struct RateLimitConfig {
     pub burst_size: u32,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct LoggingConfig {
     #[serde(default = "default_log_level")]
     pub level: String,
@@ -46,16 +88,113 @@ pub struct LoggingConfig {
     pub enable_console: bool,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct ProvidersConfig {
-    pub openai: Option<ProviderConfig>,
-    pub gemini: Option<ProviderConfig>,
-    pub ollama: Option<ProviderConfig>,
-    pub deepseek: Option<ProviderConfig>,
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct AdminConfig {
+    /// Bearer token required by every `/admin` request.
+    pub api_key: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Bearer tokens accepted by the client API. Empty means no authentication.
+    #[serde(default)]
+    pub api_keys: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct VirtualKeysConfig {
+    /// SQLite database holding the keys and their usage, created if missing.
+    pub database: PathBuf,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ContextConfig {
+    /// What to do with requests that don't fit the model's context window.
+    #[serde(default)]
+    pub overflow: ContextOverflow,
+}
+
+/// Checking of answers to requests with a `response_format`.
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct StructuredOutputConfig {
+    /// How often the model is asked again when its answer is not valid JSON
+    /// or does not match the schema.
+    #[serde(default)]
+    pub retries: u32,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive upstream failures after which a backend's circuit opens.
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// How long an open circuit rejects requests before letting them
+    /// through again to probe the backend.
+    #[serde(default = "default_open_seconds")]
+    pub open_seconds: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextOverflow {
+    /// Reject the request with `400 Bad Request`.
+    #[default]
+    Reject,
+    /// Drop the oldest turns, keeping system messages and the last message.
+    Truncate,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct UsageConfig {
+    /// SQLite database holding the usage log, created if missing.
+    pub database: PathBuf,
+}
+
+/// Prices in USD per million tokens.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub output: f64,
+    /// Price of prompt tokens served from the provider's cache. Defaults to
+    /// `input`.
+    pub cached_input: Option<f64>,
+}
+
+impl ModelPrice {
+    /// Cost of a request in USD.
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        let cached = usage.cached_tokens.unwrap_or(0).min(usage.prompt_tokens);
+        let uncached = usage.prompt_tokens - cached;
+
+        (uncached as f64 * self.input
+            + cached as f64 * self.cached_input.unwrap_or(self.input)
+            + usage.completion_tokens as f64 * self.output)
+            / 1_000_000.0
+    }
+}
+
+/// Provider instances keyed by their name, which is also the backend part
+/// of `backend:model` identifiers (e.g. `ollama-gpu:llama3.2`).
+pub type ProvidersConfig = BTreeMap<String, ProviderConfig>;
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct ProviderConfig {
+    /// Provider type used to pick a factory from the `ProviderRegistry`.
+    /// Defaults to the instance name, so `[providers.ollama]` needs no `type`.
+    #[serde(rename = "type", default)]
+    pub kind: String,
+    /// Disabled (drained) providers reject new requests.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
     pub url: String,
     #[serde(default)]
     pub api_key: Option<String>,
@@ -63,13 +202,103 @@ pub struct ProviderConfig {
     pub model: Option<String>,
     #[serde(default)]
     pub supported_models: Vec<String>,
+    /// Context window per model, for models not known to `tokens::context_window`.
+    #[serde(default)]
+    pub context_windows: BTreeMap<String, u32>,
     #[serde(default = "default_max_retries")]
     pub max_retries: u32,
     #[serde(default = "default_retry_delay_ms")]
     pub retry_delay_ms: u64,
+    /// Type-specific settings, parsed by the provider factory.
+    #[serde(flatten)]
+    #[schemars(skip)]
+    pub options: toml::Table,
+}
+
+impl ProviderConfig {
+    /// Deserialize the type-specific settings of this provider.
+    pub fn options<T: DeserializeOwned>(&self) -> Result<T, ConfigError> {
+        T::deserialize(toml::Value::Table(self.options.clone())).map_err(|e| {
+            let message = e.to_string().trim().replace('\n', " ");
+            ConfigError::InvalidConfig(format!("{} provider: {}", self.kind, message))
+        })
+    }
+
+    /// Report type-specific settings for provider types that take none.
+    pub fn unknown_options(&self, name: &str) -> Vec<ConfigIssue> {
+        self.options
+            .keys()
+            .map(|key| {
+                ConfigIssue::new(
+                    format!("providers.{}.{}", name, key),
+                    format!("unknown key for a {} provider", self.kind),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Type-specific settings of `type = "azure"` providers.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct AzureProviderConfig {
+    #[serde(default = "default_azure_api_version")]
+    pub api_version: String,
+    /// Resources (typically one per region) used as a load-balanced pool.
+    pub resources: Vec<AzureResourceConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct AzureResourceConfig {
+    pub url: String,
+    pub api_key: String,
+    /// Gateway model name -> Azure deployment name.
+    #[serde(default)]
+    pub deployments: HashMap<String, String>,
+}
+
+/// Type-specific settings of `type = "ollama"` providers.
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct OllamaProviderConfig {
+    /// Pull models of `supported_models` missing from the server on their
+    /// first request.
+    #[serde(default)]
+    pub auto_pull: bool,
+}
+
+/// Type-specific settings of `type = "gemini"` providers.
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct GeminiProviderConfig {
+    /// Service-account key (JSON) used for OAuth instead of `api_key`,
+    /// usually given as `credentials_file`.
+    pub credentials: Option<String>,
+    /// Google Cloud project; switches the provider to Vertex AI.
+    pub project: Option<String>,
+    /// Vertex AI location, e.g. `us-central1` or `global`.
+    #[serde(default = "default_vertex_location")]
+    pub location: String,
+    /// Model (or `*` for every model) -> harm category -> blocking
+    /// threshold, e.g. `"*" = { HARM_CATEGORY_HARASSMENT = "BLOCK_ONLY_HIGH" }`.
+    #[serde(default)]
+    pub safety_settings: BTreeMap<String, BTreeMap<String, String>>,
+    /// Cache conversation prefixes of at least this many tokens when they
+    /// are sent more than once. Off when missing.
+    pub auto_cache_min_tokens: Option<u32>,
+    /// Lifetime of automatic caches, in seconds.
+    #[serde(default = "default_cache_ttl")]
+    pub cache_ttl: u64,
 }
 
 // Default values
+fn default_vertex_location() -> String {
+    "us-central1".into()
+}
+fn default_cache_ttl() -> u64 {
+    3600
+}
 fn default_timeout() -> u64 {
     30
 }
@@ -97,30 +326,81 @@ fn default_retry_delay_ms() -> u64 {
 fn default_graceful_shutdown_seconds() -> u64 {
     5
 }
+fn default_failure_threshold() -> u32 {
+    5
+}
+fn default_open_seconds() -> u64 {
+    30
+}
+fn default_azure_api_version() -> String {
+    "2024-10-21".into()
+}
 
 impl TopkioConfig {
+    /// Load and validate the config, without provider type specific checks.
     pub fn load(path: &str) -> Result<Self, ConfigError> {
+        Self::load_checked(path, None, toml::Table::new())
+    }
+
+    /// Load the config and validate it, including the provider settings
+    /// understood by the factories in `registry`.
+    ///
+    /// Every problem found is reported at once in `ConfigError::Invalid`.
+    pub fn check(path: &str, registry: &ProviderRegistry) -> Result<Self, ConfigError> {
+        Self::load_checked(path, Some(registry), toml::Table::new())
+    }
+
+    /// Like `check`, with `overrides` (e.g. `server.port` from the command
+    /// line) merged into the file before it is validated.
+    pub fn check_overridden(
+        path: &str,
+        registry: &ProviderRegistry,
+        overrides: toml::Table,
+    ) -> Result<Self, ConfigError> {
+        Self::load_checked(path, Some(registry), overrides)
+    }
+
+    fn load_checked(
+        path: &str,
+        registry: Option<&ProviderRegistry>,
+        overrides: toml::Table,
+    ) -> Result<Self, ConfigError> {
         let config_str =
             std::fs::read_to_string(path).map_err(|_| ConfigError::FileNotFound(path.into()))?;
 
-        let config: TopkioConfig =
-            toml::from_str(&config_str).map_err(|e| ConfigError::InvalidConfig(e.to_string()))?;
+        validate::parse(&config_str, registry, overrides)
+    }
 
-        // Post-load validation
-        if let Some(openai) = &config.providers.openai {
-            if openai.api_key.is_none() && std::env::var("OPENAI_API_KEY").is_err() {
-                return Err(ConfigError::MissingField(
-                    "providers.openai.api_key or OPENAI_API_KEY".into(),
-                ));
-            }
-        }
+    /// Context window of `model` on `backend`, as configured or well-known.
+    pub fn context_window(&self, backend: &str, model: &str) -> Option<u32> {
+        self.providers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(backend))
+            .and_then(|(_, provider)| provider.context_windows.get(model).copied())
+            .or_else(|| crate::tokens::context_window(model))
+    }
 
-        Ok(config)
+    /// Price of `model` on `backend`, falling back to `backend:*`.
+    pub fn price(&self, backend: &str, model: &str) -> Option<&ModelPrice> {
+        let find = |model: &str| {
+            self.pricing.iter().find_map(|(key, price)| {
+                let (b, m) = key.split_once(':')?;
+                (b.eq_ignore_ascii_case(backend) && m == model).then_some(price)
+            })
+        };
+        find(model).or_else(|| find("*"))
     }
 
-    pub fn socket_addr(&self) -> SocketAddr {
-        format!("{}:{}", self.server.host, self.server.port)
-            .parse()
-            .expect("Invalid server address")
+    pub fn socket_addr(&self) -> Result<SocketAddr, ConfigError> {
+        (self.server.host.as_str(), self.server.port)
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .ok_or_else(|| {
+                ConfigError::InvalidConfig(format!(
+                    "Invalid server address {}:{}",
+                    self.server.host, self.server.port
+                ))
+            })
     }
 }
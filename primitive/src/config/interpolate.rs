@@ -0,0 +1,230 @@
+//! `${VAR}` / `${VAR:-default}` interpolation and `*_file` secret loading,
+//! applied to the parsed TOML before it is deserialized.
+
+use {crate::error::ConfigIssue, toml::Value};
+
+/// Suffix of keys whose value is a file to read into the sibling key,
+/// e.g. `api_key_file = "/run/secrets/gemini"` sets `api_key`.
+const FILE_SUFFIX: &str = "_file";
+
+/// Interpolate every string in `value`, returning all problems found.
+pub fn interpolate(value: &mut Value) -> Vec<ConfigIssue> {
+    let mut issues = vec![];
+    interpolate_value(value, "", &mut issues);
+    issues
+}
+
+fn interpolate_value(value: &mut Value, path: &str, issues: &mut Vec<ConfigIssue>) {
+    match value {
+        Value::String(s) => match interpolate_str(s) {
+            Ok(interpolated) => *s = interpolated,
+            Err(message) => issues.push(ConfigIssue::new(path, message)),
+        },
+        Value::Array(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                interpolate_value(item, &format!("{}[{}]", path, i), issues);
+            }
+        }
+        Value::Table(table) => {
+            for (key, item) in table.iter_mut() {
+                interpolate_value(item, &join(path, key), issues);
+            }
+
+            let file_keys: Vec<String> = table
+                .keys()
+                .filter(|key| key.ends_with(FILE_SUFFIX) && table[*key].is_str())
+                .cloned()
+                .collect();
+
+            for file_key in file_keys {
+                let key = file_key.trim_end_matches(FILE_SUFFIX).to_string();
+                if table.contains_key(&key) {
+                    issues.push(ConfigIssue::new(
+                        join(path, &file_key),
+                        format!("cannot be combined with {}", key),
+                    ));
+                    continue;
+                }
+
+                let Some(Value::String(file)) = table.remove(&file_key) else {
+                    continue;
+                };
+                match std::fs::read_to_string(&file) {
+                    Ok(secret) => {
+                        table.insert(key, Value::String(secret.trim_end().to_string()));
+                    }
+                    Err(e) => issues.push(ConfigIssue::new(
+                        join(path, &file_key),
+                        format!("cannot read secret file {}: {}", file, e),
+                    )),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Expand `${VAR}` and `${VAR:-default}`; `$${` yields a literal `${`.
+fn interpolate_str(input: &str) -> Result<String, String> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(start) = rest.find('$') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(escaped) = rest.strip_prefix("$${") {
+            output.push_str("${");
+            rest = escaped;
+            continue;
+        }
+
+        let Some(expr) = rest.strip_prefix("${") else {
+            output.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+
+        let end = expr
+            .find('}')
+            .ok_or_else(|| format!("unterminated ${{ in \"{}\"", input))?;
+        let (name, default) = match expr[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&expr[..end], None),
+        };
+
+        match (std::env::var(name), default) {
+            (Ok(value), Some(default)) if value.is_empty() => output.push_str(default),
+            (Ok(value), _) => output.push_str(&value),
+            (Err(_), Some(default)) => output.push_str(default),
+            (Err(_), None) => return Err(format!("environment variable {} is not set", name)),
+        }
+        rest = &expr[end + 1..];
+    }
+    output.push_str(rest);
+
+    Ok(output)
+}
+
+pub(super) fn join(path: &str, key: &str) -> String {
+    match path.is_empty() {
+        true => key.to_string(),
+        false => format!("{}.{}", path, key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(toml: &str) -> Value {
+        toml.parse::<toml::Table>().map(Value::Table).unwrap()
+    }
+
+    #[test]
+    fn expands_set_variables() {
+        std::env::set_var("TOPKIO_TEST_SET", "secret");
+        assert_eq!(
+            interpolate_str("key-${TOPKIO_TEST_SET}!").unwrap(),
+            "key-secret!"
+        );
+        assert_eq!(
+            interpolate_str("${TOPKIO_TEST_SET:-fallback}").unwrap(),
+            "secret"
+        );
+    }
+
+    #[test]
+    fn uses_defaults_for_unset_and_empty_variables() {
+        std::env::remove_var("TOPKIO_TEST_UNSET");
+        std::env::set_var("TOPKIO_TEST_EMPTY", "");
+        assert_eq!(
+            interpolate_str("${TOPKIO_TEST_UNSET:-fallback}").unwrap(),
+            "fallback"
+        );
+        assert_eq!(
+            interpolate_str("${TOPKIO_TEST_EMPTY:-fallback}").unwrap(),
+            "fallback"
+        );
+        assert_eq!(interpolate_str("${TOPKIO_TEST_UNSET:-}").unwrap(), "");
+    }
+
+    #[test]
+    fn names_missing_variables() {
+        std::env::remove_var("TOPKIO_TEST_MISSING");
+        let error = interpolate_str("${TOPKIO_TEST_MISSING}").unwrap_err();
+        assert_eq!(error, "environment variable TOPKIO_TEST_MISSING is not set");
+    }
+
+    #[test]
+    fn keeps_escapes_and_lone_dollars() {
+        assert_eq!(interpolate_str("$${HOME}").unwrap(), "${HOME}");
+        assert_eq!(interpolate_str("cost: $5 $").unwrap(), "cost: $5 $");
+    }
+
+    #[test]
+    fn rejects_unterminated_expressions() {
+        let error = interpolate_str("url-${HOST").unwrap_err();
+        assert!(error.starts_with("unterminated ${"), "{}", error);
+    }
+
+    #[test]
+    fn reports_issues_with_their_path() {
+        std::env::remove_var("TOPKIO_TEST_NESTED");
+        let mut value = parse(
+            r#"
+            [providers.gemini]
+            api_key = "${TOPKIO_TEST_NESTED}"
+            supported_models = ["ok", "${TOPKIO_TEST_NESTED}"]
+            "#,
+        );
+
+        let issues = interpolate(&mut value);
+        let paths: Vec<_> = issues.iter().map(|issue| issue.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "providers.gemini.api_key",
+                "providers.gemini.supported_models[1]"
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_secret_files() {
+        let file = std::env::temp_dir().join(format!("topkio-secret-{}", std::process::id()));
+        std::fs::write(&file, "from-file\n").unwrap();
+        let mut value = parse(&format!(
+            "[providers.anthropic]\napi_key_file = {:?}\n",
+            file.to_str().unwrap()
+        ));
+
+        let issues = interpolate(&mut value);
+        std::fs::remove_file(&file).unwrap();
+
+        assert!(issues.is_empty(), "{:?}", issues);
+        let provider = &value["providers"]["anthropic"];
+        assert_eq!(provider["api_key"].as_str(), Some("from-file"));
+        assert!(provider.get("api_key_file").is_none());
+    }
+
+    #[test]
+    fn rejects_secret_files_alongside_values_and_missing_files() {
+        let mut value = parse(
+            r#"
+            [providers.a]
+            api_key = "inline"
+            api_key_file = "/run/secrets/a"
+            [providers.b]
+            api_key_file = "/nonexistent/topkio-secret"
+            "#,
+        );
+
+        let issues = interpolate(&mut value);
+        assert_eq!(issues.len(), 2, "{:?}", issues);
+        assert_eq!(issues[0].path, "providers.a.api_key_file");
+        assert_eq!(issues[0].message, "cannot be combined with api_key");
+        assert_eq!(issues[1].path, "providers.b.api_key_file");
+        assert!(issues[1].message.starts_with("cannot read secret file"));
+    }
+}
@@ -0,0 +1,705 @@
+//! Validation of `topkio.toml`, reporting every problem at once with the
+//! line it was found on.
+
+use {
+    super::{interpolate, ProviderConfig, TopkioConfig},
+    crate::{
+        error::{ConfigError, ConfigIssue},
+        registry::ProviderRegistry,
+    },
+    jsonschema::error::ValidationErrorKind,
+    serde::Deserialize,
+    std::{collections::HashSet, net::IpAddr, ops::Range},
+    toml_edit::{ArrayOfTables, ImDocument, Item, TableLike},
+};
+
+const LOG_LEVELS: [&str; 5] = ["trace", "debug", "info", "warn", "error"];
+const MAX_RETRIES: u32 = 10;
+const MAX_RETRY_DELAY_MS: u64 = 60_000;
+
+/// JSON Schema of `topkio.toml`, e.g. for editor completion.
+pub fn schema() -> serde_json::Value {
+    serde_json::to_value(schemars::schema_for!(TopkioConfig)).expect("schema is valid JSON")
+}
+
+/// Check that `url` is an absolute http(s) URL.
+pub fn check_url(path: &str, url: &str) -> Option<ConfigIssue> {
+    match reqwest::Url::parse(url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.has_host() => None,
+        Ok(_) => Some(ConfigIssue::new(path, "must be an http(s) URL")),
+        Err(e) => Some(ConfigIssue::new(
+            path,
+            format!("invalid URL \"{}\": {}", url, e),
+        )),
+    }
+}
+
+pub(super) fn parse(
+    source: &str,
+    registry: Option<&ProviderRegistry>,
+    overrides: toml::Table,
+) -> Result<TopkioConfig, ConfigError> {
+    let document = ImDocument::parse(source).map_err(|e| {
+        let mut issue = ConfigIssue::new("", e.message().trim());
+        issue.line = e.span().map(|span| line_of(source, span.start));
+        ConfigError::Invalid(vec![issue])
+    })?;
+    let mut value: toml::Value =
+        toml::from_str(source).map_err(|e| ConfigError::InvalidConfig(e.to_string()))?;
+
+    let mut issues = interpolate::interpolate(&mut value);
+    merge(&mut value, overrides);
+    issues.extend(check_schema(&value));
+
+    // Drop the values already reported so the remaining ones can still be
+    // deserialized and checked.
+    for issue in &issues {
+        remove_path(&mut value, &issue.path);
+    }
+
+    let config = match TopkioConfig::deserialize(value) {
+        Ok(mut config) => {
+            for (name, provider) in config.providers.iter_mut() {
+                if provider.kind.is_empty() {
+                    provider.kind = name.clone();
+                }
+            }
+
+            // Don't report a value again when its interpolation already failed.
+            let reported: HashSet<String> = issues.iter().map(|i| i.path.clone()).collect();
+            issues.extend(
+                check_values(&config, registry)
+                    .into_iter()
+                    .filter(|issue| !reported.contains(&issue.path)),
+            );
+            Some(config)
+        }
+        Err(e) => {
+            if issues.is_empty() {
+                issues.push(ConfigIssue::new("", e.to_string()));
+            }
+            None
+        }
+    };
+
+    match (config, issues.is_empty()) {
+        (Some(config), true) => Ok(config),
+        _ => {
+            for issue in issues.iter_mut() {
+                issue.line = locate(&document, &issue.path).map(|span| line_of(source, span.start));
+            }
+            issues.sort_by_key(|issue| issue.line);
+            Err(ConfigError::Invalid(issues))
+        }
+    }
+}
+
+/// Set the values of `overrides` in `value`, keeping the rest of the tables
+/// they are in.
+fn merge(value: &mut toml::Value, overrides: toml::Table) {
+    for (key, new) in overrides {
+        match (value.get_mut(&key), new) {
+            (Some(current @ toml::Value::Table(_)), toml::Value::Table(new)) => merge(current, new),
+            (_, new) => {
+                if let toml::Value::Table(table) = value {
+                    table.insert(key, new);
+                }
+            }
+        }
+    }
+}
+
+/// Structural checks (unknown keys, types, required fields) against the schema.
+fn check_schema(value: &toml::Value) -> Vec<ConfigIssue> {
+    let instance = match serde_json::to_value(value) {
+        Ok(instance) => instance,
+        Err(e) => return vec![ConfigIssue::new("", e.to_string())],
+    };
+    let validator = jsonschema::validator_for(&schema()).expect("config schema compiles");
+
+    validator
+        .iter_errors(&instance)
+        .flat_map(|error| {
+            let path = pointer_to_path(error.instance_path().as_str());
+            match error.kind() {
+                ValidationErrorKind::AdditionalProperties { unexpected } => unexpected
+                    .iter()
+                    .map(|key| ConfigIssue::new(interpolate::join(&path, key), "unknown key"))
+                    .collect(),
+                _ => vec![ConfigIssue::new(path, error.to_string())],
+            }
+        })
+        .collect()
+}
+
+/// Checks on values that the schema cannot express.
+fn check_values(config: &TopkioConfig, registry: Option<&ProviderRegistry>) -> Vec<ConfigIssue> {
+    let mut issues = vec![];
+    let server = &config.server;
+
+    if server.host.parse::<IpAddr>().is_err() && !is_hostname(&server.host) {
+        issues.push(ConfigIssue::new(
+            "server.host",
+            format!("\"{}\" is not an IP address or hostname", server.host),
+        ));
+    }
+    if server.port == 0 {
+        issues.push(ConfigIssue::new(
+            "server.port",
+            "must be between 1 and 65535",
+        ));
+    }
+    if server.timeout_seconds == 0 {
+        issues.push(ConfigIssue::new(
+            "server.timeout_seconds",
+            "must be positive",
+        ));
+    }
+    if server.max_connections == 0 {
+        issues.push(ConfigIssue::new(
+            "server.max_connections",
+            "must be positive",
+        ));
+    }
+
+    if let Some(rate_limit) = config.rate_limit.as_ref().filter(|r| r.enabled) {
+        if rate_limit.requests_per_minute == 0 {
+            issues.push(ConfigIssue::new(
+                "rate_limit.requests_per_minute",
+                "must be positive",
+            ));
+        }
+        if rate_limit.burst_size == 0 {
+            issues.push(ConfigIssue::new(
+                "rate_limit.burst_size",
+                "must be positive",
+            ));
+        }
+    }
+
+    if !LOG_LEVELS.contains(&config.logging.level.to_lowercase().as_str()) {
+        issues.push(ConfigIssue::new(
+            "logging.level",
+            format!(
+                "unknown log level \"{}\", expected one of {}",
+                config.logging.level,
+                LOG_LEVELS.join(", ")
+            ),
+        ));
+    }
+
+    if let Some(circuit_breaker) = &config.circuit_breaker {
+        if circuit_breaker.failure_threshold == 0 {
+            issues.push(ConfigIssue::new(
+                "circuit_breaker.failure_threshold",
+                "must be positive",
+            ));
+        }
+        if circuit_breaker.open_seconds == 0 {
+            issues.push(ConfigIssue::new(
+                "circuit_breaker.open_seconds",
+                "must be positive",
+            ));
+        }
+    }
+
+    if let Some(admin) = &config.admin {
+        if admin.api_key.is_empty() {
+            issues.push(ConfigIssue::new("admin.api_key", "must not be empty"));
+        }
+    }
+
+    for (alias, target) in &config.aliases {
+        if let Some(message) = check_model(config, target) {
+            issues.push(ConfigIssue::new(format!("aliases.{}", alias), message));
+        }
+    }
+    for (name, provider) in &config.providers {
+        if let Some(model) = &provider.model {
+            if !provider.supported_models.is_empty() && !provider.supported_models.contains(model) {
+                issues.push(ConfigIssue::new(
+                    format!("providers.{}.model", name),
+                    format!("\"{}\" is not in supported_models", model),
+                ));
+            }
+        }
+    }
+
+    for (model, price) in &config.pricing {
+        let path = format!("pricing.{}", model);
+        match model.split_once(':') {
+            Some((backend, _))
+                if config
+                    .providers
+                    .keys()
+                    .any(|name| name.eq_ignore_ascii_case(backend)) => {}
+            Some((backend, _)) => issues.push(ConfigIssue::new(
+                &path,
+                format!("unknown backend \"{}\"", backend),
+            )),
+            None => issues.push(ConfigIssue::new(
+                &path,
+                "key must be \"backend:model\" or \"backend:*\"",
+            )),
+        }
+        let prices = [price.input, price.output, price.cached_input.unwrap_or(0.0)];
+        if prices.iter().any(|p| !p.is_finite() || *p < 0.0) {
+            issues.push(ConfigIssue::new(&path, "prices must not be negative"));
+        }
+    }
+
+    for (name, provider) in &config.providers {
+        if provider.max_retries > MAX_RETRIES {
+            issues.push(ConfigIssue::new(
+                format!("providers.{}.max_retries", name),
+                format!("must be at most {}", MAX_RETRIES),
+            ));
+        }
+        if provider.retry_delay_ms > MAX_RETRY_DELAY_MS {
+            issues.push(ConfigIssue::new(
+                format!("providers.{}.retry_delay_ms", name),
+                format!("must be at most {}", MAX_RETRY_DELAY_MS),
+            ));
+        }
+        for (model, window) in &provider.context_windows {
+            if *window == 0 {
+                issues.push(ConfigIssue::new(
+                    format!("providers.{}.context_windows.{}", name, model),
+                    "must be positive",
+                ));
+            }
+        }
+        let Some(registry) = registry else {
+            continue;
+        };
+        match registry.get(&provider.kind) {
+            Some(factory) => issues.extend(factory.validate(name, provider)),
+            None => issues.push(ConfigIssue::new(
+                format!("providers.{}.type", name),
+                format!(
+                    "unknown provider type \"{}\", expected one of {}",
+                    provider.kind,
+                    registry.kinds().join(", ")
+                ),
+            )),
+        }
+    }
+
+    issues
+}
+
+/// Check that a model name (e.g. an alias target) resolves like requested
+/// models do: `backend:model`, `backend` or `backend:` for its default
+/// model, or a bare name listed in some backend's `supported_models`.
+fn check_model(config: &TopkioConfig, target: &str) -> Option<String> {
+    let provider = |name: &str| {
+        config
+            .providers
+            .iter()
+            .find(|(provider, _)| provider.eq_ignore_ascii_case(name))
+            .map(|(_, provider)| provider)
+    };
+    let default_model = |backend: &str, provider: &ProviderConfig| match provider.model {
+        Some(_) => None,
+        None => Some(format!("backend \"{}\" has no default model", backend)),
+    };
+
+    match target.split_once(':') {
+        Some((backend, model)) => match provider(backend) {
+            Some(provider) if model.trim().is_empty() => default_model(backend, provider),
+            Some(_) => None,
+            None if is_supported(config, target) => None,
+            None => Some(format!("unknown backend \"{}\"", backend)),
+        },
+        None => match provider(target) {
+            Some(provider) => default_model(target, provider),
+            None if is_supported(config, target) => None,
+            None => Some(format!(
+                "\"{}\" is neither a backend nor in any supported_models",
+                target
+            )),
+        },
+    }
+}
+
+fn is_supported(config: &TopkioConfig, model: &str) -> bool {
+    config
+        .providers
+        .values()
+        .any(|provider| provider.supported_models.iter().any(|m| m == model))
+}
+
+/// Remove the value at `path`, leaving the rest of the document intact.
+fn remove_path(value: &mut toml::Value, path: &str) {
+    let Some((parent, key)) = path.rsplit_once('.') else {
+        if let (toml::Value::Table(table), false) = (&mut *value, path.contains('[')) {
+            table.remove(path);
+        }
+        return;
+    };
+
+    let mut current = value;
+    for segment in parent.split('.') {
+        let (key, indices) = match segment.split_once('[') {
+            Some((key, indices)) => (key, Some(indices)),
+            None => (segment, None),
+        };
+        let Some(next) = current.get_mut(key) else {
+            return;
+        };
+        current = next;
+        let indices = indices
+            .into_iter()
+            .flat_map(|i| i.split('['))
+            .filter_map(|i| i.trim_end_matches(']').parse::<usize>().ok());
+        for index in indices {
+            let Some(next) = current.get_mut(index) else {
+                return;
+            };
+            current = next;
+        }
+    }
+
+    if let (toml::Value::Table(table), false) = (current, key.contains('[')) {
+        table.remove(key);
+    }
+}
+
+fn is_hostname(host: &str) -> bool {
+    !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// `/providers/azure/resources/0/url` -> `providers.azure.resources[0].url`
+fn pointer_to_path(pointer: &str) -> String {
+    let mut path = String::new();
+    for segment in pointer.split('/').skip(1) {
+        let segment = segment.replace("~1", "/").replace("~0", "~");
+        match segment.parse::<usize>() {
+            Ok(index) => path.push_str(&format!("[{}]", index)),
+            Err(_) => path = interpolate::join(&path, &segment),
+        }
+    }
+    path
+}
+
+enum Node<'a> {
+    Table(&'a dyn TableLike),
+    Tables(&'a ArrayOfTables),
+    Value(&'a toml_edit::Value),
+}
+
+impl<'a> Node<'a> {
+    fn from_item(item: &'a Item) -> Option<Self> {
+        match item {
+            Item::Table(table) => Some(Node::Table(table)),
+            Item::ArrayOfTables(tables) => Some(Node::Tables(tables)),
+            Item::Value(value) => Some(Node::from_value(value)),
+            Item::None => None,
+        }
+    }
+
+    fn from_value(value: &'a toml_edit::Value) -> Self {
+        match value {
+            toml_edit::Value::InlineTable(table) => Node::Table(table),
+            value => Node::Value(value),
+        }
+    }
+}
+
+/// Find the span of the deepest existing key along `path`.
+fn locate(document: &ImDocument<&str>, path: &str) -> Option<Range<usize>> {
+    let mut node = Node::Table(document.as_table());
+    let mut span = None;
+
+    for segment in path.split('.').filter(|s| !s.is_empty()) {
+        let (key, indices) = match segment.split_once('[') {
+            Some((key, indices)) => (key, Some(indices)),
+            None => (segment, None),
+        };
+
+        let Node::Table(table) = node else {
+            break;
+        };
+        let Some((key, item)) = table.get_key_value(key) else {
+            break;
+        };
+        span = key.span().or(span);
+        let Some(next) = Node::from_item(item) else {
+            break;
+        };
+        node = next;
+
+        let indices = indices
+            .into_iter()
+            .flat_map(|i| i.split('['))
+            .filter_map(|i| i.trim_end_matches(']').parse::<usize>().ok());
+        for index in indices {
+            node = match node {
+                Node::Tables(tables) => match tables.get(index) {
+                    Some(table) => {
+                        span = table.span().or(span);
+                        Node::Table(table)
+                    }
+                    None => return span,
+                },
+                Node::Value(toml_edit::Value::Array(array)) => match array.get(index) {
+                    Some(value) => {
+                        span = value.span().or(span);
+                        Node::from_value(value)
+                    }
+                    None => return span,
+                },
+                _ => return span,
+            };
+        }
+    }
+
+    span
+}
+
+fn line_of(source: &str, offset: usize) -> usize {
+    source[..offset.min(source.len())].matches('\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID: &str = r#"
+[server]
+host = "127.0.0.1"
+port = 3000
+
+[logging]
+file_path = "topkio.log"
+
+[providers.ollama]
+url = "http://localhost:11434"
+model = "llama3.2"
+supported_models = ["llama3.2"]
+"#;
+
+    /// `(line, path, message)` of every issue reported for `source`.
+    fn issues(source: &str) -> Vec<(Option<usize>, String, String)> {
+        match parse(source, None, toml::Table::new()) {
+            Ok(_) => vec![],
+            Err(ConfigError::Invalid(issues)) => issues
+                .into_iter()
+                .map(|issue| (issue.line, issue.path, issue.message))
+                .collect(),
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    /// `VALID` with `lines` appended.
+    fn with(lines: &str) -> String {
+        format!("{}{}\n", VALID, lines)
+    }
+
+    #[test]
+    fn accepts_a_valid_config() {
+        let config = parse(VALID, None, toml::Table::new()).unwrap();
+        assert_eq!(config.providers["ollama"].kind, "ollama");
+        assert_eq!(config.logging.level, "info");
+    }
+
+    #[test]
+    fn validates_overrides() {
+        let source = VALID.replace("127.0.0.1", "not a host!");
+        let overrides = |host: &str| {
+            let mut server = toml::Table::new();
+            server.insert("host".into(), host.into());
+            let mut overrides = toml::Table::new();
+            overrides.insert("server".into(), server.into());
+            overrides
+        };
+
+        let config = parse(&source, None, overrides("0.0.0.0")).unwrap();
+        assert_eq!(config.server.host, "0.0.0.0");
+        assert_eq!(config.server.port, 3000);
+
+        match parse(VALID, None, overrides("bad host")) {
+            Err(ConfigError::Invalid(issues)) => assert_eq!(issues[0].path, "server.host"),
+            result => panic!("unexpected result: {:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn reports_single_issues() {
+        // `VALID` has 12 lines; the appended ones start at line 13.
+        let cases: &[(&str, &str, &str, usize)] = &[
+            ("[context]\nlimit = 1", "context.limit", "unknown key", 14),
+            (
+                "[rate_limit]\nrequests_per_minute = 0",
+                "rate_limit.requests_per_minute",
+                "must be positive",
+                14,
+            ),
+            (
+                "[admin]\napi_key = \"\"",
+                "admin.api_key",
+                "must not be empty",
+                14,
+            ),
+            (
+                "[aliases]\nfast = \"nope:model\"",
+                "aliases.fast",
+                "unknown backend \"nope\"",
+                14,
+            ),
+            (
+                "[aliases]\nfast = \"unlisted\"",
+                "aliases.fast",
+                "\"unlisted\" is neither a backend nor in any supported_models",
+                14,
+            ),
+            (
+                "[pricing]\nllama = { input = 1.0, output = 2.0 }",
+                "pricing.llama",
+                "key must be \"backend:model\" or \"backend:*\"",
+                14,
+            ),
+            (
+                "[pricing]\n\"ollama:*\" = { input = -1.0, output = 2.0 }",
+                "pricing.ollama:*",
+                "prices must not be negative",
+                14,
+            ),
+            (
+                "[providers.other]\nurl = \"http://other\"\nmax_retries = 11",
+                "providers.other.max_retries",
+                "must be at most 10",
+                15,
+            ),
+            (
+                "[providers.other]\nurl = \"http://other\"\nretry_delay_ms = 60001",
+                "providers.other.retry_delay_ms",
+                "must be at most 60000",
+                15,
+            ),
+            (
+                "[providers.other]\nurl = \"http://other\"\ncontext_windows = { m = 0 }",
+                "providers.other.context_windows.m",
+                "must be positive",
+                15,
+            ),
+            (
+                "[providers.other]\nurl = \"http://other\"\nmodel = \"m\"\nsupported_models = [\"n\"]",
+                "providers.other.model",
+                "\"m\" is not in supported_models",
+                15,
+            ),
+        ];
+
+        for (lines, path, message, line) in cases {
+            assert_eq!(
+                issues(&with(lines)),
+                [(Some(*line), path.to_string(), message.to_string())],
+                "{}",
+                lines
+            );
+        }
+    }
+
+    #[test]
+    fn reports_every_issue_sorted_by_line() {
+        let source = r#"
+[server]
+host = "not a host"
+port = 0
+colour = "blue"
+
+[logging]
+file_path = "topkio.log"
+level = "loud"
+
+[providers.ollama]
+url = "http://localhost:11434"
+"#;
+        let found = issues(source);
+        let found: Vec<_> = found
+            .iter()
+            .map(|(line, path, _)| (*line, path.as_str()))
+            .collect();
+
+        assert_eq!(
+            found,
+            [
+                (Some(3), "server.host"),
+                (Some(4), "server.port"),
+                (Some(5), "server.colour"),
+                (Some(9), "logging.level"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_missing_sections() {
+        let found = issues("[server]\nhost = \"127.0.0.1\"\nport = 3000\n");
+        assert_eq!(found.len(), 2, "{:?}", found);
+        assert!(found
+            .iter()
+            .all(|(_, _, message)| message.contains("required")));
+    }
+
+    #[test]
+    fn reports_syntax_errors_with_their_line() {
+        let found = issues("[server]\nhost = \n");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, Some(2));
+    }
+
+    #[test]
+    fn reports_failed_interpolation_once() {
+        std::env::remove_var("TOPKIO_TEST_VALIDATE_HOST");
+        let source = VALID.replace("127.0.0.1", "${TOPKIO_TEST_VALIDATE_HOST}");
+        assert_eq!(
+            issues(&source),
+            [(
+                Some(3),
+                "server.host".to_string(),
+                "environment variable TOPKIO_TEST_VALIDATE_HOST is not set".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn checks_urls() {
+        assert!(check_url("url", "https://api.openai.com/v1").is_none());
+        let cases = [
+            ("ftp://example.com", "must be an http(s) URL"),
+            ("localhost:11434", "must be an http(s) URL"),
+            ("not a url", "invalid URL \"not a url\""),
+        ];
+        for (url, message) in cases {
+            let issue = check_url("url", url).unwrap();
+            assert!(issue.message.starts_with(message), "{}: {}", url, issue);
+        }
+    }
+
+    #[test]
+    fn converts_pointers_to_paths() {
+        assert_eq!(
+            pointer_to_path("/providers/azure/resources/0/url"),
+            "providers.azure.resources[0].url"
+        );
+        assert_eq!(pointer_to_path("/pricing/a~1b"), "pricing.a/b");
+        assert_eq!(pointer_to_path(""), "");
+    }
+
+    #[test]
+    fn accepts_hostnames() {
+        assert!(is_hostname("gpu-box.internal"));
+        assert!(!is_hostname("-bad.example"));
+        assert!(!is_hostname("two..dots"));
+        assert!(!is_hostname(""));
+    }
+}
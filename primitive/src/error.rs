@@ -13,6 +13,158 @@ pub enum TopkioError {
     ServerError(String),
 }
 
+/// Why a call to an upstream provider failed.
+///
+/// Backends return it inside their `anyhow::Error`, so the gateway can answer
+/// with a matching status. Other errors are reported as upstream failures.
+#[derive(Debug, Clone, Error)]
+pub enum ProviderError {
+    /// The provider rejected the gateway's own credentials.
+    #[error("Upstream authentication failed: {0}")]
+    Authentication(String),
+
+    #[error("Rate limited by upstream: {message}")]
+    RateLimited {
+        message: String,
+        /// Seconds to wait, from the upstream `Retry-After` header.
+        retry_after: Option<u64>,
+    },
+
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
+    #[error("Context length exceeded: {0}")]
+    ContextLength(String),
+
+    #[error("Content filtered: {message}")]
+    ContentFiltered {
+        message: String,
+        /// Provider-specific details, e.g. safety ratings.
+        details: Option<serde_json::Value>,
+    },
+
+    #[error("Model not found: {0}")]
+    NotFound(String),
+
+    #[error("Upstream unavailable: {0}")]
+    Unavailable(String),
+
+    #[error("Upstream timed out: {0}")]
+    Timeout(String),
+
+    #[error("Upstream error: {0}")]
+    Upstream(String),
+}
+
+impl ProviderError {
+    /// Pass successful responses through and classify failed ones by their
+    /// status and body.
+    pub async fn check(response: reqwest::Response) -> Result<reqwest::Response, Self> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok());
+        let body = response.text().await.unwrap_or_default();
+
+        Err(Self::from_response(status.as_u16(), retry_after, &body))
+    }
+
+    /// Classify an upstream error response.
+    pub fn from_response(status: u16, retry_after: Option<u64>, body: &str) -> Self {
+        let message = error_message(body).unwrap_or_else(|| format!("HTTP {}", status));
+
+        if is_context_length(&message, body) {
+            return Self::ContextLength(message);
+        }
+        // Gemini answers invalid keys with 400 INVALID_ARGUMENT.
+        if body.contains("API_KEY_INVALID") {
+            return Self::Authentication(message);
+        }
+
+        match status {
+            401 | 403 => Self::Authentication(message),
+            404 => Self::NotFound(message),
+            408 | 504 => Self::Timeout(message),
+            429 => Self::RateLimited {
+                message,
+                retry_after,
+            },
+            400 | 413 | 422 => Self::InvalidRequest(message),
+            // 529 is Anthropic's "overloaded".
+            502 | 503 | 529 => Self::Unavailable(message),
+            _ => Self::Upstream(format!("HTTP {}: {}", status, message)),
+        }
+    }
+
+    /// Classify an error reported inside a response, e.g. in a stream, by
+    /// its OpenAI/Anthropic-style error type.
+    pub fn from_kind(kind: &str, message: String) -> Self {
+        match kind {
+            _ if is_context_length(&message, kind) => Self::ContextLength(message),
+            "authentication_error" | "permission_error" => Self::Authentication(message),
+            "rate_limit_error" => Self::RateLimited {
+                message,
+                retry_after: None,
+            },
+            "invalid_request_error" => Self::InvalidRequest(message),
+            "not_found_error" => Self::NotFound(message),
+            "overloaded_error" => Self::Unavailable(message),
+            "timeout_error" => Self::Timeout(message),
+            _ => Self::Upstream(format!("{}: {}", kind, message)),
+        }
+    }
+}
+
+impl From<reqwest::Error> for ProviderError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Self::Timeout(e.to_string())
+        } else if e.is_connect() {
+            Self::Unavailable(e.to_string())
+        } else if let Some(status) = e.status() {
+            Self::from_response(status.as_u16(), None, "")
+        } else {
+            Self::Upstream(e.to_string())
+        }
+    }
+}
+
+/// The message of an error body, as sent by OpenAI, Anthropic and Gemini
+/// (`{"error": {"message": ...}}`) or Ollama (`{"error": ...}`).
+fn error_message(body: &str) -> Option<String> {
+    let body = body.trim();
+    if body.is_empty() {
+        return None;
+    }
+
+    let message = serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|json| {
+            let error = json.get("error").unwrap_or(&json);
+            match error {
+                serde_json::Value::String(message) => Some(message.clone()),
+                error => error.get("message")?.as_str().map(str::to_string),
+            }
+        });
+
+    Some(message.unwrap_or_else(|| body.chars().take(500).collect()))
+}
+
+fn is_context_length(message: &str, body: &str) -> bool {
+    let message = message.to_lowercase();
+    body.contains("context_length_exceeded")
+        || message.contains("maximum context length")
+        || message.contains("prompt is too long")
+        || message.contains("exceeds the maximum number of tokens")
+        || message.contains("context window")
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ConfigError {
     #[error("Config file not found: {0}")]
@@ -23,4 +175,47 @@ pub enum ConfigError {
 
     #[error("Missing required field: {0}")]
     MissingField(String),
+
+    #[error("Invalid config:\n{}", format_issues(.0))]
+    Invalid(Vec<ConfigIssue>),
+}
+
+/// A single problem found while validating `topkio.toml`.
+#[derive(Debug, Clone)]
+pub struct ConfigIssue {
+    /// Dotted path of the offending key, e.g. `providers.gemini.url`.
+    pub path: String,
+    /// 1-based line in the config file, when it can be located.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    pub fn new(path: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            line: None,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {}: ", line)?;
+        }
+        match self.path.is_empty() {
+            true => write!(f, "{}", self.message),
+            false => write!(f, "{}: {}", self.path, self.message),
+        }
+    }
+}
+
+fn format_issues(issues: &[ConfigIssue]) -> String {
+    issues
+        .iter()
+        .map(|issue| format!("  - {}", issue))
+        .collect::<Vec<_>>()
+        .join("\n")
 }
@@ -1,3 +1,5 @@
 pub mod api;
 pub mod config;
 pub mod error;
+pub mod registry;
+pub mod tokens;
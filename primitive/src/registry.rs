@@ -0,0 +1,95 @@
+use {
+    crate::{
+        api::UnifiedLlmApi,
+        config::{check_url, ProviderConfig, ProvidersConfig},
+        error::ConfigIssue,
+    },
+    anyhow::Result,
+    async_trait::async_trait,
+    std::{collections::HashMap, sync::Arc},
+};
+
+/// Creates backends for one provider type from their configuration.
+#[async_trait]
+pub trait ProviderFactory: Send + Sync {
+    /// Build the backend for the provider instance `name`.
+    async fn create(&self, name: &str, config: &ProviderConfig) -> Result<Arc<dyn UnifiedLlmApi>>;
+
+    /// Report every problem in the configuration of the instance `name`.
+    ///
+    /// By default the URL must be valid and no type-specific settings are
+    /// accepted; factories taking `ProviderConfig::options` override this.
+    fn validate(&self, name: &str, config: &ProviderConfig) -> Vec<ConfigIssue> {
+        let mut issues: Vec<ConfigIssue> =
+            check_url(&format!("providers.{}.url", name), &config.url)
+                .into_iter()
+                .collect();
+        issues.extend(config.unknown_options(name));
+        issues
+    }
+}
+
+/// Provider factories keyed by provider type (the `type` field in `topkio.toml`).
+#[derive(Default, Clone)]
+pub struct ProviderRegistry {
+    factories: HashMap<String, Arc<dyn ProviderFactory>>,
+}
+
+impl ProviderRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a factory, replacing any previous one for the same type.
+    pub fn register(
+        &mut self,
+        kind: impl Into<String>,
+        factory: impl ProviderFactory + 'static,
+    ) -> &mut Self {
+        self.factories.insert(kind.into(), Arc::new(factory));
+        self
+    }
+
+    pub fn get(&self, kind: &str) -> Option<Arc<dyn ProviderFactory>> {
+        self.factories.get(kind).cloned()
+    }
+
+    pub fn kinds(&self) -> Vec<&str> {
+        let mut kinds: Vec<&str> = self.factories.keys().map(String::as_str).collect();
+        kinds.sort();
+        kinds
+    }
+
+    /// Build the backend of a single provider instance.
+    pub async fn create(
+        &self,
+        name: &str,
+        config: &ProviderConfig,
+    ) -> Result<Arc<dyn UnifiedLlmApi>> {
+        let factory = self.get(&config.kind).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Unknown provider type '{}' for providers.{} (known types: {})",
+                config.kind,
+                name,
+                self.kinds().join(", ")
+            )
+        })?;
+
+        factory.create(name, config).await
+    }
+
+    /// Build a backend for every enabled provider instance.
+    pub async fn build(
+        &self,
+        providers: &ProvidersConfig,
+    ) -> Result<HashMap<String, Arc<dyn UnifiedLlmApi>>> {
+        let mut backends = HashMap::new();
+
+        for (name, config) in providers.iter().filter(|(_, config)| config.enabled) {
+            let backend = self.create(name, config).await?;
+            backends.insert(name.to_lowercase(), backend);
+        }
+
+        Ok(backends)
+    }
+}
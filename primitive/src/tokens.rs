@@ -0,0 +1,317 @@
+//! Local token counting and known context windows, used to check request
+//! sizes before they are sent upstream.
+//!
+//! OpenAI models are counted with their BPE tokenizer. Other models are
+//! estimated at about four characters per token.
+
+use {
+    crate::api::{ChatCompletionRequest, Message},
+    tiktoken_rs::CoreBPE,
+};
+
+/// Tokens added by the chat format to every message, and once to prime the
+/// reply, as counted by OpenAI.
+const TOKENS_PER_MESSAGE: u32 = 3;
+const REPLY_PRIMING_TOKENS: u32 = 3;
+
+/// Rough size of an image, audio or file part, which cannot be counted
+/// without decoding it.
+pub const TOKENS_PER_MEDIA_PART: u32 = 258;
+
+/// Context windows of well-known model families. A model belongs to a
+/// family when it has its name, possibly with a tag (`llama3.1:8b`), a
+/// version (`claude-3@20240229`) or a dated or numbered release
+/// (`gpt-4o-2024-08-06`) appended. Other models are not checked.
+const CONTEXT_WINDOWS: &[(&str, u32)] = &[
+    ("gpt-3.5-turbo", 16_385),
+    ("gpt-4", 8_192),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4o", 128_000),
+    ("gpt-4o-mini", 128_000),
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4.1-mini", 1_047_576),
+    ("gpt-4.1-nano", 1_047_576),
+    ("o1", 200_000),
+    ("o1-mini", 128_000),
+    ("o1-preview", 128_000),
+    ("o3", 200_000),
+    ("o3-mini", 200_000),
+    ("o4-mini", 200_000),
+    ("claude", 200_000),
+    ("gemini-1.5-flash", 1_048_576),
+    ("gemini-1.5-pro", 2_097_152),
+    ("gemini-2.0-flash", 1_048_576),
+    ("gemini-2.0-flash-lite", 1_048_576),
+    ("gemini-2.5-pro", 1_048_576),
+    ("gemini-2.5-flash", 1_048_576),
+    ("gemini-2.5-flash-lite", 1_048_576),
+    ("deepseek-chat", 65_536),
+    ("deepseek-reasoner", 65_536),
+    ("llama3", 8_192),
+    ("llama3.1", 131_072),
+    ("llama3.2", 131_072),
+    ("llama3.3", 131_072),
+    ("mistral", 32_768),
+    ("mistral-large", 131_072),
+    ("mistral-nemo", 131_072),
+    ("qwen2.5", 32_768),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenCount {
+    pub tokens: u32,
+    /// Whether the model's own tokenizer was used, rather than an estimate.
+    pub exact: bool,
+}
+
+/// Context window of `model`, if it is a well-known model.
+pub fn context_window(model: &str) -> Option<u32> {
+    let model = model.to_lowercase();
+    CONTEXT_WINDOWS
+        .iter()
+        .filter(|(family, _)| in_family(&model, family))
+        .max_by_key(|(family, _)| family.len())
+        .map(|(_, window)| *window)
+}
+
+fn in_family(model: &str, family: &str) -> bool {
+    let Some(suffix) = model.strip_prefix(family) else {
+        return false;
+    };
+    match suffix.chars().next() {
+        None | Some(':' | '@') => true,
+        Some('-') => {
+            let release = &suffix[1..];
+            release.starts_with(|c: char| c.is_ascii_digit()) || release == "latest"
+        }
+        Some(_) => false,
+    }
+}
+
+fn tokenizer(model: &str) -> Option<&'static CoreBPE> {
+    let model = model.to_lowercase();
+    let starts_with = |prefixes: &[&str]| prefixes.iter().any(|p| model.starts_with(p));
+
+    if starts_with(&["gpt-4o", "gpt-4.1", "gpt-5", "chatgpt-4o", "o1", "o3", "o4"]) {
+        Some(tiktoken_rs::o200k_base_singleton())
+    } else if starts_with(&["gpt-4", "gpt-3.5", "text-embedding"]) {
+        Some(tiktoken_rs::cl100k_base_singleton())
+    } else {
+        None
+    }
+}
+
+/// Count the tokens of `text` as `model` would.
+pub fn count_text(model: &str, text: &str) -> TokenCount {
+    count_with(tokenizer(model), text)
+}
+
+fn count_with(tokenizer: Option<&CoreBPE>, text: &str) -> TokenCount {
+    match tokenizer {
+        Some(bpe) => TokenCount {
+            tokens: bpe.encode_with_special_tokens(text).len() as u32,
+            exact: true,
+        },
+        None => TokenCount {
+            tokens: text.chars().count().div_ceil(4) as u32,
+            exact: false,
+        },
+    }
+}
+
+fn count_message(tokenizer: Option<&CoreBPE>, message: &Message) -> u32 {
+    let mut tokens = TOKENS_PER_MESSAGE
+        + count_with(tokenizer, &message.role).tokens
+        + count_with(tokenizer, &message.content.text()).tokens
+        + TOKENS_PER_MEDIA_PART * message.content.media_parts() as u32;
+    for call in message.tool_calls.iter().flatten() {
+        tokens += count_with(tokenizer, &call.function.name).tokens
+            + count_with(tokenizer, &call.function.arguments).tokens;
+    }
+    if let Some(id) = &message.tool_call_id {
+        tokens += count_with(tokenizer, id).tokens;
+    }
+    tokens
+}
+
+/// Tokens of the request that don't belong to a single message.
+fn count_overhead(tokenizer: Option<&CoreBPE>, request: &ChatCompletionRequest) -> u32 {
+    let tools = match &request.tools {
+        Some(tools) => {
+            let json = serde_json::to_string(tools).unwrap_or_default();
+            count_with(tokenizer, &json).tokens
+        }
+        None => 0,
+    };
+    REPLY_PRIMING_TOKENS + tools
+}
+
+/// Count the prompt tokens of `request` as `model` would.
+pub fn count_request(model: &str, request: &ChatCompletionRequest) -> TokenCount {
+    let tokenizer = tokenizer(model);
+    let tokens = count_overhead(tokenizer, request)
+        + request
+            .messages
+            .iter()
+            .map(|message| count_message(tokenizer, message))
+            .sum::<u32>();
+
+    TokenCount {
+        tokens,
+        exact: tokenizer.is_some(),
+    }
+}
+
+/// Drop the oldest turns of `request` until its prompt fits in `limit`
+/// tokens, keeping system messages and the last message.
+///
+/// Returns whether the request fits.
+pub fn truncate_history(model: &str, request: &mut ChatCompletionRequest, limit: u32) -> bool {
+    let tokenizer = tokenizer(model);
+    let mut counts: Vec<u32> = request
+        .messages
+        .iter()
+        .map(|message| count_message(tokenizer, message))
+        .collect();
+    let mut total = count_overhead(tokenizer, request) + counts.iter().sum::<u32>();
+
+    while total > limit {
+        let last = request.messages.len().saturating_sub(1);
+        let Some(index) = request.messages[..last]
+            .iter()
+            .position(|message| message.role != "system")
+        else {
+            return false;
+        };
+
+        // Tool results answer calls of the turn before them, so they go too.
+        loop {
+            request.messages.remove(index);
+            total -= counts.remove(index);
+            let orphaned =
+                index < request.messages.len() - 1 && request.messages[index].role == "tool";
+            if !orphaned {
+                break;
+            }
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::api::{FunctionCall, ToolCall},
+    };
+
+    #[test]
+    fn context_windows_of_model_families() {
+        let cases = [
+            ("gpt-4", Some(8_192)),
+            ("gpt-4-0613", Some(8_192)),
+            ("gpt-4-32k", Some(32_768)),
+            ("gpt-4-turbo-2024-04-09", Some(128_000)),
+            ("gpt-4o-2024-08-06", Some(128_000)),
+            ("gpt-4o-mini", Some(128_000)),
+            ("o1", Some(200_000)),
+            ("o1-2024-12-17", Some(200_000)),
+            ("o1-mini", Some(128_000)),
+            ("claude-3-5-sonnet-20241022", Some(200_000)),
+            ("claude-3-opus@20240229", Some(200_000)),
+            ("Gemini-1.5-Pro-002", Some(2_097_152)),
+            ("gemini-2.5-flash", Some(1_048_576)),
+            ("llama3", Some(8_192)),
+            ("llama3:8b", Some(8_192)),
+            ("llama3.1:70b", Some(131_072)),
+            ("llama3.3", Some(131_072)),
+            ("mistral:7b", Some(32_768)),
+            ("mistral-large-latest", Some(131_072)),
+            ("mistral-nemo", Some(131_072)),
+            // Unknown members of known families are not guessed.
+            ("llama3.2-vision", None),
+            ("mistral-small", None),
+            ("qwen2.5-coder:7b", None),
+            ("gpt-3.5-turbo-instruct", None),
+            ("phi4", None),
+        ];
+        for (model, window) in cases {
+            assert_eq!(context_window(model), window, "{}", model);
+        }
+    }
+
+    fn message(role: &str, text: &str) -> Message {
+        Message::new(role, text)
+    }
+
+    fn request(messages: Vec<Message>) -> ChatCompletionRequest {
+        ChatCompletionRequest {
+            messages,
+            ..Default::default()
+        }
+    }
+
+    fn roles(request: &ChatCompletionRequest) -> Vec<&str> {
+        request.messages.iter().map(|m| m.role.as_str()).collect()
+    }
+
+    #[test]
+    fn truncate_history_drops_oldest_turns() {
+        let long = "word ".repeat(100);
+        let mut request = request(vec![
+            message("system", "Be brief."),
+            message("user", &long),
+            message("assistant", &long),
+            message("user", "And now?"),
+        ]);
+        let full = count_request("llama3", &request).tokens;
+
+        assert!(truncate_history("llama3", &mut request, full));
+        assert_eq!(request.messages.len(), 4);
+
+        assert!(truncate_history("llama3", &mut request, 100));
+        assert_eq!(roles(&request), ["system", "user"]);
+        assert_eq!(request.messages[1].content.text(), "And now?");
+        assert!(count_request("llama3", &request).tokens <= 100);
+    }
+
+    #[test]
+    fn truncate_history_drops_tool_results_with_their_call() {
+        let call = Message {
+            tool_calls: Some(vec![ToolCall {
+                id: "call_1".into(),
+                kind: "function".into(),
+                function: FunctionCall {
+                    name: "weather".into(),
+                    arguments: "{}".into(),
+                },
+            }]),
+            ..message("assistant", "")
+        };
+        let result = Message {
+            tool_call_id: Some("call_1".into()),
+            ..message("tool", &"sunny ".repeat(100))
+        };
+        let mut request = request(vec![
+            message("user", "Weather?"),
+            call,
+            result,
+            message("user", "Thanks"),
+        ]);
+
+        assert!(truncate_history("llama3", &mut request, 50));
+        assert_eq!(roles(&request), ["user"]);
+    }
+
+    #[test]
+    fn truncate_history_fails_when_the_last_message_is_too_long() {
+        let mut request = request(vec![
+            message("system", "Be brief."),
+            message("user", &"word ".repeat(100)),
+        ]);
+        assert!(!truncate_history("llama3", &mut request, 20));
+        assert_eq!(request.messages.len(), 2);
+    }
+}
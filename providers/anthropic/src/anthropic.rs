@@ -0,0 +1,3 @@
+pub mod api;
+pub mod chat_completion;
+pub mod primitive;
@@ -0,0 +1,26 @@
+use {
+    super::chat_completion::chat_completion,
+    topkio_primitive::api::{ChatCompletionRequest, ChatCompletionResponse, UnifiedLlmApi},
+};
+
+pub struct AnthropicBackend {
+    base_url: String,
+    api_key: String,
+}
+
+impl AnthropicBackend {
+    pub fn new(base_url: String, api_key: String) -> Self {
+        Self { base_url, api_key }
+    }
+}
+
+#[async_trait::async_trait]
+impl UnifiedLlmApi for AnthropicBackend {
+    async fn chat_completion(
+        &self,
+        model: &str,
+        request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, anyhow::Error> {
+        chat_completion(&self.base_url, &self.api_key, model, request).await
+    }
+}
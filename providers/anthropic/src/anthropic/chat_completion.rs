@@ -0,0 +1,144 @@
+use {
+    crate::anthropic::primitive::{
+        BlockDelta, ContentBlock, MessagesRequest, MessagesResponse, StreamEvent,
+    },
+    futures_util::StreamExt,
+    topkio_primitive::{
+        api::{ChatCompletionRequest, ChatCompletionResponse},
+        error::ProviderError,
+    },
+};
+
+pub const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+pub async fn chat_completion(
+    base_url: &str,
+    api_key: &str,
+    model: &str,
+    request: ChatCompletionRequest,
+) -> Result<ChatCompletionResponse, anyhow::Error> {
+    let enable_stream = request.stream.unwrap_or(false);
+    let body = MessagesRequest::new(model, request)?;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/v1/messages", base_url))
+        .header("x-api-key", api_key)
+        .header("anthropic-version", ANTHROPIC_VERSION)
+        .json(&body)
+        .send()
+        .await
+        .map_err(ProviderError::from)?;
+    let response = ProviderError::check(response).await?;
+
+    let response = match enable_stream {
+        true => {
+            let mut accumulator = StreamAccumulator::default();
+            let mut buffer: Vec<u8> = vec![];
+            let mut stream = response.bytes_stream();
+
+            while let Some(chunk) = stream.next().await {
+                buffer.extend_from_slice(&chunk.map_err(ProviderError::from)?);
+
+                // SSE events are separated by a blank line.
+                while let Some(end) = buffer.windows(2).position(|w| w == b"\n\n") {
+                    let event: Vec<u8> = buffer.drain(..end + 2).collect();
+                    if let Some(event) = parse_sse_event(std::str::from_utf8(&event)?)? {
+                        accumulator.push(event)?;
+                    }
+                }
+            }
+            if let Some(event) = parse_sse_event(std::str::from_utf8(&buffer)?)? {
+                accumulator.push(event)?;
+            }
+
+            accumulator.finish()?
+        }
+        false => response.json::<MessagesResponse>().await?,
+    };
+
+    Ok(response.into())
+}
+
+/// Parse the `data:` payload of a single SSE event.
+fn parse_sse_event(event: &str) -> Result<Option<StreamEvent>, anyhow::Error> {
+    let data = event
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(str::trim_start)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if data.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(serde_json::from_str(&data)?))
+}
+
+/// Rebuilds a complete `MessagesResponse` from streamed events.
+#[derive(Default)]
+struct StreamAccumulator {
+    message: Option<MessagesResponse>,
+    blocks: Vec<ContentBlock>,
+    tool_inputs: Vec<String>,
+}
+
+impl StreamAccumulator {
+    fn push(&mut self, event: StreamEvent) -> Result<(), anyhow::Error> {
+        match event {
+            StreamEvent::MessageStart { message } => self.message = Some(message),
+            StreamEvent::ContentBlockStart {
+                index,
+                content_block,
+            } => {
+                if self.blocks.len() <= index {
+                    self.blocks.resize(index + 1, ContentBlock::Unsupported);
+                    self.tool_inputs.resize(index + 1, String::new());
+                }
+                self.blocks[index] = content_block;
+            }
+            StreamEvent::ContentBlockDelta { index, delta } => {
+                match (self.blocks.get_mut(index), delta) {
+                    (Some(ContentBlock::Text { text }), BlockDelta::TextDelta { text: part }) => {
+                        text.push_str(&part)
+                    }
+                    (
+                        Some(ContentBlock::ToolUse { .. }),
+                        BlockDelta::InputJsonDelta { partial_json },
+                    ) => self.tool_inputs[index].push_str(&partial_json),
+                    _ => {}
+                }
+            }
+            StreamEvent::ContentBlockStop { index } => {
+                if let Some(ContentBlock::ToolUse { input, .. }) = self.blocks.get_mut(index) {
+                    if !self.tool_inputs[index].is_empty() {
+                        *input = serde_json::from_str(&self.tool_inputs[index])?;
+                    }
+                }
+            }
+            StreamEvent::MessageDelta { delta, usage } => {
+                if let Some(message) = self.message.as_mut() {
+                    message.stop_reason = delta.stop_reason;
+                    if let Some(usage) = usage {
+                        message.usage.output_tokens = usage.output_tokens;
+                    }
+                }
+            }
+            StreamEvent::Error { error } => {
+                return Err(ProviderError::from_kind(&error.kind, error.message).into());
+            }
+            StreamEvent::MessageStop | StreamEvent::Ping => {}
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<MessagesResponse, anyhow::Error> {
+        let mut message = self
+            .message
+            .ok_or_else(|| anyhow::anyhow!("Anthropic stream ended without message_start"))?;
+        message.content = self.blocks;
+
+        Ok(message)
+    }
+}
@@ -0,0 +1,455 @@
+use {
+    serde::{Deserialize, Serialize},
+    topkio_primitive::{
+        api::{
+            parse_data_url, ChatCompletionRequest, ChatCompletionResponse, ContentPart,
+            FunctionCall, Message, MessageContent, ResponseFormat, ToolCall, ToolChoice,
+            ToolChoiceMode, Usage,
+        },
+        error::ProviderError,
+    },
+};
+
+/// `max_tokens` is mandatory for the Messages API.
+pub const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+#[derive(Debug, Serialize)]
+pub struct MessagesRequest {
+    pub model: String,
+    pub max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    pub messages: Vec<AnthropicMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_sequences: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<AnthropicTool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<AnthropicToolChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AnthropicMessage {
+    pub role: String,
+    pub content: Vec<ContentBlock>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentBlock {
+    Text {
+        text: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        content: String,
+    },
+    Image {
+        source: MediaSource,
+    },
+    /// A PDF.
+    Document {
+        source: MediaSource,
+    },
+    /// Blocks the gateway does not translate (e.g. `thinking`).
+    #[serde(other)]
+    Unsupported,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MediaSource {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+}
+
+#[derive(Debug, Serialize)]
+pub struct AnthropicTool {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub input_schema: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnthropicToolChoice {
+    Auto,
+    /// Call at least one tool.
+    Any,
+    Tool {
+        name: String,
+    },
+    None,
+}
+
+impl From<ToolChoice> for AnthropicToolChoice {
+    fn from(choice: ToolChoice) -> Self {
+        match choice {
+            ToolChoice::Mode(ToolChoiceMode::Auto) => Self::Auto,
+            ToolChoice::Mode(ToolChoiceMode::Required) => Self::Any,
+            ToolChoice::Mode(ToolChoiceMode::None) => Self::None,
+            ToolChoice::Function { function, .. } => Self::Tool {
+                name: function.name,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MessagesResponse {
+    pub id: String,
+    #[serde(default)]
+    pub content: Vec<ContentBlock>,
+    pub stop_reason: Option<String>,
+    #[serde(default)]
+    pub usage: AnthropicUsage,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AnthropicUsage {
+    #[serde(default)]
+    pub input_tokens: u32,
+    #[serde(default)]
+    pub output_tokens: u32,
+    /// Input tokens read from the prompt cache, not counted in `input_tokens`.
+    #[serde(default)]
+    pub cache_read_input_tokens: u32,
+    /// Input tokens written to the prompt cache, not counted in `input_tokens`.
+    #[serde(default)]
+    pub cache_creation_input_tokens: u32,
+}
+
+impl From<AnthropicUsage> for Usage {
+    fn from(usage: AnthropicUsage) -> Self {
+        let prompt_tokens =
+            usage.input_tokens + usage.cache_read_input_tokens + usage.cache_creation_input_tokens;
+        let mut unified = Usage::new(prompt_tokens, usage.output_tokens);
+        unified.cached_tokens =
+            (usage.cache_read_input_tokens > 0).then_some(usage.cache_read_input_tokens);
+        unified
+    }
+}
+
+/// Server-sent events emitted when `stream` is enabled.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamEvent {
+    MessageStart {
+        message: MessagesResponse,
+    },
+    ContentBlockStart {
+        index: usize,
+        content_block: ContentBlock,
+    },
+    ContentBlockDelta {
+        index: usize,
+        delta: BlockDelta,
+    },
+    ContentBlockStop {
+        index: usize,
+    },
+    MessageDelta {
+        delta: MessageDeltaBody,
+        #[serde(default)]
+        usage: Option<AnthropicUsage>,
+    },
+    MessageStop,
+    Ping,
+    Error {
+        error: ErrorBody,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BlockDelta {
+    TextDelta {
+        text: String,
+    },
+    InputJsonDelta {
+        partial_json: String,
+    },
+    #[serde(other)]
+    Unsupported,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MessageDeltaBody {
+    pub stop_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ErrorBody {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub message: String,
+}
+
+/// Translate user content parts to content blocks.
+fn content_blocks(content: MessageContent) -> Result<Vec<ContentBlock>, ProviderError> {
+    content
+        .into_parts()
+        .into_iter()
+        .map(|part| match part {
+            ContentPart::Text { text } => Ok(ContentBlock::Text { text }),
+            ContentPart::ImageUrl { image_url } => {
+                let source = match parse_data_url(&image_url.url) {
+                    Some((media_type, data)) => MediaSource::Base64 {
+                        media_type: media_type.to_string(),
+                        data: data.to_string(),
+                    },
+                    None => MediaSource::Url { url: image_url.url },
+                };
+                Ok(ContentBlock::Image { source })
+            }
+            ContentPart::File { file } => {
+                match file.file_data.as_deref().and_then(parse_data_url) {
+                    Some((media_type @ "application/pdf", data)) => Ok(ContentBlock::Document {
+                        source: MediaSource::Base64 {
+                            media_type: media_type.to_string(),
+                            data: data.to_string(),
+                        },
+                    }),
+                    _ => Err(ProviderError::InvalidRequest(
+                        "Anthropic only accepts files as inline PDF data".into(),
+                    )),
+                }
+            }
+            part => Err(ProviderError::InvalidRequest(format!(
+                "Anthropic models do not accept {} input",
+                part.kind()
+            ))),
+        })
+        .collect()
+}
+
+impl MessagesRequest {
+    pub fn new(model: &str, request: ChatCompletionRequest) -> Result<Self, ProviderError> {
+        if request.logprobs == Some(true) {
+            return Err(ProviderError::InvalidRequest(
+                "Anthropic models do not return logprobs".into(),
+            ));
+        }
+
+        let mut system: Vec<String> = vec![];
+        let mut messages: Vec<AnthropicMessage> = vec![];
+
+        for message in request.messages {
+            let (role, content) = match message.role.as_str() {
+                "system" => {
+                    system.push(message.content.text());
+                    continue;
+                }
+                "tool" => (
+                    "user",
+                    vec![ContentBlock::ToolResult {
+                        tool_use_id: message.tool_call_id.unwrap_or_default(),
+                        content: message.content.text(),
+                    }],
+                ),
+                "assistant" => {
+                    let mut blocks = vec![];
+                    if !message.content.is_empty() {
+                        blocks.push(ContentBlock::Text {
+                            text: message.content.text(),
+                        });
+                    }
+                    for call in message.tool_calls.unwrap_or_default() {
+                        blocks.push(ContentBlock::ToolUse {
+                            id: call.id,
+                            name: call.function.name,
+                            input: serde_json::from_str(&call.function.arguments)
+                                .unwrap_or_else(|_| serde_json::json!({})),
+                        });
+                    }
+                    ("assistant", blocks)
+                }
+                _ => ("user", content_blocks(message.content)?),
+            };
+
+            // The Messages API expects alternating turns, so consecutive
+            // messages of the same role (e.g. several tool results) are merged.
+            match messages.last_mut() {
+                Some(last) if last.role == role => last.content.extend(content),
+                _ => messages.push(AnthropicMessage {
+                    role: role.to_string(),
+                    content,
+                }),
+            }
+        }
+
+        // The Messages API has no JSON mode, so the format is asked for.
+        match request.response_format {
+            Some(ResponseFormat::JsonObject) => {
+                system.push("Reply with a single JSON object and nothing else.".into())
+            }
+            Some(ResponseFormat::JsonSchema { json_schema }) => system.push(format!(
+                "Reply with a single JSON value matching this JSON Schema and nothing else:\n{}",
+                json_schema.schema
+            )),
+            Some(ResponseFormat::Text) | None => {}
+        }
+
+        let tools = request.tools.map(|tools| {
+            tools
+                .into_iter()
+                .map(|tool| AnthropicTool {
+                    name: tool.function.name,
+                    description: tool.function.description,
+                    input_schema: tool.function.parameters,
+                })
+                .collect()
+        });
+
+        Ok(Self {
+            model: model.to_string(),
+            max_tokens: request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            system: (!system.is_empty()).then(|| system.join("\n\n")),
+            messages,
+            temperature: request.temperature,
+            stop_sequences: request.stop,
+            tools,
+            tool_choice: request.tool_choice.map(AnthropicToolChoice::from),
+            stream: request.stream,
+        })
+    }
+}
+
+/// Map an Anthropic `stop_reason` to the OpenAI-style finish reason.
+pub fn finish_reason(stop_reason: &str) -> String {
+    match stop_reason {
+        "end_turn" | "stop_sequence" | "pause_turn" => "stop",
+        "max_tokens" => "length",
+        "tool_use" => "tool_calls",
+        "refusal" => "content_filter",
+        other => other,
+    }
+    .to_string()
+}
+
+impl From<MessagesResponse> for ChatCompletionResponse {
+    fn from(response: MessagesResponse) -> Self {
+        let mut text = String::new();
+        let mut tool_calls = vec![];
+
+        for block in response.content {
+            match block {
+                ContentBlock::Text { text: part } => text.push_str(&part),
+                ContentBlock::ToolUse { id, name, input } => tool_calls.push(ToolCall {
+                    id,
+                    kind: "function".to_string(),
+                    function: FunctionCall {
+                        name,
+                        arguments: input.to_string(),
+                    },
+                }),
+                ContentBlock::ToolResult { .. }
+                | ContentBlock::Image { .. }
+                | ContentBlock::Document { .. }
+                | ContentBlock::Unsupported => {}
+            }
+        }
+
+        let mut message = Message::new("assistant", text);
+        message.tool_calls = (!tool_calls.is_empty()).then_some(tool_calls);
+
+        ChatCompletionResponse {
+            message,
+            finish_reason: response.stop_reason.as_deref().map(finish_reason),
+            logprobs: None,
+            extensions: None,
+            usage: Some(response.usage.into()),
+            choices: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(parts: serde_json::Value) -> Result<Vec<ContentBlock>, ProviderError> {
+        content_blocks(serde_json::from_value(parts).unwrap())
+    }
+
+    #[test]
+    fn translates_content_parts() {
+        let translated = blocks(serde_json::json!([
+            {"type": "text", "text": "Compare these."},
+            {"type": "image_url", "image_url": {"url": "data:image/webp;base64,UklGRg=="}},
+            {"type": "image_url", "image_url": {"url": "https://example.com/cat.png"}},
+            {"type": "file", "file": {"file_data": "data:application/pdf;base64,JVBERi0="}}
+        ]))
+        .unwrap();
+
+        let json = serde_json::to_value(&translated).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {"type": "text", "text": "Compare these."},
+                {"type": "image", "source": {"type": "base64", "media_type": "image/webp", "data": "UklGRg=="}},
+                {"type": "image", "source": {"type": "url", "url": "https://example.com/cat.png"}},
+                {"type": "document", "source": {"type": "base64", "media_type": "application/pdf", "data": "JVBERi0="}}
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_content_parts() {
+        let rejected = [
+            serde_json::json!({"type": "input_audio", "input_audio": {"data": "UklGRg==", "format": "wav"}}),
+            serde_json::json!({"type": "file", "file": {"file_id": "file-abc"}}),
+            serde_json::json!({"type": "file", "file": {"file_data": "data:text/plain;base64,aGk="}}),
+        ];
+        for part in rejected {
+            assert!(
+                matches!(
+                    blocks(serde_json::json!([part])),
+                    Err(ProviderError::InvalidRequest(_))
+                ),
+                "{}",
+                part
+            );
+        }
+    }
+
+    #[test]
+    fn passes_sampling_options_and_tool_choice() {
+        let request = |tool_choice: serde_json::Value| {
+            let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+                "model": "anthropic:claude-sonnet-4-0",
+                "messages": [{"role": "user", "content": "Hi"}],
+                "temperature": 0.4,
+                "stop": "END",
+                "tool_choice": tool_choice
+            }))
+            .unwrap();
+            serde_json::to_value(MessagesRequest::new("claude-sonnet-4-0", request).unwrap())
+                .unwrap()
+        };
+
+        let body = request(serde_json::json!({"type": "function", "function": {"name": "lookup"}}));
+        assert_eq!(body["temperature"], serde_json::json!(0.4f32));
+        assert_eq!(body["stop_sequences"], serde_json::json!(["END"]));
+        assert_eq!(
+            body["tool_choice"],
+            serde_json::json!({"type": "tool", "name": "lookup"})
+        );
+        for (choice, translated) in [("auto", "auto"), ("required", "any"), ("none", "none")] {
+            assert_eq!(
+                request(serde_json::json!(choice))["tool_choice"],
+                serde_json::json!({"type": translated})
+            );
+        }
+    }
+}
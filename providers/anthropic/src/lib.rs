@@ -0,0 +1,3 @@
+mod anthropic;
+
+pub use anthropic::api::AnthropicBackend;
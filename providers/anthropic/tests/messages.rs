@@ -0,0 +1,140 @@
+use {
+    axum::{http::HeaderMap, routing::post, Json, Router},
+    serde_json::{json, Value},
+    topkio_anthropic::AnthropicBackend,
+    topkio_primitive::api::{ChatCompletionRequest, Message, UnifiedLlmApi},
+};
+
+const SSE_BODY: &str = "event: message_start
+data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_2\",\"content\":[],\"stop_reason\":null,\"usage\":{\"input_tokens\":12,\"output_tokens\":1}}}
+
+event: content_block_start
+data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}
+
+event: ping
+data: {\"type\":\"ping\"}
+
+event: content_block_delta
+data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Let me \"}}
+
+event: content_block_delta
+data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"check.\"}}
+
+event: content_block_stop
+data: {\"type\":\"content_block_stop\",\"index\":0}
+
+event: content_block_start
+data: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_1\",\"name\":\"get_weather\",\"input\":{}}}
+
+event: content_block_delta
+data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"city\\\": \"}}
+
+event: content_block_delta
+data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"\\\"Paris\\\"}\"}}
+
+event: content_block_stop
+data: {\"type\":\"content_block_stop\",\"index\":1}
+
+event: message_delta
+data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\"},\"usage\":{\"output_tokens\":20}}
+
+event: message_stop
+data: {\"type\":\"message_stop\"}
+
+";
+
+async fn messages(headers: HeaderMap, Json(body): Json<Value>) -> axum::response::Response {
+    use axum::response::IntoResponse;
+
+    assert_eq!(headers["x-api-key"], "test-key");
+    assert_eq!(body["system"], "Be brief.");
+    assert_eq!(body["messages"][0]["role"], "user");
+
+    if body["stream"] == json!(true) {
+        return ([("content-type", "text/event-stream")], SSE_BODY).into_response();
+    }
+
+    // Tool results are sent back as a user turn.
+    assert_eq!(body["messages"][2]["content"][0]["type"], "tool_result");
+    assert_eq!(body["messages"][2]["content"][0]["tool_use_id"], "toolu_1");
+
+    Json(json!({
+        "id": "msg_1",
+        "type": "message",
+        "role": "assistant",
+        "content": [{"type": "text", "text": "Sunny."}],
+        "stop_reason": "end_turn",
+        "usage": {"input_tokens": 30, "output_tokens": 3}
+    }))
+    .into_response()
+}
+
+async fn spawn_mock_server() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = Router::new().route("/v1/messages", post(messages));
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    format!("http://{}", addr)
+}
+
+fn request(messages: Vec<Message>, stream: bool) -> ChatCompletionRequest {
+    ChatCompletionRequest {
+        model: "anthropic:claude-test".to_string(),
+        messages,
+        stream: Some(stream),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn streamed_tool_use_is_accumulated() {
+    let backend = AnthropicBackend::new(spawn_mock_server().await, "test-key".to_string());
+    let messages = vec![
+        Message::new("system", "Be brief."),
+        Message::new("user", "Weather in Paris?"),
+    ];
+
+    let response = backend
+        .chat_completion("claude-test", request(messages, true))
+        .await
+        .unwrap();
+
+    assert_eq!(response.message.content, "Let me check.");
+    let calls = response.message.tool_calls.unwrap();
+    assert_eq!(calls[0].id, "toolu_1");
+    assert_eq!(calls[0].function.name, "get_weather");
+    assert_eq!(calls[0].function.arguments, r#"{"city":"Paris"}"#);
+    assert_eq!(response.finish_reason.as_deref(), Some("tool_calls"));
+    assert_eq!(response.usage.unwrap().total_tokens, 32);
+}
+
+#[tokio::test]
+async fn tool_result_round_trip() {
+    let backend = AnthropicBackend::new(spawn_mock_server().await, "test-key".to_string());
+    let mut assistant = Message::new("assistant", "");
+    assistant.tool_calls = Some(vec![serde_json::from_value(json!({
+        "id": "toolu_1",
+        "type": "function",
+        "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}
+    }))
+    .unwrap()]);
+    let mut tool = Message::new("tool", "18C and sunny");
+    tool.tool_call_id = Some("toolu_1".to_string());
+
+    let messages = vec![
+        Message::new("system", "Be brief."),
+        Message::new("user", "Weather in Paris?"),
+        assistant,
+        tool,
+    ];
+
+    let response = backend
+        .chat_completion("claude-test", request(messages, false))
+        .await
+        .unwrap();
+
+    assert_eq!(response.message.content, "Sunny.");
+    assert_eq!(response.finish_reason.as_deref(), Some("stop"));
+    assert_eq!(response.usage.unwrap().prompt_tokens, 30);
+}
@@ -1,3 +1,6 @@
 pub mod api;
+pub mod auth;
+pub mod cache;
 pub mod chat_completion;
+pub mod embed;
 pub mod primitive;
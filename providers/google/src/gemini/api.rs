@@ -1,16 +1,164 @@
 use {
-    super::chat_completion::chat_completion,
-    topkio_primitive::api::{ChatCompletionResponse, Message, UnifiedLlmApi},
+    super::{
+        auth::GeminiAuth,
+        cache::{self, AutoCache},
+        chat_completion::chat_completion,
+        embed,
+        primitive::{
+            CachedContent, CountTokensRequest, CountTokensResponse, GeminiTool,
+            GenerateContentRequest, GenerationConfig, ListModelsResponse, SafetySetting,
+            ToolConfig,
+        },
+    },
+    std::{collections::BTreeMap, time::Duration},
+    topkio_primitive::{
+        api::{
+            ChatCompletionRequest, ChatCompletionResponse, CompletionRequest, CompletionResponse,
+            ContextCache, CreateCacheRequest, EmbeddingResponse, Message, ModelInfo, UnifiedLlmApi,
+        },
+        error::ProviderError,
+    },
 };
 
 pub struct GeminiBackend {
+    /// Models endpoint, e.g. `https://generativelanguage.googleapis.com/v1beta/models`.
     base_url: String,
-    api_key: String,
+    auth: GeminiAuth,
+    /// Model (or `*`) -> harm category -> blocking threshold.
+    safety_settings: BTreeMap<String, BTreeMap<String, String>>,
+    auto_cache: Option<AutoCache>,
 }
 
 impl GeminiBackend {
     pub fn new(base_url: String, api_key: String) -> Self {
-        Self { base_url, api_key }
+        Self::with_auth(base_url, GeminiAuth::ApiKey(api_key))
+    }
+
+    pub fn with_auth(base_url: String, auth: GeminiAuth) -> Self {
+        Self {
+            base_url,
+            auth,
+            safety_settings: BTreeMap::new(),
+            auto_cache: None,
+        }
+    }
+
+    /// Cache conversation prefixes of at least `min_tokens` tokens sent more
+    /// than once, for `ttl`.
+    pub fn with_auto_cache(mut self, min_tokens: u32, ttl: Duration) -> Self {
+        self.auto_cache = Some(AutoCache::new(min_tokens, ttl));
+        self
+    }
+
+    /// Set the blocking thresholds per model, with `*` applying to every
+    /// model.
+    pub fn with_safety_settings(
+        mut self,
+        safety_settings: BTreeMap<String, BTreeMap<String, String>>,
+    ) -> Self {
+        self.safety_settings = safety_settings;
+        self
+    }
+
+    /// Thresholds for `model`: those of `*`, then of the model, then of the
+    /// request, later ones overriding earlier ones per category.
+    fn safety_settings(
+        &self,
+        model: &str,
+        requested: Option<Vec<SafetySetting>>,
+    ) -> Option<Vec<SafetySetting>> {
+        let mut thresholds = BTreeMap::new();
+        for key in ["*", model] {
+            if let Some(settings) = self.safety_settings.get(key) {
+                thresholds.extend(settings.clone());
+            }
+        }
+        for setting in requested.into_iter().flatten() {
+            thresholds.insert(setting.category, setting.threshold);
+        }
+
+        (!thresholds.is_empty()).then(|| {
+            thresholds
+                .into_iter()
+                .map(|(category, threshold)| SafetySetting {
+                    category,
+                    threshold,
+                })
+                .collect()
+        })
+    }
+
+    /// The API root, e.g. `https://generativelanguage.googleapis.com/v1beta`,
+    /// and the resource name of the models below it: `models`, or
+    /// `projects/{project}/locations/{location}/publishers/google/models`.
+    fn split_base_url(&self) -> (String, String) {
+        let base_url = self.base_url.trim_end_matches('/');
+        let path = base_url
+            .find("://")
+            .and_then(|scheme| base_url[scheme + 3..].find('/').map(|i| scheme + 3 + i))
+            .unwrap_or(base_url.len());
+        match base_url[path..].trim_start_matches('/').split_once('/') {
+            Some((version, models)) => (
+                format!("{}/{}", &base_url[..path], version),
+                models.to_string(),
+            ),
+            None => (base_url.to_string(), "models".into()),
+        }
+    }
+
+    /// Whether the backend calls Vertex AI rather than the Gemini API.
+    fn is_vertex(&self) -> bool {
+        self.split_base_url().1.starts_with("projects/")
+    }
+
+    /// `GET url` with the backend's credentials.
+    async fn get(
+        &self,
+        url: &str,
+        query: &[(&str, &str)],
+    ) -> Result<reqwest::Response, ProviderError> {
+        let builder = reqwest::Client::new().get(url).query(query);
+        let response = self.auth.authorize(builder).await?.send().await?;
+        ProviderError::check(response).await
+    }
+
+    /// Resource name of `model`, e.g. `models/gemini-2.0-flash`.
+    fn model_resource(&self, model: &str) -> String {
+        format!("{}/{}", self.split_base_url().1, model)
+    }
+
+    /// URL of the `cachedContents` collection.
+    fn caches_url(&self) -> String {
+        let (root, models) = self.split_base_url();
+        let parent = models
+            .trim_end_matches("models")
+            .trim_end_matches("publishers/google/");
+        format!("{}/{}cachedContents", root, parent)
+    }
+
+    /// URL of a cache, by name or by the last segment of its name.
+    fn cache_url(&self, name: &str) -> String {
+        match name.contains('/') {
+            true => format!("{}/{}", self.split_base_url().0, name),
+            false => format!("{}/{}", self.caches_url(), name),
+        }
+    }
+
+    /// Gemini models of a Vertex AI project.
+    pub fn vertex(project: &str, location: &str, auth: GeminiAuth) -> Self {
+        Self::with_auth(Self::vertex_url(project, location), auth)
+    }
+
+    /// Models endpoint of Vertex AI for `project` in `location`.
+    pub fn vertex_url(project: &str, location: &str) -> String {
+        let host = match location {
+            "global" => "aiplatform.googleapis.com".to_string(),
+            location => format!("{}-aiplatform.googleapis.com", location),
+        };
+        format!(
+            "https://{}/v1/projects/{}/locations/{}/publishers/google/models",
+            host, project, location
+        )
     }
 }
 
@@ -19,21 +167,184 @@ impl UnifiedLlmApi for GeminiBackend {
     async fn chat_completion(
         &self,
         model: &str,
-        messages: Vec<Message>,
-        stream: Option<bool>,
+        request: ChatCompletionRequest,
     ) -> Result<ChatCompletionResponse, anyhow::Error> {
-        let response =
-            chat_completion(&self.base_url, &self.api_key, model, messages, stream).await?;
+        let generation_config = GenerationConfig::new(&request);
+        let mut body = GenerateContentRequest::new(request.messages)?;
+        body.generation_config = generation_config;
+        body.tools = GeminiTool::all(request.tools, request.builtin_tools);
+        body.tool_config = request.tool_choice.map(ToolConfig::from);
+        body.cached_content = request.cached_content;
+        if let Some(auto_cache) = &self.auto_cache {
+            let model = self.model_resource(model);
+            auto_cache
+                .apply(&self.caches_url(), &self.auth, &model, &mut body)
+                .await;
+        }
+        body.safety_settings = self.safety_settings(model, request.safety_settings);
 
-        Ok(ChatCompletionResponse {
-            message: Message {
-                role: "assistant".to_string(),
-                content: response.text,
-            },
+        chat_completion(&self.base_url, &self.auth, model, body).await
+    }
+
+    /// Gemini has no raw completion: the prompt is sent as a user message.
+    async fn completion(
+        &self,
+        model: &str,
+        request: CompletionRequest,
+    ) -> Result<CompletionResponse, anyhow::Error> {
+        if request.suffix.is_some() || request.context.is_some() {
+            return Err(ProviderError::InvalidRequest(
+                "Gemini models take neither suffix nor context".into(),
+            )
+            .into());
+        }
+
+        let generation_config = GenerationConfig::for_completion(&request);
+        let mut body = GenerateContentRequest::new(vec![Message::new("user", request.prompt)])?;
+        body.generation_config = Some(generation_config);
+        body.safety_settings = self.safety_settings(model, None);
+
+        let response = chat_completion(&self.base_url, &self.auth, model, body).await?;
+        Ok(CompletionResponse {
+            text: response.message.content.text(),
+            finish_reason: response.finish_reason,
+            usage: response.usage,
+            context: None,
         })
     }
 
+    fn supports_n(&self) -> bool {
+        true
+    }
+
+    fn supports_caching(&self) -> bool {
+        true
+    }
+
+    async fn create_cache(
+        &self,
+        model: &str,
+        request: CreateCacheRequest,
+    ) -> Result<ContextCache, anyhow::Error> {
+        let body = GenerateContentRequest::new(request.messages)?;
+        let cache = CachedContent {
+            model: self.model_resource(model),
+            contents: body.contents,
+            system_instruction: body.system_instruction,
+            ttl: request.ttl_seconds.map(|ttl| format!("{}s", ttl)),
+            display_name: request.display_name,
+            ..Default::default()
+        };
+
+        Ok(cache::create(&self.caches_url(), &self.auth, &cache)
+            .await?
+            .into())
+    }
+
+    async fn list_caches(&self) -> Result<Vec<ContextCache>, anyhow::Error> {
+        let caches = cache::list(&self.caches_url(), &self.auth).await?;
+        Ok(caches.into_iter().map(ContextCache::from).collect())
+    }
+
+    async fn delete_cache(&self, name: &str) -> Result<(), anyhow::Error> {
+        cache::delete(&self.cache_url(name), &self.auth).await?;
+        if let Some(auto_cache) = &self.auto_cache {
+            auto_cache.forget(name);
+        }
+        Ok(())
+    }
+
+    /// Lists a single model, or cache on Vertex AI, which checks the
+    /// credentials (and project) too.
     async fn health_check(&self) -> Result<(), anyhow::Error> {
+        let url = match self.is_vertex() {
+            true => self.caches_url(),
+            false => self.base_url.clone(),
+        };
+        self.get(&url, &[("pageSize", "1")]).await?;
         Ok(())
     }
+
+    /// The models of `models.list`. Vertex AI lists publisher models through
+    /// another API, so none are reported there.
+    async fn get_models(&self) -> Result<Vec<ModelInfo>, anyhow::Error> {
+        if self.is_vertex() {
+            return Ok(vec![]);
+        }
+
+        let mut models = vec![];
+        let mut page_token = String::new();
+        loop {
+            let mut query = vec![("pageSize", "1000")];
+            if !page_token.is_empty() {
+                query.push(("pageToken", &page_token));
+            }
+            let page: ListModelsResponse = self.get(&self.base_url, &query).await?.json().await?;
+
+            models.extend(page.models.into_iter().map(ModelInfo::from));
+            match page.next_page_token {
+                Some(token) if !token.is_empty() => page_token = token,
+                _ => return Ok(models),
+            }
+        }
+    }
+
+    async fn embed(
+        &self,
+        model: &str,
+        input: Vec<String>,
+    ) -> Result<EmbeddingResponse, anyhow::Error> {
+        let response = match self.is_vertex() {
+            true => {
+                let url = format!("{}/{}:predict", self.base_url, model);
+                embed::predict(&url, &self.auth, input).await?
+            }
+            false => {
+                let url = format!("{}/{}:batchEmbedContents", self.base_url, model);
+                embed::batch_embed(&url, &self.auth, &self.model_resource(model), input).await?
+            }
+        };
+
+        Ok(response)
+    }
+
+    async fn count_tokens(
+        &self,
+        model: &str,
+        request: &ChatCompletionRequest,
+    ) -> Result<Option<u32>, anyhow::Error> {
+        let mut body = GenerateContentRequest::new(request.messages.clone())?;
+        body.tools = GeminiTool::all(request.tools.clone(), request.builtin_tools.clone());
+
+        let count = match self.is_vertex() {
+            true => CountTokensRequest {
+                contents: body.contents,
+                system_instruction: body.system_instruction,
+                tools: body.tools,
+                ..Default::default()
+            },
+            false => {
+                let mut generate_content_request = serde_json::to_value(&body)?;
+                generate_content_request["model"] = self.model_resource(model).into();
+                CountTokensRequest {
+                    generate_content_request: Some(generate_content_request),
+                    ..Default::default()
+                }
+            }
+        };
+
+        let builder = reqwest::Client::new()
+            .post(format!("{}/{}:countTokens", self.base_url, model))
+            .json(&count);
+        let response = self
+            .auth
+            .authorize(builder)
+            .await?
+            .send()
+            .await
+            .map_err(ProviderError::from)?;
+        let response: CountTokensResponse = ProviderError::check(response).await?.json().await?;
+
+        Ok(Some(response.total_tokens))
+    }
 }
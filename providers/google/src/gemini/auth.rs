@@ -0,0 +1,280 @@
+//! Authentication of Gemini requests: an API key for the Gemini API, or
+//! OAuth access tokens of a service account, as required by Vertex AI.
+
+use {
+    reqwest::RequestBuilder,
+    serde::{Deserialize, Serialize},
+    std::time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    topkio_primitive::error::ProviderError,
+};
+
+const SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+const DEFAULT_TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+
+/// Access tokens are renewed this long before they expire.
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+pub enum GeminiAuth {
+    /// Sent in the `x-goog-api-key` header.
+    ApiKey(String),
+    ServiceAccount(ServiceAccount),
+}
+
+impl GeminiAuth {
+    /// Add the credentials to a request.
+    pub async fn authorize(
+        &self,
+        builder: RequestBuilder,
+    ) -> Result<RequestBuilder, ProviderError> {
+        match self {
+            Self::ApiKey(api_key) => Ok(builder.header("x-goog-api-key", api_key)),
+            Self::ServiceAccount(account) => Ok(builder.bearer_auth(account.access_token().await?)),
+        }
+    }
+}
+
+/// The fields of a service-account key file used for the JWT grant.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServiceAccountKey {
+    pub client_email: String,
+    pub private_key: String,
+    #[serde(default)]
+    pub private_key_id: Option<String>,
+    #[serde(default = "default_token_uri")]
+    pub token_uri: String,
+}
+
+fn default_token_uri() -> String {
+    DEFAULT_TOKEN_URI.into()
+}
+
+impl ServiceAccountKey {
+    /// Parse a service-account key file (JSON).
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let key: Self = serde_json::from_str(json)
+            .map_err(|e| anyhow::anyhow!("invalid service account key: {}", e))?;
+        jsonwebtoken::EncodingKey::from_rsa_pem(key.private_key.as_bytes())
+            .map_err(|e| anyhow::anyhow!("invalid service account private_key: {}", e))?;
+        Ok(key)
+    }
+}
+
+#[derive(Serialize)]
+struct Claims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: u64,
+    exp: u64,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+/// A service account exchanging signed JWTs for cached access tokens.
+pub struct ServiceAccount {
+    key: ServiceAccountKey,
+    client: reqwest::Client,
+    token: tokio::sync::Mutex<Option<(String, Instant)>>,
+}
+
+impl ServiceAccount {
+    pub fn new(key: ServiceAccountKey) -> Self {
+        Self {
+            key,
+            client: reqwest::Client::new(),
+            token: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    /// A valid access token, fetching a new one when needed.
+    pub async fn access_token(&self) -> Result<String, ProviderError> {
+        let mut token = self.token.lock().await;
+        if let Some((access_token, expires_at)) = token.as_ref() {
+            if Instant::now() + REFRESH_MARGIN < *expires_at {
+                return Ok(access_token.clone());
+            }
+        }
+
+        let response = self
+            .client
+            .post(&self.key.token_uri)
+            .form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                ("assertion", &self.assertion()?),
+            ])
+            .send()
+            .await?;
+        let response: TokenResponse = ProviderError::check(response)
+            .await
+            .map_err(|e| ProviderError::Authentication(format!("token request failed: {}", e)))?
+            .json()
+            .await?;
+
+        let expires_at = Instant::now() + Duration::from_secs(response.expires_in);
+        *token = Some((response.access_token.clone(), expires_at));
+        Ok(response.access_token)
+    }
+
+    fn assertion(&self) -> Result<String, ProviderError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let claims = Claims {
+            iss: &self.key.client_email,
+            scope: SCOPE,
+            aud: &self.key.token_uri,
+            iat: now,
+            exp: now + 3600,
+        };
+
+        let mut header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256);
+        header.kid = self.key.private_key_id.clone();
+        jsonwebtoken::EncodingKey::from_rsa_pem(self.key.private_key.as_bytes())
+            .and_then(|key| jsonwebtoken::encode(&header, &claims, &key))
+            .map_err(|e| ProviderError::Authentication(format!("cannot sign token request: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        axum::{http::StatusCode, routing::post, Form, Json, Router},
+        jsonwebtoken::{Algorithm, DecodingKey, Validation},
+        std::{
+            collections::HashMap,
+            sync::{
+                atomic::{AtomicUsize, Ordering},
+                Arc,
+            },
+        },
+    };
+
+    /// A throwaway RSA key pair, as found in a service-account key file.
+    const PRIVATE_KEY: &str = include_str!("../../tests/fixtures/service_account.pem");
+    const PUBLIC_KEY: &str = include_str!("../../tests/fixtures/service_account.pub");
+    const CLIENT_EMAIL: &str = "topkio@my-project.iam.gserviceaccount.com";
+
+    fn key(token_uri: &str) -> ServiceAccountKey {
+        ServiceAccountKey {
+            client_email: CLIENT_EMAIL.into(),
+            private_key: PRIVATE_KEY.into(),
+            private_key_id: Some("key-1".into()),
+            token_uri: token_uri.into(),
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct AssertionClaims {
+        iss: String,
+        scope: String,
+        iat: u64,
+        exp: u64,
+    }
+
+    #[test]
+    fn assertion_is_signed_by_the_service_account() {
+        let jwt = ServiceAccount::new(key(DEFAULT_TOKEN_URI))
+            .assertion()
+            .unwrap();
+
+        let header = jsonwebtoken::decode_header(&jwt).unwrap();
+        assert_eq!(header.alg, Algorithm::RS256);
+        assert_eq!(header.kid.as_deref(), Some("key-1"));
+
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_audience(&[DEFAULT_TOKEN_URI]);
+        let decoding_key = DecodingKey::from_rsa_pem(PUBLIC_KEY.as_bytes()).unwrap();
+        let claims = jsonwebtoken::decode::<AssertionClaims>(&jwt, &decoding_key, &validation)
+            .unwrap()
+            .claims;
+        assert_eq!(claims.iss, CLIENT_EMAIL);
+        assert_eq!(claims.scope, SCOPE);
+        assert_eq!(claims.exp - claims.iat, 3600);
+    }
+
+    #[test]
+    fn parses_service_account_key_files() {
+        let json = serde_json::json!({
+            "type": "service_account",
+            "client_email": CLIENT_EMAIL,
+            "private_key": PRIVATE_KEY,
+        });
+        let key = ServiceAccountKey::from_json(&json.to_string()).unwrap();
+        assert_eq!(key.token_uri, DEFAULT_TOKEN_URI);
+        assert_eq!(key.private_key_id, None);
+
+        let bad_pem = serde_json::json!({"client_email": CLIENT_EMAIL, "private_key": "not a key"});
+        assert!(ServiceAccountKey::from_json(&bad_pem.to_string()).is_err());
+        assert!(ServiceAccountKey::from_json(r#"{"client_email": "a@b"}"#).is_err());
+    }
+
+    /// A token endpoint answering with tokens valid for `expires_in`
+    /// seconds, or with `400` when `expires_in` is `None`. Returns its URL
+    /// and the number of requests it got.
+    async fn token_server(expires_in: Option<u64>) -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let handler = move |Form(form): Form<HashMap<String, String>>| async move {
+            let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            let grant = form.get("grant_type").map(String::as_str);
+            match (expires_in, grant) {
+                (Some(expires_in), Some("urn:ietf:params:oauth:grant-type:jwt-bearer"))
+                    if form.contains_key("assertion") =>
+                {
+                    let token = serde_json::json!({
+                        "access_token": format!("token-{}", n),
+                        "expires_in": expires_in,
+                        "token_type": "Bearer",
+                    });
+                    (StatusCode::OK, Json(token))
+                }
+                _ => (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({"error": "invalid_grant"})),
+                ),
+            }
+        };
+        let app = Router::new().route("/token", post(handler));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, requests)
+    }
+
+    #[tokio::test]
+    async fn caches_access_tokens() {
+        let (url, requests) = token_server(Some(3600)).await;
+        let account = ServiceAccount::new(key(&url));
+
+        assert_eq!(account.access_token().await.unwrap(), "token-1");
+        assert_eq!(account.access_token().await.unwrap(), "token-1");
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn renews_tokens_about_to_expire() {
+        let (url, requests) = token_server(Some(REFRESH_MARGIN.as_secs() / 2)).await;
+        let account = ServiceAccount::new(key(&url));
+
+        assert_eq!(account.access_token().await.unwrap(), "token-1");
+        assert_eq!(account.access_token().await.unwrap(), "token-2");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn rejected_grants_are_authentication_errors() {
+        let (url, _) = token_server(None).await;
+        let account = ServiceAccount::new(key(&url));
+
+        assert!(matches!(
+            account.access_token().await,
+            Err(ProviderError::Authentication(_))
+        ));
+    }
+}
@@ -0,0 +1,305 @@
+//! Context caches (`cachedContents`): caches managed through the gateway,
+//! and automatic caches of long conversation prefixes that are sent again.
+
+use {
+    crate::gemini::{
+        auth::GeminiAuth,
+        primitive::{CachedContent, Content, GenerateContentRequest, ListCachedContentsResponse},
+    },
+    std::{
+        collections::HashMap,
+        hash::{DefaultHasher, Hash, Hasher},
+        sync::Mutex,
+        time::{Duration, Instant},
+    },
+    topkio_primitive::{error::ProviderError, tokens},
+};
+
+/// Automatic caches are no longer used this long before they expire.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// Prefixes remembered for automatic caching, at most.
+const MAX_TRACKED_PREFIXES: usize = 1024;
+
+/// Create a cache in the `cachedContents` collection at `url`.
+pub async fn create(
+    url: &str,
+    auth: &GeminiAuth,
+    cache: &CachedContent,
+) -> Result<CachedContent, ProviderError> {
+    let response = auth
+        .authorize(reqwest::Client::new().post(url))
+        .await?
+        .json(cache)
+        .send()
+        .await?;
+    Ok(ProviderError::check(response).await?.json().await?)
+}
+
+/// Every cache of the collection at `url`, following pages.
+pub async fn list(url: &str, auth: &GeminiAuth) -> Result<Vec<CachedContent>, ProviderError> {
+    let mut caches = vec![];
+    let mut page_token: Option<String> = None;
+    loop {
+        let mut builder = reqwest::Client::new().get(url);
+        if let Some(token) = &page_token {
+            builder = builder.query(&[("pageToken", token)]);
+        }
+        let response = auth.authorize(builder).await?.send().await?;
+        let page: ListCachedContentsResponse = ProviderError::check(response).await?.json().await?;
+
+        caches.extend(page.cached_contents);
+        match page.next_page_token {
+            Some(token) if !token.is_empty() => page_token = Some(token),
+            _ => return Ok(caches),
+        }
+    }
+}
+
+/// Delete the cache at `url`.
+pub async fn delete(url: &str, auth: &GeminiAuth) -> Result<(), ProviderError> {
+    let response = auth
+        .authorize(reqwest::Client::new().delete(url))
+        .await?
+        .send()
+        .await?;
+    ProviderError::check(response).await?;
+    Ok(())
+}
+
+/// Caches the start of conversations (system instruction, tools and every
+/// content but the last) once it is long and has been sent twice.
+pub struct AutoCache {
+    /// Prefixes of fewer tokens are not cached.
+    min_tokens: u32,
+    ttl: Duration,
+    prefixes: Mutex<HashMap<u64, Prefix>>,
+}
+
+enum Prefix {
+    /// Sent once.
+    Seen,
+    /// A request is creating the cache.
+    Creating,
+    Cached {
+        name: String,
+        expires_at: Instant,
+    },
+    /// Not tried again until `retry_at`.
+    Failed {
+        retry_at: Instant,
+    },
+}
+
+impl AutoCache {
+    pub fn new(min_tokens: u32, ttl: Duration) -> Self {
+        Self {
+            min_tokens,
+            ttl,
+            prefixes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Move the prefix of `body` to a cache of `model` (a model resource),
+    /// created in the collection at `url` when needed. Requests are sent
+    /// uncached when the cache cannot be created.
+    pub async fn apply(
+        &self,
+        url: &str,
+        auth: &GeminiAuth,
+        model: &str,
+        body: &mut GenerateContentRequest,
+    ) {
+        let Some(split) = body.contents.len().checked_sub(1) else {
+            return;
+        };
+        let prefix = &body.contents[..split];
+        if body.cached_content.is_some()
+            || (prefix.is_empty() && body.system_instruction.is_none())
+            || count_tokens(body.system_instruction.iter().chain(prefix)) < self.min_tokens
+        {
+            return;
+        }
+
+        let key = {
+            let json = serde_json::to_string(&(
+                model,
+                &body.system_instruction,
+                prefix,
+                &body.tools,
+                &body.tool_config,
+            ))
+            .unwrap_or_default();
+            let mut hasher = DefaultHasher::new();
+            json.hash(&mut hasher);
+            hasher.finish()
+        };
+
+        let now = Instant::now();
+        let cached = {
+            let mut prefixes = self.prefixes.lock().expect("auto cache lock");
+            match prefixes.get(&key) {
+                Some(Prefix::Cached { name, expires_at }) if now < *expires_at => {
+                    Some(name.clone())
+                }
+                Some(Prefix::Creating) => return,
+                Some(Prefix::Failed { retry_at }) if now < *retry_at => return,
+                None => {
+                    if prefixes.len() >= MAX_TRACKED_PREFIXES {
+                        prefixes.retain(|_, prefix| {
+                            matches!(prefix, Prefix::Cached { expires_at, .. } if now < *expires_at)
+                        });
+                    }
+                    prefixes.insert(key, Prefix::Seen);
+                    return;
+                }
+                Some(_) => {
+                    prefixes.insert(key, Prefix::Creating);
+                    None
+                }
+            }
+        };
+
+        let name = match cached {
+            Some(name) => name,
+            None => {
+                // Declared before the lock below, so it is dropped after it.
+                let _creating = CreatingGuard {
+                    prefixes: &self.prefixes,
+                    key,
+                };
+                let cache = CachedContent {
+                    model: model.to_string(),
+                    contents: body.contents[..split].to_vec(),
+                    system_instruction: body.system_instruction.clone(),
+                    tools: body.tools.clone(),
+                    tool_config: body.tool_config.clone(),
+                    ttl: Some(format!("{}s", self.ttl.as_secs())),
+                    ..Default::default()
+                };
+                let created = create(url, auth, &cache).await.and_then(|cache| {
+                    cache
+                        .name
+                        .ok_or_else(|| ProviderError::Upstream("cache without a name".into()))
+                });
+
+                let mut prefixes = self.prefixes.lock().expect("auto cache lock");
+                match created {
+                    Ok(name) => {
+                        println!("Cached a conversation prefix of {} as {}", model, name);
+                        let expires_at = now + self.ttl.saturating_sub(EXPIRY_MARGIN);
+                        let cached = Prefix::Cached {
+                            name: name.clone(),
+                            expires_at,
+                        };
+                        prefixes.insert(key, cached);
+                        name
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to cache a conversation prefix of {}: {}", model, e);
+                        let retry_at = now + self.ttl;
+                        prefixes.insert(key, Prefix::Failed { retry_at });
+                        return;
+                    }
+                }
+            }
+        };
+
+        body.cached_content = Some(name);
+        body.contents.drain(..split);
+        body.system_instruction = None;
+        body.tools = None;
+        body.tool_config = None;
+    }
+
+    /// Forget the cache `name`, which was deleted.
+    pub fn forget(&self, name: &str) {
+        let mut prefixes = self.prefixes.lock().expect("auto cache lock");
+        prefixes.retain(|_, prefix| match prefix {
+            Prefix::Cached { name: cached, .. } => {
+                cached != name && !cached.ends_with(&format!("/{}", name))
+            }
+            _ => true,
+        });
+    }
+}
+
+/// Puts a prefix whose cache is being created back to `Seen` when the
+/// creation is abandoned, e.g. because the client disconnected, so a later
+/// request creates it instead.
+struct CreatingGuard<'a> {
+    prefixes: &'a Mutex<HashMap<u64, Prefix>>,
+    key: u64,
+}
+
+impl Drop for CreatingGuard<'_> {
+    fn drop(&mut self) {
+        let mut prefixes = self.prefixes.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(prefix @ Prefix::Creating) = prefixes.get_mut(&self.key) {
+            *prefix = Prefix::Seen;
+        }
+    }
+}
+
+/// Rough token count of `contents`, as Gemini has no local tokenizer.
+fn count_tokens<'a>(contents: impl Iterator<Item = &'a Content>) -> u32 {
+    contents
+        .flat_map(|content| &content.parts)
+        .map(|part| match &part.text {
+            Some(text) => tokens::count_text("gemini", text).tokens,
+            None => tokens::TOKENS_PER_MEDIA_PART,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::gemini::primitive::Part,
+        tokio::{net::TcpListener, time::timeout},
+    };
+
+    fn request() -> GenerateContentRequest {
+        let content = |role: &str, text: String| Content {
+            parts: vec![Part::text(text)],
+            role: Some(role.into()),
+        };
+        GenerateContentRequest {
+            contents: vec![
+                content("user", "A long document. ".repeat(100)),
+                content("user", "Summarize it.".into()),
+            ],
+            system_instruction: None,
+            tools: None,
+            tool_config: None,
+            cached_content: None,
+            safety_settings: None,
+            generation_config: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn abandoned_creation_is_retried() {
+        // Accepts connections and never answers.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/cachedContents", listener.local_addr().unwrap());
+        let auth = GeminiAuth::ApiKey("test".into());
+        let auto_cache = AutoCache::new(100, Duration::from_secs(600));
+        let model = "models/gemini-2.0-flash-001";
+
+        let mut body = request();
+        auto_cache.apply(&url, &auth, model, &mut body).await;
+        assert!(body.cached_content.is_none());
+
+        let mut body = request();
+        let creating = auto_cache.apply(&url, &auth, model, &mut body);
+        assert!(timeout(Duration::from_millis(200), creating).await.is_err());
+
+        let prefixes = auto_cache.prefixes.lock().unwrap();
+        assert_eq!(prefixes.len(), 1);
+        assert!(prefixes
+            .values()
+            .all(|prefix| matches!(prefix, Prefix::Seen)));
+    }
+}
@@ -1 +1,89 @@
-// embed for Gemini
\ No newline at end of file
+//! Embeddings: `batchEmbedContents` on the Gemini API, `predict` on
+//! Vertex AI.
+
+use {
+    crate::gemini::{
+        auth::GeminiAuth,
+        primitive::{
+            BatchEmbedContentsRequest, BatchEmbedContentsResponse, Content, EmbedContentRequest,
+            EmbeddingInstance, Part, PredictEmbeddingsRequest, PredictEmbeddingsResponse,
+        },
+    },
+    serde::{de::DeserializeOwned, Serialize},
+    topkio_primitive::{
+        api::{EmbeddingResponse, Usage},
+        error::ProviderError,
+    },
+};
+
+/// Embed `input` with `model` (a model resource) at `url`, a model's
+/// `:batchEmbedContents` endpoint.
+pub async fn batch_embed(
+    url: &str,
+    auth: &GeminiAuth,
+    model: &str,
+    input: Vec<String>,
+) -> Result<EmbeddingResponse, ProviderError> {
+    let body = BatchEmbedContentsRequest {
+        requests: input
+            .into_iter()
+            .map(|text| EmbedContentRequest {
+                model: model.to_string(),
+                content: Content {
+                    parts: vec![Part::text(text)],
+                    role: None,
+                },
+            })
+            .collect(),
+    };
+    let response: BatchEmbedContentsResponse = post(url, auth, &body).await?;
+
+    Ok(EmbeddingResponse {
+        embeddings: response.embeddings.into_iter().map(|e| e.values).collect(),
+        usage: None,
+    })
+}
+
+/// Embed `input` at `url`, a Vertex AI model's `:predict` endpoint.
+pub async fn predict(
+    url: &str,
+    auth: &GeminiAuth,
+    input: Vec<String>,
+) -> Result<EmbeddingResponse, ProviderError> {
+    let body = PredictEmbeddingsRequest {
+        instances: input
+            .into_iter()
+            .map(|content| EmbeddingInstance { content })
+            .collect(),
+    };
+    let response: PredictEmbeddingsResponse = post(url, auth, &body).await?;
+
+    let tokens = response
+        .predictions
+        .iter()
+        .filter_map(|prediction| prediction.embeddings.statistics.as_ref())
+        .map(|statistics| statistics.token_count as u32)
+        .sum();
+    Ok(EmbeddingResponse {
+        embeddings: response
+            .predictions
+            .into_iter()
+            .map(|prediction| prediction.embeddings.values)
+            .collect(),
+        usage: Some(Usage::new(tokens, 0)),
+    })
+}
+
+async fn post<T: DeserializeOwned>(
+    url: &str,
+    auth: &GeminiAuth,
+    body: &impl Serialize,
+) -> Result<T, ProviderError> {
+    let response = auth
+        .authorize(reqwest::Client::new().post(url))
+        .await?
+        .json(body)
+        .send()
+        .await?;
+    Ok(ProviderError::check(response).await?.json().await?)
+}
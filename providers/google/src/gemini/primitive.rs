@@ -1,35 +1,179 @@
-#![allow(unused)]
+use {
+    serde::{Deserialize, Serialize},
+    topkio_primitive::{
+        api::{
+            parse_data_url, BuiltinTool, ChatCompletionRequest, ChatCompletionResponse, Choice,
+            CompletionRequest, ContentPart, ContextCache, FunctionCall, Logprobs, Message,
+            ModelInfo, ResponseFormat, TokenLogprob, Tool, ToolCall, ToolChoice, ToolChoiceMode,
+            TopLogprob, Usage,
+        },
+        error::ProviderError,
+    },
+};
 
-use serde::{Deserialize, Serialize};
+pub use topkio_primitive::api::SafetySetting;
 
-#[derive(Debug, Serialize)]
-pub struct GeminiRequest {
-    pub contents: Vec<Content>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub safety_settings: Option<Vec<SafetySetting>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub generation_config: Option<GenerationConfig>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Content {
+    /// Missing from candidates blocked for safety.
+    #[serde(default)]
     pub parts: Vec<Part>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub role: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Part {
-    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inline_data: Option<Blob>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_data: Option<FileData>,
+
+    /// Code written by the model, with the code execution tool.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub executable_code: Option<serde_json::Value>,
+
+    /// Result of running `executable_code`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code_execution_result: Option<serde_json::Value>,
+
+    /// A call of a declared function, by the model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function_call: Option<GeminiFunctionCall>,
+
+    /// Result of a `function_call`, sent back to the model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function_response: Option<FunctionResponse>,
 }
 
-#[derive(Debug, Serialize)]
-pub struct SafetySetting {
-    pub category: String,
-    pub threshold: String,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Blob {
+    /// The IANA standard MIME type of the source data. Examples: - image/png - image/jpeg
+    pub mime_type: String,
+    /// Raw bytes for media formats. A base64-encoded string.
+    pub data: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileData {
+    /// Optional. The IANA standard MIME type of the source data.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    /// Required. URI, e.g. of the Files API or `gs://`.
+    pub file_uri: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeminiFunctionCall {
+    pub name: String,
+    /// Arguments as an object, where OpenAI has a JSON string.
+    #[serde(default)]
+    pub args: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionResponse {
+    pub name: String,
+    /// Must be an object: other results are wrapped as `{"content": ...}`.
+    pub response: serde_json::Value,
+}
+
+impl Part {
+    pub fn text(text: String) -> Self {
+        Self {
+            text: Some(text),
+            ..Default::default()
+        }
+    }
+
+    fn inline(mime_type: &str, data: &str) -> Self {
+        Self {
+            inline_data: Some(Blob {
+                mime_type: mime_type.to_string(),
+                data: data.to_string(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// A `fileData` part, typed by the extension of `name` or the URI.
+    fn file(file_uri: String, name: Option<&str>) -> Result<Self, ProviderError> {
+        let name = name.unwrap_or(&file_uri);
+        let mime_type = mime_type_of(name).ok_or_else(|| {
+            ProviderError::InvalidRequest(format!(
+                "cannot tell the MIME type of {}; give a filename with a known extension",
+                name
+            ))
+        })?;
+        Ok(Self {
+            file_data: Some(FileData {
+                mime_type: Some(mime_type.to_string()),
+                file_uri,
+            }),
+            ..Default::default()
+        })
+    }
+}
+
+impl TryFrom<ContentPart> for Part {
+    type Error = ProviderError;
+
+    fn try_from(part: ContentPart) -> Result<Self, Self::Error> {
+        match part {
+            ContentPart::Text { text } => Ok(Part::text(text)),
+            // Gemini does not fetch web URLs; only Cloud Storage URIs.
+            ContentPart::ImageUrl { image_url } => match parse_data_url(&image_url.url) {
+                Some((mime_type, data)) => Ok(Part::inline(mime_type, data)),
+                None if image_url.url.starts_with("gs://") => Part::file(image_url.url, None),
+                None => Err(ProviderError::InvalidRequest(
+                    "Gemini only accepts images as base64 data URLs or gs:// URIs".into(),
+                )),
+            },
+            ContentPart::InputAudio { input_audio } => Ok(Part::inline(
+                &format!("audio/{}", input_audio.format),
+                &input_audio.data,
+            )),
+            ContentPart::File { file } => {
+                match (file.file_data.as_deref().map(parse_data_url), file.file_id) {
+                    (Some(Some((mime_type, data))), _) => Ok(Part::inline(mime_type, data)),
+                    (None, Some(file_id)) => Part::file(file_id, file.filename.as_deref()),
+                    _ => Err(ProviderError::InvalidRequest(
+                        "files must have a file_id or base64 file_data".into(),
+                    )),
+                }
+            }
+        }
+    }
+}
+
+/// MIME type of a file by its extension, for `fileData` URIs.
+fn mime_type_of(name: &str) -> Option<&'static str> {
+    let extension = name.rsplit_once('.')?.1.to_lowercase();
+    Some(match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "webp" => "image/webp",
+        "gif" => "image/gif",
+        "heic" => "image/heic",
+        "pdf" => "application/pdf",
+        "txt" => "text/plain",
+        "mp3" => "audio/mp3",
+        "wav" => "audio/wav",
+        "flac" => "audio/flac",
+        "mp4" => "video/mp4",
+        _ => return None,
+    })
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct GenerationConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
@@ -37,59 +181,399 @@ pub struct GenerationConfig {
     pub top_p: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_output_tokens: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_sequences: Option<Vec<String>>,
+    /// `application/json` for JSON output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_mime_type: Option<String>,
+    /// OpenAPI schema the JSON output must match.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_schema: Option<serde_json::Value>,
+    /// Number of candidates to generate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub candidate_count: Option<u32>,
+    /// Return the log probabilities of the chosen tokens.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_logprobs: Option<bool>,
+    /// Number of top tokens to return per position, with `response_logprobs`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<u32>,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct GeminiResponse {
-    pub text: String,
+impl GenerationConfig {
+    /// Generation config of a unified request, if it needs one.
+    pub fn new(request: &ChatCompletionRequest) -> Option<Self> {
+        let mut config = request
+            .response_format
+            .clone()
+            .and_then(Self::for_format)
+            .unwrap_or_default();
+        config.temperature = request.temperature;
+        config.max_output_tokens = request.max_tokens.map(|tokens| tokens as i32);
+        config.stop_sequences = request.stop.clone();
+        config.candidate_count = request.n.filter(|n| *n > 1);
+        if request.logprobs == Some(true) {
+            config.response_logprobs = Some(true);
+            config.logprobs = request.top_logprobs;
+        }
+
+        (config.temperature.is_some()
+            || config.max_output_tokens.is_some()
+            || config.stop_sequences.is_some()
+            || config.response_mime_type.is_some()
+            || config.candidate_count.is_some()
+            || config.response_logprobs.is_some())
+        .then_some(config)
+    }
+
+    /// Generation config of a plain text completion request.
+    pub fn for_completion(request: &CompletionRequest) -> Self {
+        Self {
+            temperature: request.temperature,
+            max_output_tokens: request.max_tokens.map(|tokens| tokens as i32),
+            stop_sequences: request.stop.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Generation config asking for `format`, if it needs one.
+    pub fn for_format(format: ResponseFormat) -> Option<Self> {
+        let schema = match format {
+            ResponseFormat::Text => return None,
+            ResponseFormat::JsonObject => None,
+            ResponseFormat::JsonSchema { json_schema } => Some(openapi_schema(json_schema.schema)),
+        };
+        Some(Self {
+            response_mime_type: Some("application/json".into()),
+            response_schema: schema,
+            ..Default::default()
+        })
+    }
 }
 
-impl GeminiResponse {
-    pub fn new(text: String) -> Self {
-        Self { text }
+/// Keywords of the OpenAPI schema subset accepted as `responseSchema`.
+const SCHEMA_KEYWORDS: &[&str] = &[
+    "type",
+    "format",
+    "title",
+    "description",
+    "nullable",
+    "enum",
+    "items",
+    "minItems",
+    "maxItems",
+    "properties",
+    "required",
+    "minProperties",
+    "maxProperties",
+    "minLength",
+    "maxLength",
+    "pattern",
+    "minimum",
+    "maximum",
+    "anyOf",
+    "propertyOrdering",
+    "default",
+    "example",
+];
+
+/// Convert a JSON Schema to Gemini's OpenAPI subset: unsupported keywords
+/// (`additionalProperties`, `$schema`, ...) are dropped and
+/// `"type": ["string", "null"]` becomes `"nullable": true`.
+pub fn openapi_schema(schema: serde_json::Value) -> serde_json::Value {
+    use serde_json::Value;
+
+    let Value::Object(object) = schema else {
+        return schema;
+    };
+
+    let mut converted = serde_json::Map::new();
+    for (key, value) in object {
+        if !SCHEMA_KEYWORDS.contains(&key.as_str()) {
+            continue;
+        }
+        let value = match (key.as_str(), value) {
+            ("type", Value::Array(types)) => {
+                let mut types: Vec<Value> = types;
+                if let Some(null) = types.iter().position(|t| t == "null") {
+                    types.remove(null);
+                    converted.insert("nullable".into(), Value::Bool(true));
+                }
+                types.into_iter().next().unwrap_or(Value::Null)
+            }
+            ("properties", Value::Object(properties)) => Value::Object(
+                properties
+                    .into_iter()
+                    .map(|(name, schema)| (name, openapi_schema(schema)))
+                    .collect(),
+            ),
+            ("items", items) => openapi_schema(items),
+            ("anyOf", Value::Array(schemas)) => {
+                Value::Array(schemas.into_iter().map(openapi_schema).collect())
+            }
+            (_, value) => value,
+        };
+        converted.insert(key, value);
     }
+    Value::Object(converted)
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Candidate {
-    pub content: Content,
+/// A built-in tool, e.g. `{"googleSearch": {}}`, or function declarations.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiTool {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub google_search: Option<serde_json::Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub finish_reason: Option<String>,
+    pub code_execution: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function_declarations: Option<Vec<FunctionDeclaration>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionDeclaration {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// In Gemini's OpenAPI subset; omitted for functions without parameters.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<serde_json::Value>,
+}
+
+impl From<BuiltinTool> for GeminiTool {
+    fn from(tool: BuiltinTool) -> Self {
+        let enabled = Some(serde_json::json!({}));
+        match tool {
+            BuiltinTool::GoogleSearch => Self {
+                google_search: enabled,
+                ..Default::default()
+            },
+            BuiltinTool::CodeExecution => Self {
+                code_execution: enabled,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl GeminiTool {
+    /// The function tools of a request, declared together, and its built-in
+    /// tools.
+    pub fn all(
+        tools: Option<Vec<Tool>>,
+        builtin_tools: Option<Vec<BuiltinTool>>,
+    ) -> Option<Vec<Self>> {
+        let declarations: Vec<FunctionDeclaration> = tools
+            .unwrap_or_default()
+            .into_iter()
+            .map(|tool| FunctionDeclaration {
+                name: tool.function.name,
+                description: tool.function.description,
+                parameters: (!tool.function.parameters.is_null())
+                    .then(|| openapi_schema(tool.function.parameters)),
+            })
+            .collect();
+
+        let tools: Vec<Self> = (!declarations.is_empty())
+            .then(|| Self {
+                function_declarations: Some(declarations),
+                ..Default::default()
+            })
+            .into_iter()
+            .chain(
+                builtin_tools
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(Self::from),
+            )
+            .collect();
+        (!tools.is_empty()).then_some(tools)
+    }
+}
+
+/// Whether the model must call a function, from `tool_choice`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolConfig {
+    pub function_calling_config: FunctionCallingConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionCallingConfig {
+    /// `AUTO`, `ANY` or `NONE`.
+    pub mode: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_function_names: Option<Vec<String>>,
+}
+
+impl From<ToolChoice> for ToolConfig {
+    fn from(choice: ToolChoice) -> Self {
+        let (mode, allowed_function_names) = match choice {
+            ToolChoice::Mode(ToolChoiceMode::None) => ("NONE", None),
+            ToolChoice::Mode(ToolChoiceMode::Auto) => ("AUTO", None),
+            ToolChoice::Mode(ToolChoiceMode::Required) => ("ANY", None),
+            ToolChoice::Function { function, .. } => ("ANY", Some(vec![function.name])),
+        };
+        Self {
+            function_calling_config: FunctionCallingConfig {
+                mode: mode.to_string(),
+                allowed_function_names,
+            },
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct GenerateContentRequest {
     pub contents: Vec<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_instruction: Option<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<GeminiTool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_config: Option<ToolConfig>,
+    /// Cache holding the start of the conversation, which then has no
+    /// system instruction or tools of its own.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cached_content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub safety_settings: Option<Vec<SafetySetting>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation_config: Option<GenerationConfig>,
 }
 
 impl GenerateContentRequest {
-    pub fn new(prompt: &str) -> Self {
-        let part = Part {
-            text: prompt.to_string(),
-        };
-        let content = Content {
-            parts: vec![part],
-            role: Some("user".to_string()),
-        };
+    /// Translate a conversation; system messages become the system
+    /// instruction, tool calls `functionCall` parts and tool messages
+    /// `functionResponse` parts.
+    pub fn new(messages: Vec<Message>) -> Result<Self, ProviderError> {
+        let mut system = vec![];
+        let mut contents: Vec<Content> = vec![];
+        // Ids and names of the calls of the last assistant message, which
+        // tool messages answer.
+        let mut calls: Vec<(String, String)> = vec![];
 
-        Self {
-            contents: vec![content],
+        for message in messages {
+            if message.role == "system" {
+                system.push(Part::text(message.content.text()));
+                continue;
+            }
+
+            if message.role == "tool" {
+                let position = calls
+                    .iter()
+                    .position(|(id, _)| Some(id) == message.tool_call_id.as_ref())
+                    .or((!calls.is_empty()).then_some(0))
+                    .ok_or_else(|| {
+                        ProviderError::InvalidRequest(
+                            "tool messages must answer a tool call of the assistant".into(),
+                        )
+                    })?;
+                let (_, name) = calls.remove(position);
+                let text = message.content.text();
+                let response = match serde_json::from_str(&text) {
+                    Ok(serde_json::Value::Object(object)) => serde_json::Value::Object(object),
+                    _ => serde_json::json!({ "content": text }),
+                };
+                let part = Part {
+                    function_response: Some(FunctionResponse { name, response }),
+                    ..Default::default()
+                };
+
+                // The answers to the calls of a turn go together.
+                match contents.last_mut() {
+                    Some(content)
+                        if content
+                            .parts
+                            .iter()
+                            .all(|part| part.function_response.is_some()) =>
+                    {
+                        content.parts.push(part)
+                    }
+                    _ => contents.push(Content {
+                        parts: vec![part],
+                        role: Some("user".to_string()),
+                    }),
+                }
+                continue;
+            }
+
+            let role = match message.role.as_str() {
+                "assistant" => "model",
+                _ => "user",
+            };
+            let mut parts: Vec<Part> = message
+                .content
+                .into_parts()
+                .into_iter()
+                .map(Part::try_from)
+                .collect::<Result<_, _>>()?;
+            if let Some(tool_calls) = message.tool_calls {
+                parts.retain(|part| part.text.as_deref() != Some(""));
+                calls.clear();
+                for call in tool_calls {
+                    let args = serde_json::from_str(&call.function.arguments).map_err(|e| {
+                        ProviderError::InvalidRequest(format!(
+                            "arguments of a call to {} are not JSON: {}",
+                            call.function.name, e
+                        ))
+                    })?;
+                    calls.push((call.id, call.function.name.clone()));
+                    parts.push(Part {
+                        function_call: Some(GeminiFunctionCall {
+                            name: call.function.name,
+                            args,
+                        }),
+                        ..Default::default()
+                    });
+                }
+            }
+            contents.push(Content {
+                parts,
+                role: Some(role.to_string()),
+            });
         }
+
+        Ok(Self {
+            contents,
+            system_instruction: (!system.is_empty()).then_some(Content {
+                parts: system,
+                role: None,
+            }),
+            tools: None,
+            tool_config: None,
+            cached_content: None,
+            safety_settings: None,
+            generation_config: None,
+        })
     }
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct GenerateContentResponse {
+    /// Empty when the prompt was blocked.
+    #[serde(default)]
     pub(crate) candidates: Vec<ContentCandidate>,
-    pub(crate) model_version: String,
-    pub(crate) usage_metadata: UsageMetadata,
+    pub(crate) prompt_feedback: Option<PromptFeedback>,
+    pub(crate) usage_metadata: Option<UsageMetadata>,
+}
+
+/// Why the prompt was blocked, if it was.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptFeedback {
+    pub block_reason: Option<String>,
+    pub safety_ratings: Option<Vec<SafetyRating>>,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ContentCandidate {
     /// Output only. Generated content returned from the model.
+    #[serde(default)]
     pub content: Content,
     /// Optional. Output only. The reason why the model stopped generating tokens.
     /// If empty, the model has not stopped generating tokens.
@@ -101,14 +585,38 @@ pub struct ContentCandidate {
     /// This field may be populated with recitation information for any text included in the content.
     /// These are passages that are "recited" from copyrighted material in the foundational LLM's training data.
     pub citation_metadata: Option<CitationMetadata>,
-    /// Output only. Token count for this candidate.
-    pub token_count: Option<i32>,
-    /// Output only.
-    pub avg_logprobs: Option<f64>,
     /// Output only. Log-likelihood scores for the response tokens and top tokens
     pub logprobs_result: Option<LogprobsResult>,
     /// Output only. Index of the candidate in the list of response candidates.
     pub index: Option<i32>,
+    /// Output only. Sources of a grounded answer, with Google Search.
+    pub grounding_metadata: Option<serde_json::Value>,
+}
+
+/// What a candidate carries besides its text, returned as the `gemini`
+/// response extension.
+#[derive(Debug, Default, Serialize)]
+pub struct CandidateMetadata {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub citations: Vec<CitationSource>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub safety_ratings: Vec<SafetyRating>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grounding_metadata: Option<serde_json::Value>,
+    /// `executableCode` and `codeExecutionResult` parts, in order.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub code_execution: Vec<serde_json::Value>,
+}
+
+impl CandidateMetadata {
+    /// The response extension, if there is any metadata.
+    pub fn into_extensions(self) -> Option<serde_json::Value> {
+        let empty = self.citations.is_empty()
+            && self.safety_ratings.is_empty()
+            && self.grounding_metadata.is_none()
+            && self.code_execution.is_empty();
+        (!empty).then(|| serde_json::json!({ "gemini": self }))
+    }
 }
 
 /// Gemini Generate Content Response
@@ -116,6 +624,7 @@ pub struct ContentCandidate {
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum FinishReason {
     /// Default value. This value is unused.
+    #[serde(rename = "FINISH_REASON_UNSPECIFIED")]
     Unspecified,
     /// Natural stop point of the model or provided stop sequence.
     Stop,
@@ -127,8 +636,6 @@ pub enum FinishReason {
     Recitation,
     /// The response candidate content was flagged for using an unsupported language.
     Language,
-    /// Unknown reason.
-    Other,
     /// Token generation stopped because the content contains forbidden terms.
     Blocklist,
     /// Token generation stopped for potentially containing prohibited content.
@@ -137,122 +644,727 @@ pub enum FinishReason {
     Spii,
     /// The function call generated by the model is invalid.
     MalformedFunctionCall,
+    /// Unknown reason.
+    #[serde(other)]
+    Other,
+}
+
+impl FinishReason {
+    /// Whether generation stopped because the content was blocked.
+    pub fn is_blocked(&self) -> bool {
+        matches!(
+            self,
+            Self::Safety
+                | Self::Recitation
+                | Self::Blocklist
+                | Self::ProhibitedContent
+                | Self::Spii
+        )
+    }
+
+    /// The OpenAI-style finish reason.
+    pub fn openai(&self) -> &'static str {
+        match self {
+            Self::MaxTokens => "length",
+            reason if reason.is_blocked() => "content_filter",
+            _ => "stop",
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct SafetyRating {
     pub category: HarmCategory,
     pub probability: HarmProbability,
+    /// Whether this rating caused the content to be blocked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blocked: Option<bool>,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CitationMetadata {
+    /// `citations` on Vertex AI.
+    #[serde(default, alias = "citations")]
     pub citation_sources: Vec<CitationSource>,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
 pub struct CitationSource {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub start_index: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub end_index: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub license: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LogProbCandidate {
+    #[serde(default)]
     pub token: String,
-    pub token_id: String,
+    pub token_id: Option<i64>,
+    #[serde(default)]
     pub log_probability: f64,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LogprobsResult {
-    pub top_candidate: Vec<TopCandidate>,
-    pub chosen_candidate: Vec<LogProbCandidate>,
+    /// The most likely tokens at each position.
+    #[serde(default)]
+    pub top_candidates: Vec<TopCandidate>,
+    /// The chosen token at each position.
+    #[serde(default)]
+    pub chosen_candidates: Vec<LogProbCandidate>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct TopCandidate {
+    #[serde(default)]
     pub candidates: Vec<LogProbCandidate>,
 }
 
+impl From<LogprobsResult> for Logprobs {
+    fn from(result: LogprobsResult) -> Self {
+        let mut top_candidates = result.top_candidates.into_iter();
+        let content = result
+            .chosen_candidates
+            .into_iter()
+            .map(|chosen| TokenLogprob {
+                bytes: Some(chosen.token.as_bytes().to_vec()),
+                top_logprobs: top_candidates
+                    .next()
+                    .map(|top| {
+                        top.candidates
+                            .into_iter()
+                            .map(|candidate| TopLogprob {
+                                bytes: Some(candidate.token.as_bytes().to_vec()),
+                                token: candidate.token,
+                                logprob: candidate.log_probability,
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                token: chosen.token,
+                logprob: chosen.log_probability,
+            })
+            .collect();
+
+        Self { content }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UsageMetadata {
-    candidates_token_count: Option<usize>,
-    prompt_token_count: Option<usize>,
-    total_token_count: Option<usize>,
+    candidates_token_count: Option<u32>,
+    prompt_token_count: Option<u32>,
+    /// Part of `prompt_token_count` read from a context cache.
+    cached_content_token_count: Option<u32>,
+}
+
+impl From<UsageMetadata> for Usage {
+    fn from(usage: UsageMetadata) -> Self {
+        let mut unified = Usage::new(
+            usage.prompt_token_count.unwrap_or_default(),
+            usage.candidates_token_count.unwrap_or_default(),
+        );
+        unified.cached_tokens = usage.cached_content_token_count;
+        unified
+    }
+}
+
+/// A `cachedContents` resource.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedContent {
+    /// Output only, e.g. `cachedContents/abc123`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Model resource, e.g. `models/gemini-2.0-flash-001`.
+    #[serde(default)]
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub contents: Vec<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_instruction: Option<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<GeminiTool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_config: Option<ToolConfig>,
+    /// Input only, e.g. `3600s`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expire_time: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage_metadata: Option<CacheUsageMetadata>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheUsageMetadata {
+    pub total_token_count: Option<u32>,
+}
+
+impl From<CachedContent> for ContextCache {
+    fn from(cache: CachedContent) -> Self {
+        ContextCache {
+            name: cache.name.unwrap_or_default(),
+            // Only the model ID, as in chat requests.
+            model: match cache.model.rsplit_once('/') {
+                Some((_, model)) => model.to_string(),
+                None => cache.model,
+            },
+            display_name: cache.display_name,
+            expire_time: cache.expire_time,
+            token_count: cache
+                .usage_metadata
+                .and_then(|usage| usage.total_token_count),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListCachedContentsResponse {
+    #[serde(default)]
+    pub cached_contents: Vec<CachedContent>,
+    pub next_page_token: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum HarmCategory {
-    Unspecified,
+    #[serde(rename = "HARM_CATEGORY_DEROGATORY")]
     Derogatory,
+    #[serde(rename = "HARM_CATEGORY_TOXICITY")]
     Toxicity,
+    #[serde(rename = "HARM_CATEGORY_VIOLENCE")]
     Violence,
+    #[serde(rename = "HARM_CATEGORY_SEXUAL")]
     Sexually,
+    #[serde(rename = "HARM_CATEGORY_MEDICAL")]
     Medical,
+    #[serde(rename = "HARM_CATEGORY_DANGEROUS")]
     Dangerous,
+    #[serde(rename = "HARM_CATEGORY_HARASSMENT")]
     Harassment,
+    #[serde(rename = "HARM_CATEGORY_HATE_SPEECH")]
     HateSpeech,
+    #[serde(rename = "HARM_CATEGORY_SEXUALLY_EXPLICIT")]
     SexuallyExplicit,
+    #[serde(rename = "HARM_CATEGORY_DANGEROUS_CONTENT")]
     DangerousContent,
+    #[serde(rename = "HARM_CATEGORY_CIVIC_INTEGRITY")]
     CivicIntegrity,
+    /// Also used for values unknown to topkio.
+    #[serde(rename = "HARM_CATEGORY_UNSPECIFIED", other)]
+    Unspecified,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum HarmProbability {
-    Unspecified,
     Negligible,
     Low,
     Medium,
     High,
+    /// Also used for values unknown to topkio.
+    #[serde(rename = "HARM_PROBABILITY_UNSPECIFIED", other)]
+    Unspecified,
+}
+
+/// A model of `models.list`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiModel {
+    /// e.g. `models/gemini-2.0-flash`.
+    pub name: String,
+    pub input_token_limit: Option<u32>,
+    pub output_token_limit: Option<u32>,
+    #[serde(default)]
+    pub supported_generation_methods: Vec<String>,
+}
+
+impl From<GeminiModel> for ModelInfo {
+    fn from(model: GeminiModel) -> Self {
+        ModelInfo {
+            id: match model.name.strip_prefix("models/") {
+                Some(id) => id.to_string(),
+                None => model.name,
+            },
+            input_token_limit: model.input_token_limit,
+            output_token_limit: model.output_token_limit,
+            generation_methods: model.supported_generation_methods,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListModelsResponse {
+    #[serde(default)]
+    pub models: Vec<GeminiModel>,
+    pub next_page_token: Option<String>,
 }
 
-#[derive(Debug)]
-pub struct CompletionResponse<T> {
-    /// The completion choice returned by the completion model provider
-    pub choice: ModelChoice,
-    /// The raw response returned by the completion model provider
-    pub raw_response: T,
+/// Body of `countTokens`: the Gemini API takes a whole
+/// `generateContentRequest`, Vertex AI its fields.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CountTokensRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generate_content_request: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub contents: Vec<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_instruction: Option<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<GeminiTool>>,
 }
 
-#[derive(Debug)]
-pub enum ModelChoice {
-    /// Represents a completion response as a message
-    Message(String),
-    /// Represents a completion response as a tool call of the form
-    /// `ToolCall(function_name, function_params)`.
-    ToolCall(String, serde_json::Value),
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CountTokensResponse {
+    #[serde(default)]
+    pub total_tokens: u32,
 }
 
-impl TryFrom<GenerateContentResponse> for CompletionResponse<GenerateContentResponse> {
-    type Error = String;
+impl TryFrom<GenerateContentResponse> for ChatCompletionResponse {
+    type Error = ProviderError;
 
+    /// Every candidate becomes a choice. Blocked candidates are kept empty
+    /// with a `content_filter` finish reason, unless all of them were blocked.
     fn try_from(response: GenerateContentResponse) -> Result<Self, Self::Error> {
-        match response.candidates.as_slice() {
-            [ContentCandidate { content, .. }, ..] => Ok(CompletionResponse {
-                choice: match content.parts.first().unwrap() {
-                    Part { text } => ModelChoice::Message(text.clone()),
-                    Part { .. } => {
-                        // let args_value = serde_json::Value::Object(
-                        //     function_call.args.clone().unwrap_or_default(),
-                        // );
-                        // ModelChoice::ToolCall(function_call.name.clone(), args_value)
-
-                        unimplemented!("Tool call not implemented yet");
-                    }
-                    _ => return Err("Unsupported response by the model of type ".into()),
-                },
-                raw_response: response,
-            }),
-            _ => Err("No candidates found in response".into()),
+        if let Some(PromptFeedback {
+            block_reason: Some(reason),
+            safety_ratings,
+        }) = &response.prompt_feedback
+        {
+            return Err(content_filtered(
+                format!("prompt blocked by Gemini ({})", reason),
+                Some(reason),
+                safety_ratings,
+            ));
+        }
+
+        let Some(candidate) = response.candidates.first() else {
+            return Err(ProviderError::Upstream(
+                "No candidates found in response".into(),
+            ));
+        };
+
+        if response
+            .candidates
+            .iter()
+            .all(|candidate| candidate.content.parts.is_empty())
+        {
+            return match &candidate.finish_reason {
+                Some(reason) if reason.is_blocked() => Err(content_filtered(
+                    format!("response blocked by Gemini ({:?})", reason),
+                    None,
+                    &candidate.safety_ratings,
+                )),
+                reason => Err(ProviderError::Upstream(format!(
+                    "empty candidate (finish reason {:?})",
+                    reason
+                ))),
+            };
+        }
+
+        let choices = response
+            .candidates
+            .into_iter()
+            .enumerate()
+            .map(|(position, mut candidate)| {
+                let text: String = candidate
+                    .content
+                    .parts
+                    .iter()
+                    .filter_map(|part| part.text.as_deref())
+                    .collect();
+                let tool_calls: Vec<ToolCall> = candidate
+                    .content
+                    .parts
+                    .iter_mut()
+                    .filter_map(|part| part.function_call.take())
+                    .enumerate()
+                    .map(|(n, call)| ToolCall {
+                        id: format!("call_{}", n),
+                        kind: "function".into(),
+                        function: FunctionCall {
+                            name: call.name,
+                            arguments: call.args.to_string(),
+                        },
+                    })
+                    .collect();
+                let metadata = CandidateMetadata {
+                    citations: candidate
+                        .citation_metadata
+                        .map(|metadata| metadata.citation_sources)
+                        .unwrap_or_default(),
+                    safety_ratings: candidate.safety_ratings.unwrap_or_default(),
+                    grounding_metadata: candidate.grounding_metadata,
+                    code_execution: candidate
+                        .content
+                        .parts
+                        .into_iter()
+                        .filter_map(|part| {
+                            let (key, value) =
+                                match (part.executable_code, part.code_execution_result) {
+                                    (Some(code), _) => ("executable_code", code),
+                                    (_, Some(result)) => ("code_execution_result", result),
+                                    _ => return None,
+                                };
+                            Some(serde_json::json!({ key: value }))
+                        })
+                        .collect(),
+                };
+
+                // Gemini reports `STOP` when the model calls functions.
+                let finish_reason = match tool_calls.is_empty() {
+                    true => candidate
+                        .finish_reason
+                        .as_ref()
+                        .map(|reason| reason.openai().to_string()),
+                    false => Some("tool_calls".to_string()),
+                };
+                let mut message = Message::new("assistant", text);
+                message.tool_calls = (!tool_calls.is_empty()).then_some(tool_calls);
+
+                Choice {
+                    index: candidate
+                        .index
+                        .map_or(position as u32, |index| index as u32),
+                    message,
+                    finish_reason,
+                    logprobs: candidate.logprobs_result.map(Logprobs::from),
+                    extensions: metadata.into_extensions(),
+                }
+            })
+            .collect();
+
+        ChatCompletionResponse::from_choices(choices, response.usage_metadata.map(Usage::from))
+            .ok_or_else(|| ProviderError::Upstream("No candidates found in response".into()))
+    }
+}
+
+fn content_filtered(
+    message: String,
+    block_reason: Option<&str>,
+    safety_ratings: &Option<Vec<SafetyRating>>,
+) -> ProviderError {
+    ProviderError::ContentFiltered {
+        message,
+        details: Some(serde_json::json!({
+            "block_reason": block_reason,
+            "safety_ratings": safety_ratings.as_deref().unwrap_or_default(),
+        })),
+    }
+}
+
+/// Body of `batchEmbedContents` (Gemini API).
+#[derive(Debug, Serialize)]
+pub struct BatchEmbedContentsRequest {
+    pub requests: Vec<EmbedContentRequest>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmbedContentRequest {
+    /// Model resource, e.g. `models/text-embedding-004`.
+    pub model: String,
+    pub content: Content,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchEmbedContentsResponse {
+    #[serde(default)]
+    pub embeddings: Vec<ContentEmbedding>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ContentEmbedding {
+    #[serde(default)]
+    pub values: Vec<f32>,
+}
+
+/// Body of `predict` for embedding models on Vertex AI.
+#[derive(Debug, Serialize)]
+pub struct PredictEmbeddingsRequest {
+    pub instances: Vec<EmbeddingInstance>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmbeddingInstance {
+    pub content: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PredictEmbeddingsResponse {
+    #[serde(default)]
+    pub predictions: Vec<EmbeddingPrediction>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmbeddingPrediction {
+    pub embeddings: VertexEmbedding,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VertexEmbedding {
+    #[serde(default)]
+    pub values: Vec<f32>,
+    pub statistics: Option<EmbeddingStatistics>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmbeddingStatistics {
+    #[serde(default)]
+    pub token_count: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(json: serde_json::Value) -> Result<ChatCompletionResponse, ProviderError> {
+        let response: GenerateContentResponse = serde_json::from_value(json).unwrap();
+        response.try_into()
+    }
+
+    #[test]
+    fn blocked_prompt_is_content_filtered() {
+        let response = serde_json::json!({
+            "promptFeedback": {
+                "blockReason": "SAFETY",
+                "safetyRatings": [
+                    {"category": "HARM_CATEGORY_HARASSMENT", "probability": "NEGLIGIBLE"},
+                    {"category": "HARM_CATEGORY_DANGEROUS_CONTENT", "probability": "HIGH", "blocked": true}
+                ]
+            },
+            "usageMetadata": {"promptTokenCount": 12, "totalTokenCount": 12},
+            "modelVersion": "gemini-2.0-flash"
+        });
+
+        let Err(ProviderError::ContentFiltered { message, details }) = convert(response) else {
+            panic!("expected a content filter error");
+        };
+        assert!(message.contains("SAFETY"), "{}", message);
+        let details = details.unwrap();
+        assert_eq!(details["block_reason"], "SAFETY");
+        assert_eq!(
+            details["safety_ratings"][1]["category"],
+            "HARM_CATEGORY_DANGEROUS_CONTENT"
+        );
+        assert_eq!(details["safety_ratings"][1]["blocked"], true);
+    }
+
+    #[test]
+    fn safety_candidate_without_parts_is_content_filtered() {
+        let response = serde_json::json!({
+            "candidates": [{
+                "finishReason": "SAFETY",
+                "index": 0,
+                "safetyRatings": [
+                    {"category": "HARM_CATEGORY_SEXUALLY_EXPLICIT", "probability": "HIGH", "blocked": true}
+                ]
+            }],
+            "usageMetadata": {"promptTokenCount": 8, "totalTokenCount": 8},
+            "modelVersion": "gemini-1.5-flash-002"
+        });
+
+        let Err(ProviderError::ContentFiltered { details, .. }) = convert(response) else {
+            panic!("expected a content filter error");
+        };
+        let details = details.unwrap();
+        assert_eq!(details["block_reason"], serde_json::Value::Null);
+        assert_eq!(details["safety_ratings"][0]["probability"], "HIGH");
+    }
+
+    #[test]
+    fn blocked_candidate_among_others_is_kept_empty() {
+        let response = serde_json::json!({
+            "candidates": [
+                {"content": {"parts": [{"text": "Hello"}], "role": "model"}, "finishReason": "STOP", "index": 0},
+                {"content": {"role": "model"}, "finishReason": "RECITATION", "index": 1}
+            ],
+            "usageMetadata": {"promptTokenCount": 4, "candidatesTokenCount": 1, "totalTokenCount": 5}
+        });
+
+        let response = convert(response).unwrap();
+        assert_eq!(response.choices.len(), 2);
+        assert_eq!(response.message.content.text(), "Hello");
+        assert_eq!(response.choices[1].message.content.text(), "");
+        assert_eq!(
+            response.choices[1].finish_reason.as_deref(),
+            Some("content_filter")
+        );
+    }
+
+    #[test]
+    fn unknown_enum_values_are_accepted() {
+        let response = serde_json::json!({
+            "candidates": [{
+                "content": {"parts": [{"text": "A cat."}], "role": "model"},
+                "finishReason": "SOME_FUTURE_REASON",
+                "safetyRatings": [
+                    {"category": "HARM_CATEGORY_SOMETHING_NEW", "probability": "VERY_HIGH"}
+                ],
+                "index": 0
+            }],
+            "usageMetadata": {"promptTokenCount": 4, "candidatesTokenCount": 3, "totalTokenCount": 7}
+        });
+
+        let response: GenerateContentResponse = serde_json::from_value(response).unwrap();
+        let candidate = &response.candidates[0];
+        assert!(matches!(candidate.finish_reason, Some(FinishReason::Other)));
+        let rating = &candidate.safety_ratings.as_ref().unwrap()[0];
+        assert!(matches!(rating.category, HarmCategory::Unspecified));
+        assert!(matches!(rating.probability, HarmProbability::Unspecified));
+
+        let response = ChatCompletionResponse::try_from(response).unwrap();
+        assert_eq!(response.message.content.text(), "A cat.");
+        assert_eq!(response.finish_reason.as_deref(), Some("stop"));
+        assert_eq!(response.usage.unwrap().completion_tokens, 3);
+    }
+
+    fn part(json: serde_json::Value) -> Result<Part, ProviderError> {
+        serde_json::from_value::<ContentPart>(json)
+            .unwrap()
+            .try_into()
+    }
+
+    #[test]
+    fn converts_content_parts() {
+        let image = part(serde_json::json!({
+            "type": "image_url",
+            "image_url": {"url": "data:image/png;base64,iVBORw0KGgo="}
+        }))
+        .unwrap();
+        let blob = image.inline_data.unwrap();
+        assert_eq!(
+            (blob.mime_type.as_str(), blob.data.as_str()),
+            ("image/png", "iVBORw0KGgo=")
+        );
+
+        let stored = part(serde_json::json!({
+            "type": "image_url",
+            "image_url": {"url": "gs://bucket/cat.JPG"}
+        }))
+        .unwrap();
+        let file = stored.file_data.unwrap();
+        assert_eq!(file.mime_type.as_deref(), Some("image/jpeg"));
+        assert_eq!(file.file_uri, "gs://bucket/cat.JPG");
+
+        let uploaded = part(serde_json::json!({
+            "type": "file",
+            "file": {"file_id": "https://generativelanguage.googleapis.com/v1beta/files/abc", "filename": "report.pdf"}
+        }))
+        .unwrap();
+        assert_eq!(
+            uploaded.file_data.unwrap().mime_type.as_deref(),
+            Some("application/pdf")
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_content_parts() {
+        let rejected = [
+            // Web URLs are not fetched by Gemini.
+            serde_json::json!({"type": "image_url", "image_url": {"url": "https://example.com/cat.png"}}),
+            // The MIME type cannot be told from the URI.
+            serde_json::json!({"type": "image_url", "image_url": {"url": "gs://bucket/cat"}}),
+            serde_json::json!({"type": "file", "file": {"file_id": "files/abc"}}),
+            // Neither a file id nor inline data.
+            serde_json::json!({"type": "file", "file": {"filename": "report.pdf"}}),
+            serde_json::json!({"type": "file", "file": {"file_data": "not a data URL"}}),
+        ];
+        for json in rejected {
+            assert!(
+                matches!(part(json.clone()), Err(ProviderError::InvalidRequest(_))),
+                "{}",
+                json
+            );
         }
     }
+
+    #[test]
+    fn empty_candidate_without_block_is_an_upstream_error() {
+        let response = serde_json::json!({
+            "candidates": [{"content": {"role": "model"}, "finishReason": "MAX_TOKENS"}]
+        });
+        assert!(matches!(convert(response), Err(ProviderError::Upstream(_))));
+    }
+
+    #[test]
+    fn generation_config_of_sampling_options() {
+        let request = |extra: serde_json::Value| -> ChatCompletionRequest {
+            let mut request = serde_json::json!({
+                "model": "gemini:gemini-2.0-flash",
+                "messages": [{"role": "user", "content": "Hi"}]
+            });
+            request
+                .as_object_mut()
+                .unwrap()
+                .extend(extra.as_object().unwrap().clone());
+            serde_json::from_value(request).unwrap()
+        };
+        assert!(GenerationConfig::new(&request(serde_json::json!({}))).is_none());
+
+        let config = GenerationConfig::new(&request(serde_json::json!({
+            "max_tokens": 100, "temperature": 0.3, "stop": "END"
+        })))
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(config).unwrap(),
+            serde_json::json!({"temperature": 0.3f32, "maxOutputTokens": 100, "stopSequences": ["END"]})
+        );
+    }
+
+    #[test]
+    fn tool_calls_and_results_become_function_parts() {
+        let messages: Vec<Message> = serde_json::from_value(serde_json::json!([
+            {"role": "user", "content": "Weather in Paris and Rome?"},
+            {"role": "assistant", "content": "", "tool_calls": [
+                {"id": "call_a", "type": "function", "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}},
+                {"id": "call_b", "type": "function", "function": {"name": "get_time", "arguments": "{}"}}
+            ]},
+            {"role": "tool", "tool_call_id": "call_b", "content": "12:00"},
+            {"role": "tool", "tool_call_id": "call_a", "content": "{\"temperature\": 21}"}
+        ]))
+        .unwrap();
+        let request = GenerateContentRequest::new(messages).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&request.contents[1..]).unwrap(),
+            serde_json::json!([
+                {"role": "model", "parts": [
+                    {"functionCall": {"name": "get_weather", "args": {"city": "Paris"}}},
+                    {"functionCall": {"name": "get_time", "args": {}}}
+                ]},
+                {"role": "user", "parts": [
+                    {"functionResponse": {"name": "get_time", "response": {"content": "12:00"}}},
+                    {"functionResponse": {"name": "get_weather", "response": {"temperature": 21}}}
+                ]}
+            ])
+        );
+    }
+
+    #[test]
+    fn tool_results_without_a_call_are_rejected() {
+        let messages = vec![Message::new("user", "Hi"), Message::new("tool", "12:00")];
+        assert!(matches!(
+            GenerateContentRequest::new(messages),
+            Err(ProviderError::InvalidRequest(_))
+        ));
+    }
 }
@@ -1,3 +1,6 @@
 mod gemini;
 
-pub use gemini::api::GeminiBackend;
+pub use gemini::{
+    api::GeminiBackend,
+    auth::{GeminiAuth, ServiceAccount, ServiceAccountKey},
+};
@@ -0,0 +1,126 @@
+//! Chat completions against a mock Gemini server.
+
+use {
+    axum::{extract::Path, routing::post, Json, Router},
+    std::sync::{Arc, Mutex},
+    topkio_google::GeminiBackend,
+    topkio_primitive::api::{ChatCompletionRequest, Message, UnifiedLlmApi},
+};
+
+/// The methods called (e.g. `gemini-2.0-flash:generateContent`) and the
+/// bodies sent, in order.
+type Calls = Arc<Mutex<Vec<(String, serde_json::Value)>>>;
+
+async fn serve(answer: serde_json::Value) -> (String, Calls) {
+    let calls = Calls::default();
+    let recorded = calls.clone();
+    let generate = move |Path(method): Path<String>, Json(body): Json<serde_json::Value>| {
+        recorded.lock().unwrap().push((method, body));
+        let answer = answer.clone();
+        async move { Json(answer) }
+    };
+
+    let app = Router::new().route("/models/{method}", post(generate));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/models", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (url, calls)
+}
+
+fn text_answer(text: &str) -> serde_json::Value {
+    serde_json::json!({
+        "candidates": [{
+            "content": {"role": "model", "parts": [{"text": text}]},
+            "finishReason": "STOP"
+        }],
+        "usageMetadata": {"promptTokenCount": 3, "candidatesTokenCount": 1}
+    })
+}
+
+#[tokio::test]
+async fn streamed_requests_are_answered_in_one_piece() {
+    let (url, calls) = serve(text_answer("Hi")).await;
+    let backend = GeminiBackend::new(url, "key".into());
+
+    let request = ChatCompletionRequest {
+        model: "gemini:gemini-2.0-flash".into(),
+        messages: vec![Message::new("user", "Hello")],
+        stream: Some(true),
+        ..Default::default()
+    };
+    let response = backend
+        .chat_completion("gemini-2.0-flash", request)
+        .await
+        .unwrap();
+
+    assert_eq!(response.message.content.text(), "Hi");
+    let calls = calls.lock().unwrap();
+    assert_eq!(calls[0].0, "gemini-2.0-flash:generateContent");
+}
+
+#[tokio::test]
+async fn function_tools_are_declared_and_called() {
+    let (url, calls) = serve(serde_json::json!({
+        "candidates": [{
+            "content": {"role": "model", "parts": [
+                {"functionCall": {"name": "get_weather", "args": {"city": "Paris"}}}
+            ]},
+            "finishReason": "STOP"
+        }]
+    }))
+    .await;
+    let backend = GeminiBackend::new(url, "key".into());
+
+    let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+        "model": "gemini:gemini-2.0-flash",
+        "messages": [{"role": "user", "content": "Weather in Paris?"}],
+        "tools": [{
+            "type": "function",
+            "function": {
+                "name": "get_weather",
+                "description": "Current weather",
+                "parameters": {
+                    "type": "object",
+                    "properties": {"city": {"type": "string"}},
+                    "required": ["city"],
+                    "additionalProperties": false
+                }
+            }
+        }],
+        "tool_choice": {"type": "function", "function": {"name": "get_weather"}}
+    }))
+    .unwrap();
+    let response = backend
+        .chat_completion("gemini-2.0-flash", request)
+        .await
+        .unwrap();
+
+    let calls = calls.lock().unwrap();
+    let body = &calls[0].1;
+    assert_eq!(
+        body["tools"],
+        serde_json::json!([{"functionDeclarations": [{
+            "name": "get_weather",
+            "description": "Current weather",
+            "parameters": {
+                "type": "object",
+                "properties": {"city": {"type": "string"}},
+                "required": ["city"]
+            }
+        }]}])
+    );
+    assert_eq!(
+        body["toolConfig"],
+        serde_json::json!({"functionCallingConfig": {
+            "mode": "ANY", "allowedFunctionNames": ["get_weather"]
+        }})
+    );
+
+    assert_eq!(response.finish_reason.as_deref(), Some("tool_calls"));
+    let tool_calls = response.message.tool_calls.unwrap();
+    assert_eq!(tool_calls[0].function.name, "get_weather");
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&tool_calls[0].function.arguments).unwrap(),
+        serde_json::json!({"city": "Paris"})
+    );
+}
@@ -1,2 +1,6 @@
 pub mod api;
 pub mod chat_completion;
+pub mod embed;
+pub mod generate;
+pub mod models;
+pub mod primitive;
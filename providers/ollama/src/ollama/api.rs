@@ -1,15 +1,97 @@
 use {
-    crate::ollama::chat_completion::chat_completion,
-    topkio_primitive::api::{ChatCompletionResponse, Message, UnifiedLlmApi},
+    crate::ollama::{
+        chat_completion::{chat_completion, chat_completion_stream},
+        embed::embed,
+        generate::generate,
+        models,
+    },
+    futures_util::{
+        future::{BoxFuture, Shared},
+        stream::BoxStream,
+        FutureExt, StreamExt,
+    },
+    std::{collections::HashMap, future::Future, sync::Mutex},
+    topkio_primitive::{
+        api::{
+            ChatCompletionDelta, ChatCompletionRequest, ChatCompletionResponse, CompletionRequest,
+            CompletionResponse, EmbeddingResponse, ModelDetails, ModelManager, PullProgress,
+            RunningModel, UnifiedLlmApi,
+        },
+        error::ProviderError,
+    },
 };
 
+type Pull = Shared<BoxFuture<'static, Result<(), ProviderError>>>;
+
 pub struct OllamaBackend {
     base_url: String,
+    /// Models pulled on their first request when the server lacks them.
+    auto_pull: Vec<String>,
+    /// Pulls in progress by model, shared by the requests waiting for them.
+    pulls: Mutex<HashMap<String, Pull>>,
 }
 
 impl OllamaBackend {
     pub fn new(base_url: String) -> Self {
-        Self { base_url }
+        Self {
+            base_url,
+            auto_pull: vec![],
+            pulls: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Pull `models` on their first request when the server does not have
+    /// them. Other models are never pulled.
+    pub fn with_auto_pull(mut self, models: Vec<String>) -> Self {
+        self.auto_pull = models;
+        self
+    }
+
+    fn may_pull(&self, model: &str) -> bool {
+        let name = |model: &str| model.strip_suffix(":latest").unwrap_or(model).to_string();
+        self.auto_pull
+            .iter()
+            .any(|allowed| name(allowed) == name(model))
+    }
+
+    /// Pull `model`, or wait for the pull another request started.
+    async fn pull(&self, model: &str) -> Result<(), ProviderError> {
+        let pull = {
+            let mut pulls = self.pulls.lock().expect("pulls lock");
+            let pull = pulls.entry(model.to_string()).or_insert_with(|| {
+                let (base_url, model) = (self.base_url.clone(), model.to_string());
+                async move { models::pull_to_end(&base_url, &model).await }
+                    .boxed()
+                    .shared()
+            });
+            pull.clone()
+        };
+        let result = pull.await;
+        self.pulls.lock().expect("pulls lock").remove(model);
+        result
+    }
+
+    /// Send `request` with `call`, and again after pulling `model` when the
+    /// server does not have it and auto-pull is on.
+    async fn pulling<R, T, F, Fut>(
+        &self,
+        model: &str,
+        request: R,
+        call: F,
+    ) -> Result<T, ProviderError>
+    where
+        R: Clone,
+        F: Fn(R) -> Fut,
+        Fut: Future<Output = Result<T, ProviderError>>,
+    {
+        let retry = self.may_pull(model).then(|| request.clone());
+        match (call(request).await, retry) {
+            (Err(ProviderError::NotFound(_)), Some(request)) => {
+                self.pull(model).await?;
+                call(request).await
+            }
+            (response, _) => response,
+        }
     }
 }
 
@@ -18,18 +100,96 @@ impl UnifiedLlmApi for OllamaBackend {
     async fn chat_completion(
         &self,
         model: &str,
-        messages: Vec<Message>,
-        stream: Option<bool>,
+        request: ChatCompletionRequest,
     ) -> Result<ChatCompletionResponse, anyhow::Error> {
-        let response = chat_completion(&self.base_url, model, messages, stream).await?;
+        let response = self
+            .pulling(model, request, |request| {
+                chat_completion(&self.base_url, model, request)
+            })
+            .await?;
 
         Ok(response)
     }
 
+    async fn chat_completion_stream(
+        &self,
+        model: &str,
+        request: ChatCompletionRequest,
+    ) -> Result<BoxStream<'static, Result<ChatCompletionDelta, anyhow::Error>>, anyhow::Error> {
+        let chunks = self
+            .pulling(model, request, |request| {
+                chat_completion_stream(&self.base_url, model, request)
+            })
+            .await?;
+
+        Ok(chunks
+            .map(|chunk| chunk.map_err(anyhow::Error::from))
+            .boxed())
+    }
+
     async fn health_check(&self) -> Result<(), anyhow::Error> {
         reqwest::get(&format!("{}/api/version", self.base_url))
             .await?
             .error_for_status()?;
         Ok(())
     }
+
+    async fn completion(
+        &self,
+        model: &str,
+        request: CompletionRequest,
+    ) -> Result<CompletionResponse, anyhow::Error> {
+        let response = self
+            .pulling(model, request, |request| {
+                generate(&self.base_url, model, request)
+            })
+            .await?;
+
+        Ok(response)
+    }
+
+    async fn embed(
+        &self,
+        model: &str,
+        input: Vec<String>,
+    ) -> Result<EmbeddingResponse, anyhow::Error> {
+        let response = self
+            .pulling(model, input, |input| embed(&self.base_url, model, input))
+            .await?;
+
+        Ok(response)
+    }
+
+    fn model_manager(&self) -> Option<&dyn ModelManager> {
+        Some(self)
+    }
+}
+
+#[async_trait::async_trait]
+impl ModelManager for OllamaBackend {
+    async fn pull_model(
+        &self,
+        model: &str,
+    ) -> Result<BoxStream<'static, Result<PullProgress, anyhow::Error>>, anyhow::Error> {
+        let progress = models::pull(&self.base_url, model).await?;
+        Ok(progress
+            .map(|step| step.map_err(anyhow::Error::from))
+            .boxed())
+    }
+
+    async fn delete_model(&self, model: &str) -> Result<(), anyhow::Error> {
+        Ok(models::delete(&self.base_url, model).await?)
+    }
+
+    async fn show_model(&self, model: &str) -> Result<ModelDetails, anyhow::Error> {
+        Ok(models::show(&self.base_url, model).await?)
+    }
+
+    async fn running_models(&self) -> Result<Vec<RunningModel>, anyhow::Error> {
+        Ok(models::running(&self.base_url).await?)
+    }
+
+    async fn keep_alive(&self, model: &str, keep_alive: &str) -> Result<(), anyhow::Error> {
+        Ok(models::keep_alive(&self.base_url, model, keep_alive).await?)
+    }
 }
@@ -1,30 +1,84 @@
-use topkio_primitive::api::{ChatCompletionRequest, ChatCompletionResponse, Message};
+use {
+    crate::ollama::{
+        models::ndjson,
+        primitive::{ModelCapabilities, OllamaChatRequest, OllamaChatResponse},
+    },
+    futures_util::{stream::BoxStream, StreamExt},
+    topkio_primitive::{
+        api::{ChatCompletionDelta, ChatCompletionRequest, ChatCompletionResponse},
+        error::ProviderError,
+    },
+};
 
 pub async fn chat_completion(
     base_url: &str,
     model: &str,
-    messages: Vec<Message>,
-    stream: Option<bool>,
-) -> Result<ChatCompletionResponse, reqwest::Error> {
-    println!(
-        "Sending request to {} with model {} and messages {:#?}",
-        base_url, model, messages
-    );
+    request: ChatCompletionRequest,
+) -> Result<ChatCompletionResponse, ProviderError> {
+    let response = post_chat(base_url, model, request, false).await?;
+    let response = response.json::<OllamaChatResponse>().await?;
+
+    Ok(response.into())
+}
+
+/// Stream the answer, from Ollama's newline-delimited chunks.
+pub async fn chat_completion_stream(
+    base_url: &str,
+    model: &str,
+    request: ChatCompletionRequest,
+) -> Result<BoxStream<'static, Result<ChatCompletionDelta, ProviderError>>, ProviderError> {
+    let response = post_chat(base_url, model, request, true).await?;
+
+    Ok(ndjson::<OllamaChatResponse>(response)
+        .map(|chunk| chunk.map(ChatCompletionDelta::from))
+        .boxed())
+}
+
+/// Post `request` to `/api/chat`, checking first that the model accepts its
+/// images.
+async fn post_chat(
+    base_url: &str,
+    model: &str,
+    request: ChatCompletionRequest,
+    stream: bool,
+) -> Result<reqwest::Response, ProviderError> {
+    // Messages are not printed: they may carry base64 images.
+    println!("Sending request to {} with model {}", base_url, model);
+
+    let mut body = OllamaChatRequest::new(model, request)?;
+    body.stream = Some(stream);
+    if body.has_images() && !supports_vision(base_url, model).await {
+        return Err(ProviderError::InvalidRequest(format!(
+            "Ollama model {} does not accept image input",
+            model
+        )));
+    }
 
     let response = reqwest::Client::new()
         .post(format!("{}/api/chat", base_url))
-        .json(&ChatCompletionRequest {
-            model: "llama3.2".to_string(), // Updated model name format
-            messages,
-            stream,
-        })
+        .json(&body)
         .send()
-        .await?
-        .error_for_status()? // Add proper HTTP error handling
-        .json::<ChatCompletionResponse>()
         .await?;
+    ProviderError::check(response).await
+}
+/// Whether `model` accepts images. Assumed when Ollama does not report the
+/// model's capabilities.
+async fn supports_vision(base_url: &str, model: &str) -> bool {
+    let capabilities = async {
+        reqwest::Client::new()
+            .post(format!("{}/api/show", base_url))
+            .json(&serde_json::json!({ "model": model }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<ModelCapabilities>()
+            .await
+    };
 
-    println!("Received response: {:?}", response);
-
-    Ok(response)
+    match capabilities.await {
+        Ok(ModelCapabilities {
+            capabilities: Some(capabilities),
+        }) => capabilities.iter().any(|c| c == "vision"),
+        _ => true,
+    }
 }
@@ -1 +1,25 @@
-// embed for ollama
\ No newline at end of file
+use {
+    crate::ollama::primitive::OllamaEmbedResponse,
+    topkio_primitive::{api::EmbeddingResponse, error::ProviderError},
+};
+
+/// Embed `input` with `/api/embed`.
+pub async fn embed(
+    base_url: &str,
+    model: &str,
+    input: Vec<String>,
+) -> Result<EmbeddingResponse, ProviderError> {
+    println!("Sending embed request to {} with model {}", base_url, model);
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/embed", base_url))
+        .json(&serde_json::json!({ "model": model, "input": input }))
+        .send()
+        .await?;
+    let response = ProviderError::check(response)
+        .await?
+        .json::<OllamaEmbedResponse>()
+        .await?;
+
+    Ok(response.into())
+}
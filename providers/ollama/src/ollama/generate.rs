@@ -0,0 +1,33 @@
+use {
+    crate::ollama::primitive::{OllamaGenerateRequest, OllamaGenerateResponse},
+    topkio_primitive::{
+        api::{CompletionRequest, CompletionResponse},
+        error::ProviderError,
+    },
+};
+
+/// Plain text completion with `/api/generate`, including fill-in-the-middle
+/// with `suffix` on models that support it.
+pub async fn generate(
+    base_url: &str,
+    model: &str,
+    request: CompletionRequest,
+) -> Result<CompletionResponse, ProviderError> {
+    println!(
+        "Sending completion request to {} with model {}",
+        base_url, model
+    );
+
+    let body = OllamaGenerateRequest::new(model, request);
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/generate", base_url))
+        .json(&body)
+        .send()
+        .await?;
+    let response = ProviderError::check(response)
+        .await?
+        .json::<OllamaGenerateResponse>()
+        .await?;
+
+    Ok(response.into())
+}
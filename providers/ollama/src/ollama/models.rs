@@ -0,0 +1,146 @@
+//! Model management: `/api/pull`, `/api/delete`, `/api/show`, `/api/ps`,
+//! and loading or unloading models with `keep_alive`.
+
+use {
+    crate::ollama::primitive::{RunningModels, ShowResponse},
+    futures_util::{
+        stream::{self, BoxStream},
+        StreamExt,
+    },
+    serde::de::DeserializeOwned,
+    topkio_primitive::{
+        api::{ModelDetails, PullProgress, RunningModel},
+        error::ProviderError,
+    },
+};
+
+/// Download `model`, streaming Ollama's progress lines.
+pub async fn pull(
+    base_url: &str,
+    model: &str,
+) -> Result<BoxStream<'static, Result<PullProgress, ProviderError>>, ProviderError> {
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/pull", base_url))
+        .json(&serde_json::json!({ "model": model, "stream": true }))
+        .send()
+        .await?;
+    Ok(ndjson(ProviderError::check(response).await?))
+}
+
+/// Pull `model` to the end, logging its progress.
+pub async fn pull_to_end(base_url: &str, model: &str) -> Result<(), ProviderError> {
+    println!("Pulling Ollama model {}", model);
+    let mut progress = pull(base_url, model).await?;
+    let mut last_status = String::new();
+    while let Some(step) = progress.next().await {
+        let step = step?;
+        if step.status != last_status {
+            println!("Pulling Ollama model {}: {}", model, step.status);
+            last_status = step.status;
+        }
+    }
+    Ok(())
+}
+
+pub async fn delete(base_url: &str, model: &str) -> Result<(), ProviderError> {
+    let response = reqwest::Client::new()
+        .delete(format!("{}/api/delete", base_url))
+        .json(&serde_json::json!({ "model": model }))
+        .send()
+        .await?;
+    ProviderError::check(response).await?;
+    Ok(())
+}
+
+pub async fn show(base_url: &str, model: &str) -> Result<ModelDetails, ProviderError> {
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/show", base_url))
+        .json(&serde_json::json!({ "model": model }))
+        .send()
+        .await?;
+    let show: ShowResponse = ProviderError::check(response).await?.json().await?;
+    Ok(show.into())
+}
+
+pub async fn running(base_url: &str) -> Result<Vec<RunningModel>, ProviderError> {
+    let response = reqwest::get(format!("{}/api/ps", base_url)).await?;
+    let running: RunningModels = ProviderError::check(response).await?.json().await?;
+    Ok(running.models)
+}
+
+/// Load `model` and keep it for `keep_alive`, by generating from an empty
+/// prompt. Ollama reads plain numbers as seconds and other values as
+/// durations such as `10m`.
+pub async fn keep_alive(
+    base_url: &str,
+    model: &str,
+    keep_alive: &str,
+) -> Result<(), ProviderError> {
+    let keep_alive = match keep_alive.trim().parse::<i64>() {
+        Ok(seconds) => serde_json::json!(seconds),
+        Err(_) => serde_json::json!(keep_alive.trim()),
+    };
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/generate", base_url))
+        .json(&serde_json::json!({ "model": model, "keep_alive": keep_alive, "stream": false }))
+        .send()
+        .await?;
+    ProviderError::check(response).await?;
+    Ok(())
+}
+
+/// Read a body of newline-delimited JSON objects. `{"error": ...}` lines,
+/// which Ollama sends when failing mid-stream, end the stream with an error.
+pub fn ndjson<T: DeserializeOwned + Send + 'static>(
+    response: reqwest::Response,
+) -> BoxStream<'static, Result<T, ProviderError>> {
+    let state = (Box::pin(response.bytes_stream()), Vec::new(), false);
+    stream::unfold(state, |(mut bytes, mut buffer, mut done)| async move {
+        loop {
+            if let Some(end) = buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                if let Some(item) = parse_line(&line) {
+                    if item.is_err() {
+                        buffer.clear();
+                        done = true;
+                    }
+                    return Some((item, (bytes, buffer, done)));
+                }
+                continue;
+            }
+            if done {
+                let line = std::mem::take(&mut buffer);
+                return parse_line(&line).map(|item| (item, (bytes, buffer, done)));
+            }
+            match bytes.next().await {
+                Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                Some(Err(e)) => {
+                    buffer.clear();
+                    return Some((Err(e.into()), (bytes, buffer, true)));
+                }
+                None => done = true,
+            }
+        }
+    })
+    .boxed()
+}
+
+/// One NDJSON line; `None` when it is blank.
+fn parse_line<T: DeserializeOwned>(line: &[u8]) -> Option<Result<T, ProviderError>> {
+    let line = std::str::from_utf8(line).ok()?.trim();
+    if line.is_empty() {
+        return None;
+    }
+    let value: serde_json::Value = match serde_json::from_str(line) {
+        Ok(value) => value,
+        Err(e) => return Some(Err(ProviderError::Upstream(e.to_string()))),
+    };
+    if let Some(error) = value.get("error") {
+        let message = error
+            .as_str()
+            .map(String::from)
+            .unwrap_or(error.to_string());
+        return Some(Err(ProviderError::Upstream(message)));
+    }
+    Some(serde_json::from_value(value).map_err(|e| ProviderError::Upstream(e.to_string())))
+}
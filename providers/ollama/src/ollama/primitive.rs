@@ -0,0 +1,566 @@
+use {
+    serde::{Deserialize, Serialize},
+    topkio_primitive::{
+        api::{
+            parse_data_url, ChatCompletionDelta, ChatCompletionRequest, ChatCompletionResponse,
+            CompletionRequest, CompletionResponse, ContentPart, EmbeddingResponse, FunctionCall,
+            Message, ModelDetails, ResponseFormat, RunningModel, Tool, ToolCall, ToolChoice,
+            ToolChoiceMode, Usage,
+        },
+        error::ProviderError,
+    },
+};
+
+#[derive(Debug, Serialize)]
+pub struct OllamaChatRequest {
+    pub model: String,
+    pub messages: Vec<OllamaMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    /// `"json"`, or a JSON Schema the answer must match.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "GenerateOptions::is_empty")]
+    pub options: GenerateOptions,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OllamaMessage {
+    pub role: String,
+    pub content: String,
+    /// Base64-encoded images, for vision models.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<OllamaToolCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+/// A tool call in Ollama's format, which has no id.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OllamaToolCall {
+    pub function: OllamaFunctionCall,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OllamaFunctionCall {
+    pub name: String,
+    /// Arguments as a JSON object, not a JSON-encoded string as in the
+    /// unified format.
+    #[serde(default)]
+    pub arguments: serde_json::Value,
+}
+
+impl TryFrom<ToolCall> for OllamaToolCall {
+    type Error = ProviderError;
+
+    fn try_from(call: ToolCall) -> Result<Self, Self::Error> {
+        let arguments = serde_json::from_str(&call.function.arguments).map_err(|e| {
+            ProviderError::InvalidRequest(format!(
+                "arguments of the call to {} are not JSON: {}",
+                call.function.name, e
+            ))
+        })?;
+        Ok(Self {
+            function: OllamaFunctionCall {
+                name: call.function.name,
+                arguments,
+            },
+        })
+    }
+}
+
+impl From<OllamaToolCall> for ToolCall {
+    fn from(call: OllamaToolCall) -> Self {
+        Self {
+            id: String::new(),
+            kind: "function".into(),
+            function: FunctionCall {
+                name: call.function.name,
+                arguments: call.function.arguments.to_string(),
+            },
+        }
+    }
+}
+
+/// Response of `/api/chat`.
+#[derive(Debug, Deserialize)]
+pub struct OllamaChatResponse {
+    pub message: OllamaResponseMessage,
+    /// Whether this is the last chunk of a stream, with the counts.
+    #[serde(default)]
+    pub done: bool,
+    #[serde(default)]
+    pub done_reason: Option<String>,
+    #[serde(default)]
+    pub prompt_eval_count: u32,
+    #[serde(default)]
+    pub eval_count: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OllamaResponseMessage {
+    pub role: String,
+    #[serde(default)]
+    pub content: String,
+    #[serde(default)]
+    pub tool_calls: Vec<OllamaToolCall>,
+}
+
+impl From<OllamaChatResponse> for ChatCompletionResponse {
+    fn from(response: OllamaChatResponse) -> Self {
+        let OllamaResponseMessage {
+            role,
+            content,
+            tool_calls,
+        } = response.message;
+        // Ollama reports tool calls as `stop`.
+        let finish_reason = match tool_calls.is_empty() {
+            true => response.done_reason,
+            false => Some("tool_calls".into()),
+        };
+        let mut message = Message::new(role, content);
+        message.tool_calls =
+            (!tool_calls.is_empty()).then(|| tool_calls.into_iter().map(ToolCall::from).collect());
+
+        let mut unified = ChatCompletionResponse::new(message);
+        unified.finish_reason = finish_reason;
+        unified.usage = Some(Usage::new(response.prompt_eval_count, response.eval_count));
+        unified
+    }
+}
+
+/// A chunk of a streamed answer. Tool calls come whole, in a chunk of
+/// their own.
+impl From<OllamaChatResponse> for ChatCompletionDelta {
+    fn from(chunk: OllamaChatResponse) -> Self {
+        Self {
+            content: chunk.message.content,
+            tool_calls: chunk
+                .message
+                .tool_calls
+                .into_iter()
+                .map(ToolCall::from)
+                .collect(),
+            finish_reason: chunk
+                .done
+                .then(|| chunk.done_reason.unwrap_or_else(|| "stop".into())),
+            usage: chunk
+                .done
+                .then(|| Usage::new(chunk.prompt_eval_count, chunk.eval_count)),
+        }
+    }
+}
+
+impl OllamaChatRequest {
+    pub fn new(model: &str, request: ChatCompletionRequest) -> Result<Self, ProviderError> {
+        if request.logprobs == Some(true) {
+            return Err(ProviderError::InvalidRequest(
+                "Ollama models do not return logprobs".into(),
+            ));
+        }
+        // Ollama lets the model decide; it can only be kept from calling tools.
+        let tools =
+            match request.tool_choice {
+                None | Some(ToolChoice::Mode(ToolChoiceMode::Auto)) => request.tools,
+                Some(ToolChoice::Mode(ToolChoiceMode::None)) => None,
+                Some(_) => return Err(ProviderError::InvalidRequest(
+                    "Ollama models cannot be made to call a tool; tool_choice must be auto or none"
+                        .into(),
+                )),
+            };
+
+        Ok(Self {
+            model: model.to_string(),
+            messages: request
+                .messages
+                .into_iter()
+                .map(OllamaMessage::try_from)
+                .collect::<Result<_, _>>()?,
+            stream: request.stream,
+            tools,
+            format: match request.response_format {
+                Some(ResponseFormat::JsonObject) => Some("json".into()),
+                Some(ResponseFormat::JsonSchema { json_schema }) => Some(json_schema.schema),
+                Some(ResponseFormat::Text) | None => None,
+            },
+            options: GenerateOptions {
+                num_predict: request.max_tokens,
+                temperature: request.temperature,
+                stop: request.stop,
+            },
+        })
+    }
+
+    pub fn has_images(&self) -> bool {
+        self.messages
+            .iter()
+            .any(|message| !message.images.is_empty())
+    }
+}
+
+impl TryFrom<Message> for OllamaMessage {
+    type Error = ProviderError;
+
+    fn try_from(message: Message) -> Result<Self, Self::Error> {
+        let mut content = String::new();
+        let mut images = vec![];
+
+        for part in message.content.into_parts() {
+            match part {
+                ContentPart::Text { text } => content.push_str(&text),
+                ContentPart::ImageUrl { image_url } => match parse_data_url(&image_url.url) {
+                    Some((_, data)) => images.push(data.to_string()),
+                    None => {
+                        return Err(ProviderError::InvalidRequest(
+                            "Ollama only accepts images as base64 data URLs".into(),
+                        ))
+                    }
+                },
+                part => {
+                    return Err(ProviderError::InvalidRequest(format!(
+                        "Ollama models do not accept {} input",
+                        part.kind()
+                    )))
+                }
+            }
+        }
+
+        Ok(Self {
+            role: message.role,
+            content,
+            images,
+            tool_calls: message
+                .tool_calls
+                .unwrap_or_default()
+                .into_iter()
+                .map(OllamaToolCall::try_from)
+                .collect::<Result<_, _>>()?,
+            tool_call_id: message.tool_call_id,
+        })
+    }
+}
+
+/// The part of `/api/show` telling what a model can do.
+#[derive(Debug, Deserialize)]
+pub struct ModelCapabilities {
+    /// E.g. `completion`, `vision`, `tools`. Missing on older Ollama versions.
+    #[serde(default)]
+    pub capabilities: Option<Vec<String>>,
+}
+
+/// Response of `/api/show`.
+#[derive(Debug, Deserialize)]
+pub struct ShowResponse {
+    #[serde(default)]
+    pub modelfile: String,
+    #[serde(default)]
+    pub parameters: String,
+    #[serde(default)]
+    pub template: String,
+    #[serde(default)]
+    pub details: serde_json::Value,
+    /// Architecture metadata, e.g. `llama.context_length`.
+    #[serde(default)]
+    pub model_info: serde_json::Map<String, serde_json::Value>,
+}
+
+impl From<ShowResponse> for ModelDetails {
+    fn from(show: ShowResponse) -> Self {
+        let context_length = show
+            .model_info
+            .iter()
+            .find(|(key, _)| key.ends_with(".context_length"))
+            .and_then(|(_, value)| value.as_u64());
+
+        Self {
+            modelfile: show.modelfile,
+            parameters: show.parameters,
+            template: show.template,
+            context_length,
+            details: show.details,
+        }
+    }
+}
+
+/// Response of `/api/ps`.
+#[derive(Debug, Deserialize)]
+pub struct RunningModels {
+    #[serde(default)]
+    pub models: Vec<RunningModel>,
+}
+
+/// Request of `/api/generate`.
+#[derive(Debug, Serialize)]
+pub struct OllamaGenerateRequest {
+    pub model: String,
+    pub prompt: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suffix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<Vec<i64>>,
+    pub stream: bool,
+    #[serde(skip_serializing_if = "GenerateOptions::is_empty")]
+    pub options: GenerateOptions,
+}
+
+/// Options of `/api/chat` and `/api/generate`.
+#[derive(Debug, Default, Serialize)]
+pub struct GenerateOptions {
+    /// Maximum number of tokens to generate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_predict: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+}
+
+impl GenerateOptions {
+    fn is_empty(&self) -> bool {
+        self.num_predict.is_none() && self.temperature.is_none() && self.stop.is_none()
+    }
+}
+
+impl OllamaGenerateRequest {
+    pub fn new(model: &str, request: CompletionRequest) -> Self {
+        Self {
+            model: model.to_string(),
+            prompt: request.prompt,
+            suffix: request.suffix,
+            raw: request.raw,
+            context: request.context,
+            stream: false,
+            options: GenerateOptions {
+                num_predict: request.max_tokens,
+                temperature: request.temperature,
+                stop: request.stop,
+            },
+        }
+    }
+}
+
+/// Response of `/api/generate`.
+#[derive(Debug, Deserialize)]
+pub struct OllamaGenerateResponse {
+    pub response: String,
+    #[serde(default)]
+    pub done_reason: Option<String>,
+    #[serde(default)]
+    pub context: Option<Vec<i64>>,
+    #[serde(default)]
+    pub prompt_eval_count: u32,
+    #[serde(default)]
+    pub eval_count: u32,
+}
+
+impl From<OllamaGenerateResponse> for CompletionResponse {
+    fn from(response: OllamaGenerateResponse) -> Self {
+        Self {
+            text: response.response,
+            finish_reason: response.done_reason,
+            usage: Some(Usage::new(response.prompt_eval_count, response.eval_count)),
+            context: response.context,
+        }
+    }
+}
+
+/// Response of `/api/embed`.
+#[derive(Debug, Deserialize)]
+pub struct OllamaEmbedResponse {
+    pub embeddings: Vec<Vec<f32>>,
+    #[serde(default)]
+    pub prompt_eval_count: u32,
+}
+
+impl From<OllamaEmbedResponse> for EmbeddingResponse {
+    fn from(response: OllamaEmbedResponse) -> Self {
+        Self {
+            embeddings: response.embeddings,
+            usage: Some(Usage::new(response.prompt_eval_count, 0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(parts: serde_json::Value) -> Result<OllamaMessage, ProviderError> {
+        let message = serde_json::json!({"role": "user", "content": parts});
+        serde_json::from_value::<Message>(message)
+            .unwrap()
+            .try_into()
+    }
+
+    #[test]
+    fn translates_text_and_inline_images() {
+        let message = message(serde_json::json!([
+            {"type": "text", "text": "What is "},
+            {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0KGgo="}},
+            {"type": "text", "text": "this?"}
+        ]))
+        .unwrap();
+        assert_eq!(message.content, "What is this?");
+        assert_eq!(message.images, ["iVBORw0KGgo="]);
+    }
+
+    #[test]
+    fn rejects_unsupported_content_parts() {
+        let rejected = [
+            serde_json::json!({"type": "image_url", "image_url": {"url": "https://example.com/cat.png"}}),
+            serde_json::json!({"type": "input_audio", "input_audio": {"data": "UklGRg==", "format": "wav"}}),
+            serde_json::json!({"type": "file", "file": {"file_data": "data:application/pdf;base64,JVBERi0="}}),
+        ];
+        for part in rejected {
+            assert!(
+                matches!(
+                    message(serde_json::json!([part])),
+                    Err(ProviderError::InvalidRequest(_))
+                ),
+                "{}",
+                part
+            );
+        }
+    }
+
+    #[test]
+    fn passes_generation_options() {
+        let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "ollama:llama3",
+            "messages": [{"role": "user", "content": "Hi"}],
+            "max_tokens": 16,
+            "temperature": 0.2,
+            "stop": "\n"
+        }))
+        .unwrap();
+        let body =
+            serde_json::to_value(OllamaChatRequest::new("llama3", request).unwrap()).unwrap();
+        assert_eq!(
+            body["options"],
+            serde_json::json!({"num_predict": 16, "temperature": 0.2f32, "stop": ["\n"]})
+        );
+
+        let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "ollama:llama3",
+            "messages": [{"role": "user", "content": "Hi"}]
+        }))
+        .unwrap();
+        let body =
+            serde_json::to_value(OllamaChatRequest::new("llama3", request).unwrap()).unwrap();
+        assert!(body.get("options").is_none());
+    }
+
+    #[test]
+    fn honours_tool_choice() {
+        let request = |tool_choice: serde_json::Value| {
+            let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+                "model": "ollama:llama3",
+                "messages": [{"role": "user", "content": "Hi"}],
+                "tools": [{"type": "function", "function": {"name": "lookup"}}],
+                "tool_choice": tool_choice
+            }))
+            .unwrap();
+            OllamaChatRequest::new("llama3", request)
+        };
+
+        assert!(request(serde_json::json!("auto")).unwrap().tools.is_some());
+        assert!(request(serde_json::json!("none")).unwrap().tools.is_none());
+        for forced in [
+            serde_json::json!("required"),
+            serde_json::json!({"type": "function", "function": {"name": "lookup"}}),
+        ] {
+            assert!(matches!(
+                request(forced),
+                Err(ProviderError::InvalidRequest(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn reads_tool_calls_of_chat_responses() {
+        // As sent by Ollama 0.5 for llama3.1.
+        let body = r#"{
+            "model": "llama3.1",
+            "created_at": "2024-07-22T20:33:28.123648Z",
+            "message": {
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [{
+                    "function": {
+                        "name": "get_current_weather",
+                        "arguments": {"format": "celsius", "location": "Paris, FR"}
+                    }
+                }]
+            },
+            "done_reason": "stop",
+            "done": true,
+            "total_duration": 885095291,
+            "load_duration": 3753500,
+            "prompt_eval_count": 122,
+            "prompt_eval_duration": 328493000,
+            "eval_count": 33,
+            "eval_duration": 552222000
+        }"#;
+        let response =
+            ChatCompletionResponse::from(serde_json::from_str::<OllamaChatResponse>(body).unwrap());
+
+        assert_eq!(response.message.role, "assistant");
+        assert_eq!(response.finish_reason.as_deref(), Some("tool_calls"));
+        let usage = response.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (122, 33));
+        let calls = response.message.tool_calls.unwrap();
+        assert_eq!(calls[0].function.name, "get_current_weather");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&calls[0].function.arguments).unwrap(),
+            serde_json::json!({"format": "celsius", "location": "Paris, FR"})
+        );
+    }
+
+    #[test]
+    fn sends_tool_call_arguments_as_objects() {
+        let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "ollama:llama3.1",
+            "messages": [
+                {"role": "user", "content": "Weather in Paris?"},
+                {"role": "assistant", "content": "", "tool_calls": [{
+                    "id": "call_1",
+                    "type": "function",
+                    "function": {"name": "get_current_weather", "arguments": "{\"location\":\"Paris, FR\"}"}
+                }]},
+                {"role": "tool", "content": "22 degrees", "tool_call_id": "call_1"}
+            ]
+        }))
+        .unwrap();
+        let body =
+            serde_json::to_value(OllamaChatRequest::new("llama3.1", request).unwrap()).unwrap();
+
+        assert_eq!(
+            body["messages"][1],
+            serde_json::json!({
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [{
+                    "function": {"name": "get_current_weather", "arguments": {"location": "Paris, FR"}}
+                }]
+            })
+        );
+        assert!(body["messages"][0].get("tool_calls").is_none());
+
+        let call: Message = serde_json::from_value(serde_json::json!({
+            "role": "assistant",
+            "content": "",
+            "tool_calls": [{"function": {"name": "f", "arguments": "not json"}}]
+        }))
+        .unwrap();
+        assert!(matches!(
+            OllamaMessage::try_from(call),
+            Err(ProviderError::InvalidRequest(_))
+        ));
+    }
+}
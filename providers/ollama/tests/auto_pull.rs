@@ -0,0 +1,102 @@
+//! Auto-pull against a mock Ollama server that lacks every model until it
+//! is pulled.
+
+use {
+    axum::{http::StatusCode, routing::post, Json, Router},
+    std::{
+        collections::HashSet,
+        sync::{Arc, Mutex},
+        time::Duration,
+    },
+    topkio_ollama::OllamaBackend,
+    topkio_primitive::api::{ChatCompletionRequest, Message, UnifiedLlmApi},
+};
+
+#[derive(Default)]
+struct Server {
+    models: Mutex<HashSet<String>>,
+    pulls: Mutex<Vec<String>>,
+}
+
+async fn serve() -> (String, Arc<Server>) {
+    let server = Arc::new(Server::default());
+
+    let state = server.clone();
+    let chat = move |Json(body): Json<serde_json::Value>| async move {
+        let model = body["model"].as_str().unwrap_or_default();
+        match state.models.lock().unwrap().contains(model) {
+            true => (
+                StatusCode::OK,
+                Json(serde_json::json!({
+                    "model": model,
+                    "message": {"role": "assistant", "content": "Hi"},
+                    "done": true
+                })),
+            ),
+            false => (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({"error": format!("model \"{}\" not found", model)})),
+            ),
+        }
+    };
+
+    let state = server.clone();
+    let pull = move |Json(body): Json<serde_json::Value>| async move {
+        let model = body["model"].as_str().unwrap_or_default().to_string();
+        state.pulls.lock().unwrap().push(model.clone());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        state.models.lock().unwrap().insert(model);
+        "{\"status\":\"pulling manifest\"}\n{\"status\":\"success\"}\n"
+    };
+
+    let app = Router::new()
+        .route("/api/chat", post(chat))
+        .route("/api/pull", post(pull));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (url, server)
+}
+
+fn request(model: &str) -> ChatCompletionRequest {
+    ChatCompletionRequest {
+        model: format!("ollama:{}", model),
+        messages: vec![Message::new("user", "Hello")],
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn concurrent_requests_share_one_pull() {
+    let (url, server) = serve().await;
+    let backend = OllamaBackend::new(url).with_auto_pull(vec!["llama3.2".into()]);
+
+    let responses = futures_util::future::join_all(
+        (0..3).map(|_| backend.chat_completion("llama3.2", request("llama3.2"))),
+    )
+    .await;
+
+    for response in responses {
+        assert_eq!(response.unwrap().message.content.text(), "Hi");
+    }
+    assert_eq!(*server.pulls.lock().unwrap(), ["llama3.2"]);
+}
+
+#[tokio::test]
+async fn pulls_only_supported_models() {
+    let (url, server) = serve().await;
+    let backend = OllamaBackend::new(url).with_auto_pull(vec!["llama3.2:latest".into()]);
+
+    assert!(backend
+        .chat_completion("mistral", request("mistral"))
+        .await
+        .is_err());
+    assert!(server.pulls.lock().unwrap().is_empty());
+
+    // `llama3.2` is `llama3.2:latest`.
+    assert!(backend
+        .chat_completion("llama3.2", request("llama3.2"))
+        .await
+        .is_ok());
+    assert_eq!(*server.pulls.lock().unwrap(), ["llama3.2"]);
+}
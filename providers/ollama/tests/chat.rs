@@ -0,0 +1,68 @@
+//! Streamed chats against a mock Ollama server.
+
+use {
+    axum::{routing::post, Json, Router},
+    futures_util::StreamExt,
+    std::sync::{Arc, Mutex},
+    topkio_ollama::OllamaBackend,
+    topkio_primitive::api::{ChatCompletionRequest, Message, UnifiedLlmApi},
+};
+
+/// Chunks of a streamed answer, as Ollama 0.5 sends them.
+const CHUNKS: &str = r#"{"model":"llama3.2","created_at":"2025-01-01T00:00:00Z","message":{"role":"assistant","content":"Hel"},"done":false}
+{"model":"llama3.2","created_at":"2025-01-01T00:00:00Z","message":{"role":"assistant","content":"lo"},"done":false}
+{"model":"llama3.2","created_at":"2025-01-01T00:00:00Z","message":{"role":"assistant","content":"","tool_calls":[{"function":{"name":"wave","arguments":{"hand":"left"}}}]},"done":false}
+{"model":"llama3.2","created_at":"2025-01-01T00:00:00Z","message":{"role":"assistant","content":""},"done_reason":"stop","done":true,"total_duration":1000,"prompt_eval_count":12,"eval_count":3}
+"#;
+
+/// A server answering `/api/chat` with `CHUNKS`, recording the bodies sent.
+async fn serve() -> (String, Arc<Mutex<Vec<serde_json::Value>>>) {
+    let bodies = Arc::new(Mutex::new(vec![]));
+    let recorded = bodies.clone();
+    let chat = move |Json(body): Json<serde_json::Value>| async move {
+        recorded.lock().unwrap().push(body);
+        CHUNKS
+    };
+
+    let app = Router::new().route("/api/chat", post(chat));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (url, bodies)
+}
+
+#[tokio::test]
+async fn streams_chat_chunks() {
+    let (url, bodies) = serve().await;
+    let backend = OllamaBackend::new(url);
+
+    let request = ChatCompletionRequest {
+        model: "ollama:llama3.2".into(),
+        messages: vec![Message::new("user", "Hi")],
+        ..Default::default()
+    };
+    let deltas: Vec<_> = backend
+        .chat_completion_stream("llama3.2", request)
+        .await
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+        .await;
+
+    assert_eq!(bodies.lock().unwrap()[0]["stream"], true);
+    let content: Vec<&str> = deltas.iter().map(|delta| delta.content.as_str()).collect();
+    assert_eq!(content, ["Hel", "lo", "", ""]);
+    assert_eq!(deltas[2].tool_calls[0].function.name, "wave");
+    assert_eq!(
+        deltas[2].tool_calls[0].function.arguments,
+        r#"{"hand":"left"}"#
+    );
+
+    let last = deltas.last().unwrap();
+    assert_eq!(last.finish_reason.as_deref(), Some("stop"));
+    let usage = last.usage.as_ref().unwrap();
+    assert_eq!((usage.prompt_tokens, usage.completion_tokens), (12, 3));
+    assert!(deltas[..3]
+        .iter()
+        .all(|delta| delta.finish_reason.is_none()));
+}
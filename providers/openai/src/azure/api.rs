@@ -0,0 +1,117 @@
+use {
+    crate::openai::chat_completion::{post_chat_completion, read_chat_completion},
+    std::{
+        collections::HashMap,
+        sync::atomic::{AtomicUsize, Ordering},
+    },
+    topkio_primitive::{
+        api::{ChatCompletionRequest, ChatCompletionResponse, ModelInfo, UnifiedLlmApi},
+        error::ProviderError,
+    },
+};
+
+/// One Azure OpenAI resource (usually one region).
+pub struct AzureResource {
+    /// e.g. `https://my-eastus.openai.azure.com`
+    pub url: String,
+    pub api_key: String,
+    /// Gateway model name -> Azure deployment name. When empty, the model
+    /// name is used as the deployment name.
+    pub deployments: HashMap<String, String>,
+}
+
+impl AzureResource {
+    fn deployment<'a>(&'a self, model: &'a str) -> Option<&'a str> {
+        match self.deployments.is_empty() {
+            true => Some(model),
+            false => self.deployments.get(model).map(String::as_str),
+        }
+    }
+}
+
+/// Backend for Azure OpenAI, load balancing over a pool of resources.
+pub struct AzureOpenAiBackend {
+    api_version: String,
+    resources: Vec<AzureResource>,
+    next: AtomicUsize,
+}
+
+impl AzureOpenAiBackend {
+    pub fn new(api_version: String, resources: Vec<AzureResource>) -> Self {
+        Self {
+            api_version,
+            resources,
+            next: AtomicUsize::new(0),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl UnifiedLlmApi for AzureOpenAiBackend {
+    async fn chat_completion(
+        &self,
+        model: &str,
+        request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, anyhow::Error> {
+        // Round-robin over the resources that deploy this model, failing over
+        // to the next one when a resource is rate limited, failing or
+        // unreachable. Other errors would be the same on every resource.
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let candidates = (0..self.resources.len())
+            .map(|i| &self.resources[(start + i) % self.resources.len()])
+            .filter_map(|resource| Some((resource, resource.deployment(model)?)));
+        let enable_stream = request.stream.unwrap_or(false);
+
+        let mut first_error = None;
+        for (resource, deployment) in candidates {
+            let builder = reqwest::Client::new()
+                .post(format!(
+                    "{}/openai/deployments/{}/chat/completions",
+                    resource.url.trim_end_matches('/'),
+                    deployment
+                ))
+                .query(&[("api-version", &self.api_version)])
+                .header("api-key", &resource.api_key);
+
+            let error = match post_chat_completion(builder, None, request.clone()).await {
+                Ok(response) if !is_transient(response.status()) => {
+                    return read_chat_completion(response, enable_stream).await;
+                }
+                Ok(response) => match ProviderError::check(response).await {
+                    Ok(_) => unreachable!("transient statuses are failures"),
+                    Err(e) => e,
+                },
+                Err(e) if e.is_connect() || e.is_timeout() => ProviderError::from(e),
+                Err(e) => return Err(ProviderError::from(e).into()),
+            };
+            eprintln!("Azure resource {} failed: {}", resource.url, error);
+            first_error.get_or_insert(error);
+        }
+
+        Err(match first_error {
+            Some(e) => e.into(),
+            None => anyhow::anyhow!("No Azure deployment configured for model {model}"),
+        })
+    }
+
+    fn supports_n(&self) -> bool {
+        true
+    }
+
+    async fn get_models(&self) -> Result<Vec<ModelInfo>, anyhow::Error> {
+        let mut models: Vec<String> = self
+            .resources
+            .iter()
+            .flat_map(|resource| resource.deployments.keys().cloned())
+            .collect();
+        models.sort();
+        models.dedup();
+
+        Ok(models.into_iter().map(ModelInfo::new).collect())
+    }
+}
+
+/// Whether a failed request may succeed on another resource.
+fn is_transient(status: reqwest::StatusCode) -> bool {
+    status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
@@ -0,0 +1,5 @@
+mod azure;
+mod openai;
+
+pub use azure::api::{AzureOpenAiBackend, AzureResource};
+pub use openai::api::OpenAiBackend;
@@ -0,0 +1,4 @@
+pub mod api;
+pub mod chat_completion;
+pub mod embed;
+pub mod primitive;
@@ -0,0 +1,54 @@
+use {
+    super::{
+        chat_completion::{chat_completion, chat_completion_stream},
+        embed::embed,
+    },
+    futures_util::stream::BoxStream,
+    topkio_primitive::api::{
+        ChatCompletionDelta, ChatCompletionRequest, ChatCompletionResponse, EmbeddingResponse,
+        UnifiedLlmApi,
+    },
+};
+
+/// Backend for OpenAI and OpenAI-compatible APIs (e.g. DeepSeek).
+pub struct OpenAiBackend {
+    base_url: String,
+    api_key: String,
+}
+
+impl OpenAiBackend {
+    pub fn new(base_url: String, api_key: String) -> Self {
+        Self { base_url, api_key }
+    }
+}
+
+#[async_trait::async_trait]
+impl UnifiedLlmApi for OpenAiBackend {
+    async fn chat_completion(
+        &self,
+        model: &str,
+        request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, anyhow::Error> {
+        chat_completion(&self.base_url, &self.api_key, model, request).await
+    }
+
+    async fn chat_completion_stream(
+        &self,
+        model: &str,
+        request: ChatCompletionRequest,
+    ) -> Result<BoxStream<'static, Result<ChatCompletionDelta, anyhow::Error>>, anyhow::Error> {
+        chat_completion_stream(&self.base_url, &self.api_key, model, request).await
+    }
+
+    fn supports_n(&self) -> bool {
+        true
+    }
+
+    async fn embed(
+        &self,
+        model: &str,
+        input: Vec<String>,
+    ) -> Result<EmbeddingResponse, anyhow::Error> {
+        Ok(embed(&self.base_url, &self.api_key, model, input).await?)
+    }
+}
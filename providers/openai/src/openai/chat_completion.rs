@@ -0,0 +1,193 @@
+use {
+    crate::openai::primitive::{
+        ChatCompletionChunk, ChunkAccumulator, OpenAiChatRequest, OpenAiChatResponse,
+    },
+    futures_util::{
+        stream::{self, BoxStream},
+        StreamExt,
+    },
+    reqwest::RequestBuilder,
+    topkio_primitive::{
+        api::{ChatCompletionDelta, ChatCompletionRequest, ChatCompletionResponse},
+        error::ProviderError,
+    },
+};
+
+pub async fn chat_completion(
+    base_url: &str,
+    api_key: &str,
+    model: &str,
+    request: ChatCompletionRequest,
+) -> Result<ChatCompletionResponse, anyhow::Error> {
+    let builder = reqwest::Client::new()
+        .post(format!("{}/chat/completions", base_url))
+        .bearer_auth(api_key);
+
+    send_chat_completion(builder, Some(model), request).await
+}
+
+/// Stream the answer to a chat completion.
+pub async fn chat_completion_stream(
+    base_url: &str,
+    api_key: &str,
+    model: &str,
+    mut request: ChatCompletionRequest,
+) -> Result<BoxStream<'static, Result<ChatCompletionDelta, anyhow::Error>>, anyhow::Error> {
+    let builder = reqwest::Client::new()
+        .post(format!("{}/chat/completions", base_url))
+        .bearer_auth(api_key);
+
+    request.stream = Some(true);
+    let response = post_chat_completion(builder, Some(model), request)
+        .await
+        .map_err(ProviderError::from)?;
+    Ok(stream_chat_completion(
+        ProviderError::check(response).await?,
+    ))
+}
+
+/// Send an OpenAI-format chat completion to a prepared endpoint.
+///
+/// The builder carries the URL and authentication, so OpenAI-compatible
+/// vendors (Azure, DeepSeek, ...) only differ in how they build it.
+pub async fn send_chat_completion(
+    builder: RequestBuilder,
+    model: Option<&str>,
+    request: ChatCompletionRequest,
+) -> Result<ChatCompletionResponse, anyhow::Error> {
+    let enable_stream = request.stream.unwrap_or(false);
+    let response = post_chat_completion(builder, model, request)
+        .await
+        .map_err(ProviderError::from)?;
+    read_chat_completion(response, enable_stream).await
+}
+
+/// Post a chat completion without looking at the response status, for
+/// callers deciding themselves what to do with a failure.
+pub async fn post_chat_completion(
+    builder: RequestBuilder,
+    model: Option<&str>,
+    request: ChatCompletionRequest,
+) -> Result<reqwest::Response, reqwest::Error> {
+    builder
+        .json(&OpenAiChatRequest::new(model, request))
+        .send()
+        .await
+}
+
+/// Read the answer to a posted chat completion, streamed or not.
+pub async fn read_chat_completion(
+    response: reqwest::Response,
+    enable_stream: bool,
+) -> Result<ChatCompletionResponse, anyhow::Error> {
+    let response = ProviderError::check(response).await?;
+
+    match enable_stream {
+        true => {
+            let mut accumulator = ChunkAccumulator::default();
+            let mut buffer: Vec<u8> = vec![];
+            let mut stream = response.bytes_stream();
+
+            while let Some(chunk) = stream.next().await {
+                buffer.extend_from_slice(&chunk.map_err(ProviderError::from)?);
+
+                while let Some(end) = buffer.iter().position(|b| *b == b'\n') {
+                    let line: Vec<u8> = buffer.drain(..end + 1).collect();
+                    if let Some(chunk) = parse_sse_line(std::str::from_utf8(&line)?)? {
+                        accumulator.push(chunk);
+                    }
+                }
+            }
+            if let Some(chunk) = parse_sse_line(std::str::from_utf8(&buffer)?)? {
+                accumulator.push(chunk);
+            }
+
+            Ok(accumulator.finish())
+        }
+        false => response.json::<OpenAiChatResponse>().await?.try_into(),
+    }
+}
+
+/// The text of the first choice of a streamed answer as it arrives, then a
+/// last piece with its tool calls, finish reason and usage, which may only
+/// be complete at the end of the stream.
+fn stream_chat_completion(
+    response: reqwest::Response,
+) -> BoxStream<'static, Result<ChatCompletionDelta, anyhow::Error>> {
+    let state = (
+        Box::pin(response.bytes_stream()),
+        Vec::new(),
+        Some(ChunkAccumulator::default()),
+        false,
+    );
+    stream::unfold(
+        state,
+        |(mut bytes, mut buffer, mut accumulator, mut ended)| async move {
+            loop {
+                let chunks = accumulator.as_mut()?;
+                let line_end = buffer.iter().position(|b| *b == b'\n');
+                if line_end.is_none() && !ended {
+                    match bytes.next().await {
+                        Some(Ok(bytes)) => buffer.extend_from_slice(&bytes),
+                        Some(Err(e)) => {
+                            let error = anyhow::Error::from(ProviderError::from(e));
+                            return Some((Err(error), (bytes, buffer, None, true)));
+                        }
+                        None => ended = true,
+                    }
+                    continue;
+                }
+                if line_end.is_none() && buffer.is_empty() {
+                    let mut last = ChatCompletionDelta::from(accumulator.take()?.finish());
+                    last.content.clear();
+                    return Some((Ok(last), (bytes, buffer, None, true)));
+                }
+
+                let line: Vec<u8> = buffer
+                    .drain(..line_end.map_or(buffer.len(), |end| end + 1))
+                    .collect();
+                let chunk = std::str::from_utf8(&line)
+                    .map_err(anyhow::Error::from)
+                    .and_then(parse_sse_line);
+                match chunk {
+                    Ok(Some(chunk)) => {
+                        let content = chunk
+                            .choices
+                            .iter()
+                            .find(|choice| choice.index == 0)
+                            .and_then(|choice| choice.delta.content.clone())
+                            .unwrap_or_default();
+                        chunks.push(chunk);
+                        if !content.is_empty() {
+                            let delta = ChatCompletionDelta {
+                                content,
+                                ..Default::default()
+                            };
+                            return Some((Ok(delta), (bytes, buffer, accumulator, ended)));
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => return Some((Err(e), (bytes, buffer, None, true))),
+                }
+            }
+        },
+    )
+    .boxed()
+}
+
+/// Parse a single `data:` line of the SSE stream.
+fn parse_sse_line(line: &str) -> Result<Option<ChatCompletionChunk>, anyhow::Error> {
+    match line.trim().strip_prefix("data:").map(str::trim) {
+        Some("[DONE]") | Some("") | None => Ok(None),
+        Some(data) => {
+            let value: serde_json::Value = serde_json::from_str(data)?;
+            // Errors after the stream started arrive as a data line.
+            if let Some(error) = value.get("error") {
+                let kind = error["type"].as_str().unwrap_or("api_error");
+                let message = error["message"].as_str().unwrap_or_default().to_string();
+                return Err(ProviderError::from_kind(kind, message).into());
+            }
+            Ok(Some(serde_json::from_value(value)?))
+        }
+    }
+}
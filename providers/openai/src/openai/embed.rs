@@ -0,0 +1,24 @@
+use {
+    crate::openai::primitive::OpenAiEmbeddingResponse,
+    topkio_primitive::{api::EmbeddingResponse, error::ProviderError},
+};
+
+pub async fn embed(
+    base_url: &str,
+    api_key: &str,
+    model: &str,
+    input: Vec<String>,
+) -> Result<EmbeddingResponse, ProviderError> {
+    let response = reqwest::Client::new()
+        .post(format!("{}/embeddings", base_url))
+        .bearer_auth(api_key)
+        .json(&serde_json::json!({ "model": model, "input": input }))
+        .send()
+        .await?;
+    let response = ProviderError::check(response)
+        .await?
+        .json::<OpenAiEmbeddingResponse>()
+        .await?;
+
+    Ok(response.into())
+}
@@ -0,0 +1,318 @@
+use {
+    serde::{Deserialize, Serialize},
+    std::collections::BTreeMap,
+    topkio_primitive::api::{
+        ChatCompletionRequest, ChatCompletionResponse, Choice as UnifiedChoice, EmbeddingResponse,
+        FunctionCall, Logprobs, Message, ResponseFormat, Tool, ToolCall, ToolChoice, Usage,
+    },
+};
+
+#[derive(Debug, Serialize)]
+pub struct OpenAiChatRequest {
+    /// Omitted for Azure, where the deployment in the URL selects the model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    pub messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<ToolChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StreamOptions {
+    pub include_usage: bool,
+}
+
+impl OpenAiChatRequest {
+    pub fn new(model: Option<&str>, request: ChatCompletionRequest) -> Self {
+        let stream_options = request.stream.unwrap_or(false).then_some(StreamOptions {
+            include_usage: true,
+        });
+
+        Self {
+            model: model.map(str::to_string),
+            messages: request.messages,
+            stream: request.stream,
+            stream_options,
+            max_tokens: request.max_tokens,
+            temperature: request.temperature,
+            stop: request.stop,
+            tools: request.tools,
+            tool_choice: request.tool_choice,
+            response_format: request.response_format,
+            n: request.n,
+            logprobs: request.logprobs,
+            top_logprobs: request.top_logprobs,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenAiChatResponse {
+    pub choices: Vec<Choice>,
+    pub usage: Option<OpenAiUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenAiUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub prompt_tokens_details: Option<PromptTokensDetails>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PromptTokensDetails {
+    pub cached_tokens: Option<u32>,
+}
+
+impl From<OpenAiUsage> for Usage {
+    fn from(usage: OpenAiUsage) -> Self {
+        let mut unified = Usage::new(usage.prompt_tokens, usage.completion_tokens);
+        unified.cached_tokens = usage
+            .prompt_tokens_details
+            .and_then(|details| details.cached_tokens);
+        unified
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Choice {
+    #[serde(default)]
+    pub index: u32,
+    pub message: ResponseMessage,
+    pub finish_reason: Option<String>,
+    pub logprobs: Option<Logprobs>,
+}
+
+/// Response messages may carry `content: null` alongside tool calls.
+#[derive(Debug, Default, Deserialize)]
+pub struct ResponseMessage {
+    pub role: Option<String>,
+    pub content: Option<String>,
+    pub tool_calls: Option<Vec<ToolCall>>,
+}
+
+impl TryFrom<OpenAiChatResponse> for ChatCompletionResponse {
+    type Error = anyhow::Error;
+
+    fn try_from(response: OpenAiChatResponse) -> Result<Self, Self::Error> {
+        let choices = response
+            .choices
+            .into_iter()
+            .map(|choice| {
+                let mut message = Message::new(
+                    choice.message.role.unwrap_or_else(|| "assistant".into()),
+                    choice.message.content.unwrap_or_default(),
+                );
+                message.tool_calls = choice.message.tool_calls;
+
+                UnifiedChoice {
+                    index: choice.index,
+                    message,
+                    finish_reason: choice.finish_reason,
+                    logprobs: choice.logprobs,
+                    extensions: None,
+                }
+            })
+            .collect();
+
+        ChatCompletionResponse::from_choices(choices, response.usage.map(Usage::from))
+            .ok_or_else(|| anyhow::anyhow!("No choices found in response"))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChatCompletionChunk {
+    #[serde(default)]
+    pub choices: Vec<ChunkChoice>,
+    pub usage: Option<OpenAiUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChunkChoice {
+    #[serde(default)]
+    pub index: u32,
+    #[serde(default)]
+    pub delta: ChunkDelta,
+    pub finish_reason: Option<String>,
+    pub logprobs: Option<Logprobs>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ChunkDelta {
+    pub role: Option<String>,
+    pub content: Option<String>,
+    pub tool_calls: Option<Vec<ToolCallDelta>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ToolCallDelta {
+    pub index: usize,
+    pub id: Option<String>,
+    pub function: Option<FunctionCallDelta>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FunctionCallDelta {
+    pub name: Option<String>,
+    pub arguments: Option<String>,
+}
+
+/// Rebuilds a complete response from streamed chunks.
+#[derive(Default)]
+pub struct ChunkAccumulator {
+    choices: BTreeMap<u32, ChoiceAccumulator>,
+    usage: Option<Usage>,
+}
+
+/// The streamed parts of one choice.
+#[derive(Default)]
+struct ChoiceAccumulator {
+    role: Option<String>,
+    content: String,
+    tool_calls: Vec<ToolCall>,
+    finish_reason: Option<String>,
+    logprobs: Option<Logprobs>,
+}
+
+impl ChunkAccumulator {
+    pub fn push(&mut self, chunk: ChatCompletionChunk) {
+        if let Some(usage) = chunk.usage {
+            self.usage = Some(usage.into());
+        }
+
+        for choice in chunk.choices {
+            self.choices.entry(choice.index).or_default().push(choice);
+        }
+    }
+
+    pub fn finish(mut self) -> ChatCompletionResponse {
+        // An empty stream still yields an empty assistant message.
+        self.choices.entry(0).or_default();
+        let choices = self
+            .choices
+            .into_iter()
+            .map(|(index, choice)| choice.finish(index))
+            .collect();
+
+        ChatCompletionResponse::from_choices(choices, self.usage).expect("choice 0 is present")
+    }
+}
+
+impl ChoiceAccumulator {
+    fn push(&mut self, choice: ChunkChoice) {
+        if let Some(role) = choice.delta.role {
+            self.role = Some(role);
+        }
+        if let Some(content) = choice.delta.content {
+            self.content.push_str(&content);
+        }
+        for delta in choice.delta.tool_calls.unwrap_or_default() {
+            if self.tool_calls.len() <= delta.index {
+                self.tool_calls.resize(
+                    delta.index + 1,
+                    ToolCall {
+                        id: String::new(),
+                        kind: "function".into(),
+                        function: FunctionCall {
+                            name: String::new(),
+                            arguments: String::new(),
+                        },
+                    },
+                );
+            }
+            let call = &mut self.tool_calls[delta.index];
+            if let Some(id) = delta.id {
+                call.id = id;
+            }
+            if let Some(function) = delta.function {
+                if let Some(name) = function.name {
+                    call.function.name.push_str(&name);
+                }
+                if let Some(arguments) = function.arguments {
+                    call.function.arguments.push_str(&arguments);
+                }
+            }
+        }
+        if let Some(logprobs) = choice.logprobs {
+            self.logprobs
+                .get_or_insert_with(Logprobs::default)
+                .content
+                .extend(logprobs.content);
+        }
+        if choice.finish_reason.is_some() {
+            self.finish_reason = choice.finish_reason;
+        }
+    }
+
+    fn finish(self, index: u32) -> UnifiedChoice {
+        let mut message = Message::new(
+            self.role.unwrap_or_else(|| "assistant".into()),
+            self.content,
+        );
+        message.tool_calls = (!self.tool_calls.is_empty()).then_some(self.tool_calls);
+
+        UnifiedChoice {
+            index,
+            message,
+            finish_reason: self.finish_reason,
+            logprobs: self.logprobs,
+            extensions: None,
+        }
+    }
+}
+
+/// Response of `/embeddings`.
+#[derive(Debug, Deserialize)]
+pub struct OpenAiEmbeddingResponse {
+    pub data: Vec<EmbeddingData>,
+    pub usage: Option<EmbeddingUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmbeddingData {
+    #[serde(default)]
+    pub index: usize,
+    pub embedding: Vec<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmbeddingUsage {
+    #[serde(default)]
+    pub prompt_tokens: u32,
+}
+
+impl From<OpenAiEmbeddingResponse> for EmbeddingResponse {
+    fn from(mut response: OpenAiEmbeddingResponse) -> Self {
+        response.data.sort_by_key(|data| data.index);
+        Self {
+            embeddings: response
+                .data
+                .into_iter()
+                .map(|data| data.embedding)
+                .collect(),
+            usage: response
+                .usage
+                .map(|usage| Usage::new(usage.prompt_tokens, 0)),
+        }
+    }
+}
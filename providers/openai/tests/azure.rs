@@ -0,0 +1,124 @@
+use {
+    axum::{extract::State, http::StatusCode, routing::post, Json, Router},
+    serde_json::{json, Value},
+    std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    },
+    topkio_openai::{AzureOpenAiBackend, AzureResource},
+    topkio_primitive::{
+        api::{ChatCompletionRequest, Message, UnifiedLlmApi},
+        error::ProviderError,
+    },
+};
+
+/// A resource answering every request with `status`, counting the requests.
+async fn spawn_resource(status: StatusCode) -> (String, Arc<AtomicUsize>) {
+    async fn chat(
+        State((status, calls)): State<(StatusCode, Arc<AtomicUsize>)>,
+    ) -> (StatusCode, Json<Value>) {
+        calls.fetch_add(1, Ordering::SeqCst);
+        if !status.is_success() {
+            let error = json!({"error": {"message": format!("status {}", status.as_u16())}});
+            return (status, Json(error));
+        }
+        let response = json!({
+            "id": "chatcmpl-1",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "Hello."},
+                "finish_reason": "stop"
+            }],
+            "usage": {"prompt_tokens": 5, "completion_tokens": 2, "total_tokens": 7}
+        });
+        (status, Json(response))
+    }
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = Router::new()
+        .route(
+            "/openai/deployments/{deployment}/chat/completions",
+            post(chat),
+        )
+        .with_state((status, calls.clone()));
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    (format!("http://{}", addr), calls)
+}
+
+fn backend(urls: &[&str]) -> AzureOpenAiBackend {
+    let resources = urls
+        .iter()
+        .map(|url| AzureResource {
+            url: url.to_string(),
+            api_key: "test-key".to_string(),
+            deployments: HashMap::new(),
+        })
+        .collect();
+    AzureOpenAiBackend::new("2024-10-21".to_string(), resources)
+}
+
+fn request() -> ChatCompletionRequest {
+    ChatCompletionRequest {
+        model: "azure:gpt-4o".to_string(),
+        messages: vec![Message::new("user", "Hi")],
+        stream: Some(false),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn fails_over_on_unavailable_resources() {
+    let (down, down_calls) = spawn_resource(StatusCode::SERVICE_UNAVAILABLE).await;
+    let (up, up_calls) = spawn_resource(StatusCode::OK).await;
+    let backend = backend(&[&down, &up]);
+
+    // Whichever resource the rotation starts at, both requests succeed.
+    for _ in 0..2 {
+        let response = backend.chat_completion("gpt-4o", request()).await.unwrap();
+        assert_eq!(response.message.content, "Hello.");
+    }
+    assert_eq!(up_calls.load(Ordering::SeqCst), 2);
+    assert_eq!(down_calls.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn invalid_requests_are_not_retried() {
+    let (first, first_calls) = spawn_resource(StatusCode::BAD_REQUEST).await;
+    let (second, second_calls) = spawn_resource(StatusCode::BAD_REQUEST).await;
+    let backend = backend(&[&first, &second]);
+
+    let error = backend
+        .chat_completion("gpt-4o", request())
+        .await
+        .unwrap_err();
+
+    assert!(matches!(
+        error.downcast_ref::<ProviderError>(),
+        Some(ProviderError::InvalidRequest(_))
+    ));
+    let calls = first_calls.load(Ordering::SeqCst) + second_calls.load(Ordering::SeqCst);
+    assert_eq!(calls, 1);
+}
+
+#[tokio::test]
+async fn reports_the_first_error_when_every_resource_fails() {
+    let (limited, _) = spawn_resource(StatusCode::TOO_MANY_REQUESTS).await;
+    let backend = backend(&[&limited, "http://127.0.0.1:1"]);
+
+    let error = backend
+        .chat_completion("gpt-4o", request())
+        .await
+        .unwrap_err();
+
+    // The rotation starts at the first resource.
+    assert!(matches!(
+        error.downcast_ref::<ProviderError>(),
+        Some(ProviderError::RateLimited { .. })
+    ));
+}
@@ -0,0 +1,68 @@
+//! Streamed chat completions against a mock OpenAI server.
+
+use {
+    axum::{routing::post, Json, Router},
+    futures_util::StreamExt,
+    serde_json::Value,
+    std::sync::{Arc, Mutex},
+    topkio_openai::OpenAiBackend,
+    topkio_primitive::api::{ChatCompletionRequest, Message, UnifiedLlmApi},
+};
+
+/// A streamed answer with a tool call whose arguments come in two pieces,
+/// and the usage in a chunk of its own.
+const EVENTS: &str = r#"data: {"id":"chatcmpl-1","choices":[{"index":0,"delta":{"role":"assistant","content":"Let me "},"finish_reason":null}]}
+
+data: {"id":"chatcmpl-1","choices":[{"index":0,"delta":{"content":"check."},"finish_reason":null}]}
+
+data: {"id":"chatcmpl-1","choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"get_weather","arguments":"{\"city\":"}}]},"finish_reason":null}]}
+
+data: {"id":"chatcmpl-1","choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"\"Paris\"}"}}]},"finish_reason":null}]}
+
+data: {"id":"chatcmpl-1","choices":[{"index":0,"delta":{},"finish_reason":"tool_calls"}]}
+
+data: {"id":"chatcmpl-1","choices":[],"usage":{"prompt_tokens":9,"completion_tokens":4,"total_tokens":13}}
+
+data: [DONE]
+
+"#;
+
+#[tokio::test]
+async fn streams_chat_completions() {
+    let bodies = Arc::new(Mutex::new(vec![]));
+    let recorded = bodies.clone();
+    let chat = move |Json(body): Json<Value>| async move {
+        recorded.lock().unwrap().push(body);
+        EVENTS
+    };
+    let app = Router::new().route("/chat/completions", post(chat));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let backend = OpenAiBackend::new(url, "sk-test".into());
+    let request = ChatCompletionRequest {
+        model: "openai:gpt-4o".into(),
+        messages: vec![Message::new("user", "Weather in Paris?")],
+        ..Default::default()
+    };
+    let deltas: Vec<_> = backend
+        .chat_completion_stream("gpt-4o", request)
+        .await
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+        .await;
+
+    let body = &bodies.lock().unwrap()[0];
+    assert_eq!(body["stream"], true);
+    assert_eq!(body["stream_options"]["include_usage"], true);
+
+    let content: Vec<&str> = deltas.iter().map(|delta| delta.content.as_str()).collect();
+    assert_eq!(content, ["Let me ", "check.", ""]);
+    let last = deltas.last().unwrap();
+    assert_eq!(last.finish_reason.as_deref(), Some("tool_calls"));
+    assert_eq!(last.tool_calls[0].id, "call_1");
+    assert_eq!(last.tool_calls[0].function.arguments, r#"{"city":"Paris"}"#);
+    assert_eq!(last.usage.as_ref().unwrap().total_tokens, 13);
+}
@@ -0,0 +1,179 @@
+//! Several candidates (`n`) for backends that generate only one, by sending
+//! the request that many times in parallel.
+
+use {
+    crate::ApiError,
+    futures_util::future::join_all,
+    topkio_primitive::api::{
+        ChatCompletionRequest, ChatCompletionResponse, Choice, UnifiedLlmApi, Usage,
+    },
+};
+
+/// Parallel requests made for a single emulated `n`, at most.
+const MAX_EMULATED_CANDIDATES: u32 = 8;
+
+/// Complete `request`, generating its `n` candidates.
+///
+/// Returns the usage alongside the result: when some of the emulated calls
+/// fail, the usage of those that finished is still counted.
+pub async fn complete(
+    backend: &dyn UnifiedLlmApi,
+    model: &str,
+    mut request: ChatCompletionRequest,
+) -> (Result<ChatCompletionResponse, ApiError>, Option<Usage>) {
+    let n = match request.n {
+        Some(0) => {
+            return (
+                Err(ApiError::BadRequest("n must be at least 1".into())),
+                None,
+            )
+        }
+        Some(n) if n > 1 && !backend.supports_n() => n,
+        _ => {
+            let result = backend
+                .chat_completion(model, request)
+                .await
+                .map_err(ApiError::from_backend);
+            let usage = result.as_ref().ok().and_then(|r| r.usage.clone());
+            return (result, usage);
+        }
+    };
+    if n > MAX_EMULATED_CANDIDATES {
+        let message = format!(
+            "n may be at most {} for {}",
+            MAX_EMULATED_CANDIDATES, request.model
+        );
+        return (Err(ApiError::BadRequest(message)), None);
+    }
+
+    request.n = None;
+    let results = join_all((0..n).map(|_| backend.chat_completion(model, request.clone()))).await;
+
+    let mut usage: Option<Usage> = None;
+    for response in results.iter().flatten() {
+        if let Some(response_usage) = &response.usage {
+            *usage.get_or_insert_with(Usage::default) += response_usage;
+        }
+    }
+
+    let mut choices = vec![];
+    for (result, index) in results.into_iter().zip(0..) {
+        let response = match result {
+            Ok(response) => response,
+            Err(e) => return (Err(ApiError::from_backend(e)), usage),
+        };
+        choices.push(Choice {
+            index,
+            message: response.message,
+            finish_reason: response.finish_reason,
+            logprobs: response.logprobs,
+            extensions: response.extensions,
+        });
+    }
+
+    let response =
+        ChatCompletionResponse::from_choices(choices, usage.clone()).expect("n > 1 choices");
+    (Ok(response), usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::sync::atomic::{AtomicU32, Ordering},
+        topkio_primitive::api::Message,
+    };
+
+    /// A backend generating one candidate per call, whose call number
+    /// `failing_call` fails.
+    #[derive(Default)]
+    struct Mock {
+        calls: AtomicU32,
+        failing_call: Option<u32>,
+    }
+
+    impl Mock {
+        /// A backend whose second call fails.
+        fn flaky() -> Self {
+            Self {
+                failing_call: Some(1),
+                ..Default::default()
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl UnifiedLlmApi for Mock {
+        async fn chat_completion(
+            &self,
+            _model: &str,
+            request: ChatCompletionRequest,
+        ) -> anyhow::Result<ChatCompletionResponse> {
+            assert_eq!(request.n.unwrap_or(1), 1, "one candidate per call");
+            if Some(self.calls.fetch_add(1, Ordering::SeqCst)) == self.failing_call {
+                anyhow::bail!("connection reset");
+            }
+            let choice = Choice {
+                index: 0,
+                message: Message::new("assistant", "Hi"),
+                finish_reason: Some("stop".into()),
+                logprobs: None,
+                extensions: None,
+            };
+            Ok(
+                ChatCompletionResponse::from_choices(vec![choice], Some(Usage::new(10, 2)))
+                    .unwrap(),
+            )
+        }
+    }
+
+    fn request(n: u32) -> ChatCompletionRequest {
+        ChatCompletionRequest {
+            model: "mock:model".into(),
+            messages: vec![Message::new("user", "Hello")],
+            n: Some(n),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn counts_the_usage_of_finished_candidates_on_failure() {
+        let backend = Mock::flaky();
+        let (result, usage) = complete(&backend, "model", request(3)).await;
+
+        assert!(result.is_err());
+        assert_eq!(backend.calls.load(Ordering::SeqCst), 3);
+        let usage = usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (20, 4));
+    }
+
+    #[tokio::test]
+    async fn emulates_n() {
+        let backend = Mock::default();
+        let (result, usage) = complete(&backend, "model", request(1)).await;
+        assert!(result.unwrap().choices.is_empty());
+        assert_eq!(usage.unwrap().prompt_tokens, 10);
+
+        let (result, _) = complete(&backend, "model", request(9)).await;
+        assert!(matches!(result, Err(ApiError::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn emulates_n_with_one_call_per_candidate() {
+        let backend = Mock::default();
+        assert!(!backend.supports_n());
+        let (result, usage) = complete(&backend, "model", request(3)).await;
+
+        assert_eq!(backend.calls.load(Ordering::SeqCst), 3);
+        let response = result.unwrap();
+        let indices: Vec<u32> = response.choices.iter().map(|choice| choice.index).collect();
+        assert_eq!(indices, [0, 1, 2]);
+        assert!(response
+            .choices
+            .iter()
+            .all(|choice| choice.message.content.text() == "Hi"));
+        let usage = usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (30, 6));
+        assert_eq!(response.usage.unwrap().total_tokens, 36);
+    }
+}
@@ -0,0 +1,237 @@
+//! Circuit breakers: backends failing repeatedly are left alone for a while
+//! instead of being sent every request.
+
+use {
+    crate::ApiError,
+    serde::Serialize,
+    std::{
+        collections::HashMap,
+        sync::Mutex,
+        time::{Duration, Instant},
+    },
+    topkio_primitive::{config::CircuitBreakerConfig, error::ProviderError},
+};
+
+/// The circuit of every backend, by backend name. Kept across reloads.
+#[derive(Default)]
+pub struct Circuits {
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+#[derive(Default)]
+struct Circuit {
+    consecutive_failures: u32,
+    last_error: Option<String>,
+    /// When the circuit last opened. It stays set while requests probe the
+    /// backend, until one succeeds.
+    opened_at: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    /// Requests are rejected.
+    Open,
+    /// The open period is over; requests are let through to probe the
+    /// backend, and the next failure opens the circuit again.
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CircuitStatus {
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    /// Seconds until an open circuit lets requests through again.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_in_seconds: Option<u64>,
+}
+
+impl Circuits {
+    /// Reject requests to `backend` while its circuit is open.
+    pub fn check(
+        &self,
+        config: Option<&CircuitBreakerConfig>,
+        backend: &str,
+    ) -> Result<(), ApiError> {
+        match self.status(config, backend) {
+            CircuitStatus {
+                state: CircuitState::Open,
+                retry_in_seconds,
+                ..
+            } => Err(ApiError::CircuitOpen(
+                backend.to_string(),
+                retry_in_seconds.unwrap_or(1),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Record the outcome of a request to `backend`.
+    ///
+    /// Only failures of the backend itself count; rejected requests and rate
+    /// limits say nothing about its health.
+    pub fn record(
+        &self,
+        config: Option<&CircuitBreakerConfig>,
+        backend: &str,
+        error: Option<&ApiError>,
+    ) {
+        let mut circuits = self.circuits.lock().expect("circuits lock");
+        let circuit = circuits.entry(backend.to_string()).or_default();
+
+        match error {
+            None => *circuit = Circuit::default(),
+            Some(error) if is_backend_failure(error) => {
+                circuit.consecutive_failures += 1;
+                circuit.last_error = Some(error.to_string());
+                let threshold = config.map(|config| config.failure_threshold);
+                if threshold.is_some_and(|threshold| circuit.consecutive_failures >= threshold) {
+                    if circuit.opened_at.is_none() {
+                        eprintln!(
+                            "Circuit of {} opened after {} consecutive failures",
+                            backend, circuit.consecutive_failures
+                        );
+                    }
+                    circuit.opened_at = Some(Instant::now());
+                }
+            }
+            Some(_) => {}
+        }
+    }
+
+    /// Close the circuit of `backend`, e.g. when an operator re-enables it.
+    pub fn reset(&self, backend: &str) {
+        let mut circuits = self.circuits.lock().expect("circuits lock");
+        circuits.remove(backend);
+    }
+
+    pub fn status(&self, config: Option<&CircuitBreakerConfig>, backend: &str) -> CircuitStatus {
+        let circuits = self.circuits.lock().expect("circuits lock");
+        let Some(circuit) = circuits.get(backend) else {
+            return CircuitStatus {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+                last_error: None,
+                retry_in_seconds: None,
+            };
+        };
+
+        let open_for = config.map(|config| Duration::from_secs(config.open_seconds));
+        let (state, retry_in) = match (circuit.opened_at, open_for) {
+            (Some(opened_at), Some(open_for)) => match open_for.checked_sub(opened_at.elapsed()) {
+                Some(remaining) => (CircuitState::Open, Some(remaining)),
+                None => (CircuitState::HalfOpen, None),
+            },
+            _ => (CircuitState::Closed, None),
+        };
+        CircuitStatus {
+            state,
+            consecutive_failures: circuit.consecutive_failures,
+            last_error: circuit.last_error.clone(),
+            // Round up, so clients never retry before the circuit lets them.
+            retry_in_seconds: retry_in.map(|remaining| remaining.as_secs() + 1),
+        }
+    }
+}
+
+fn is_backend_failure(error: &ApiError) -> bool {
+    match error {
+        ApiError::BackendError(_) => true,
+        ApiError::Provider(e) => matches!(
+            e,
+            ProviderError::Unavailable(_) | ProviderError::Timeout(_) | ProviderError::Upstream(_)
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: CircuitBreakerConfig = CircuitBreakerConfig {
+        failure_threshold: 2,
+        open_seconds: 30,
+    };
+
+    fn unavailable() -> ApiError {
+        ApiError::Provider(ProviderError::Unavailable("503".into()))
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let circuits = Circuits::default();
+        circuits.record(Some(&CONFIG), "ollama", Some(&unavailable()));
+        assert!(circuits.check(Some(&CONFIG), "ollama").is_ok());
+
+        circuits.record(Some(&CONFIG), "ollama", Some(&unavailable()));
+        let status = circuits.status(Some(&CONFIG), "ollama");
+        assert_eq!(status.state, CircuitState::Open);
+        assert_eq!(status.consecutive_failures, 2);
+        assert!(status.retry_in_seconds.is_some_and(|s| s > 0 && s <= 30));
+        assert!(matches!(
+            circuits.check(Some(&CONFIG), "ollama"),
+            Err(ApiError::CircuitOpen(backend, _)) if backend == "ollama"
+        ));
+        assert!(circuits.check(Some(&CONFIG), "gemini").is_ok());
+    }
+
+    #[test]
+    fn success_and_reset_close_the_circuit() {
+        let circuits = Circuits::default();
+        circuits.record(Some(&CONFIG), "ollama", Some(&unavailable()));
+        circuits.record(None, "ollama", None);
+        circuits.record(Some(&CONFIG), "ollama", Some(&unavailable()));
+        assert_eq!(
+            circuits.status(Some(&CONFIG), "ollama").state,
+            CircuitState::Closed
+        );
+
+        circuits.record(Some(&CONFIG), "ollama", Some(&unavailable()));
+        circuits.reset("ollama");
+        let status = circuits.status(Some(&CONFIG), "ollama");
+        assert_eq!(status.state, CircuitState::Closed);
+        assert_eq!(status.consecutive_failures, 0);
+    }
+
+    #[test]
+    fn client_errors_do_not_count() {
+        let circuits = Circuits::default();
+        for _ in 0..3 {
+            let error = ApiError::Provider(ProviderError::InvalidRequest("bad".into()));
+            circuits.record(Some(&CONFIG), "ollama", Some(&error));
+        }
+        assert_eq!(
+            circuits
+                .status(Some(&CONFIG), "ollama")
+                .consecutive_failures,
+            0
+        );
+    }
+
+    #[test]
+    fn half_open_after_the_open_period() {
+        let config = CircuitBreakerConfig {
+            failure_threshold: 1,
+            open_seconds: 0,
+        };
+        let circuits = Circuits::default();
+        circuits.record(Some(&config), "ollama", Some(&unavailable()));
+        assert_eq!(
+            circuits.status(Some(&config), "ollama").state,
+            CircuitState::HalfOpen
+        );
+        assert!(circuits.check(Some(&config), "ollama").is_ok());
+    }
+
+    #[test]
+    fn disabled_without_config() {
+        let circuits = Circuits::default();
+        for _ in 0..10 {
+            circuits.record(None, "ollama", Some(&unavailable()));
+        }
+        assert!(circuits.check(None, "ollama").is_ok());
+    }
+}
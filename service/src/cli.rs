@@ -0,0 +1,56 @@
+use {
+    clap::{Parser, Subcommand},
+    topkio_service::{Options, DEFAULT_CONFIG_PATH},
+};
+
+/// Topkio - open source LLM gateway
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Path of the config file
+    #[arg(short, long, env = "TOPKIO_CONFIG", default_value = DEFAULT_CONFIG_PATH, global = true)]
+    pub config: String,
+
+    /// Override server.host
+    #[arg(long, env = "TOPKIO_HOST", global = true)]
+    pub host: Option<String>,
+
+    /// Override server.port
+    #[arg(long, env = "TOPKIO_PORT", global = true, value_parser = clap::value_parser!(u16).range(1..))]
+    pub port: Option<u16>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the gateway (default)
+    Serve,
+    /// Validate the config file and report every problem found
+    CheckConfig,
+    /// Print the JSON Schema of the config file
+    Schema,
+    /// List the models of every configured backend
+    ListModels,
+    /// Send a one-shot prompt to a configured backend
+    Chat {
+        /// Model as "backend:model_name", e.g. "gemini:gemini-2.0-flash"
+        model: String,
+        /// The prompt to send
+        prompt: String,
+        /// Optional system prompt
+        #[arg(short, long)]
+        system: Option<String>,
+    },
+}
+
+impl Cli {
+    pub fn options(&self) -> Options {
+        Options {
+            config_path: self.config.clone(),
+            host: self.host.clone(),
+            port: self.port,
+        }
+    }
+}
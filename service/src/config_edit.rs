@@ -0,0 +1,245 @@
+use {
+    crate::{ApiError, AppState, Gateway},
+    std::{collections::hash_map::Entry, sync::Arc},
+    toml_edit::{value, Array, DocumentMut, Item, Table},
+    topkio_primitive::config::TopkioConfig,
+};
+
+/// A runtime change to the config, made through the admin API.
+#[derive(Debug, Clone)]
+pub enum ConfigEdit {
+    SetEnabled {
+        provider: String,
+        enabled: bool,
+    },
+    SetModels {
+        provider: String,
+        models: Vec<String>,
+    },
+    SetAlias {
+        alias: String,
+        target: String,
+    },
+    RemoveAlias {
+        alias: String,
+    },
+    AddApiKey {
+        key: String,
+    },
+    RemoveApiKey {
+        key: String,
+    },
+}
+
+impl ConfigEdit {
+    /// The same edit, naming its provider like the config does: backend
+    /// names are case-insensitive.
+    fn named_as_configured(mut self, config: &TopkioConfig) -> Result<Self, ApiError> {
+        if let Self::SetEnabled { provider, .. } | Self::SetModels { provider, .. } = &mut self {
+            *provider = config
+                .providers
+                .keys()
+                .find(|name| name.eq_ignore_ascii_case(provider))
+                .ok_or_else(|| ApiError::NotFound(format!("backend {}", provider)))?
+                .clone();
+        }
+        Ok(self)
+    }
+
+    fn apply(&self, config: &mut TopkioConfig) -> Result<(), ApiError> {
+        match self {
+            Self::SetEnabled { provider, enabled } => {
+                provider_mut(config, provider)?.enabled = *enabled;
+            }
+            Self::SetModels { provider, models } => {
+                provider_mut(config, provider)?.supported_models = models.clone();
+            }
+            Self::SetAlias { alias, target } => {
+                config.aliases.insert(alias.clone(), target.clone());
+            }
+            Self::RemoveAlias { alias } => {
+                config
+                    .aliases
+                    .remove(alias)
+                    .ok_or_else(|| ApiError::NotFound(format!("alias {}", alias)))?;
+            }
+            Self::AddApiKey { key } => {
+                if !config.auth.api_keys.contains(key) {
+                    config.auth.api_keys.push(key.clone());
+                }
+            }
+            Self::RemoveApiKey { key } => {
+                let before = config.auth.api_keys.len();
+                config.auth.api_keys.retain(|k| k != key);
+                if config.auth.api_keys.len() == before {
+                    return Err(ApiError::NotFound("API key".into()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Apply the same change to the config file, keeping its formatting.
+    fn apply_to_document(&self, document: &mut DocumentMut) {
+        match self {
+            Self::SetEnabled { provider, enabled } => {
+                table_mut(document, &["providers", provider])["enabled"] = value(*enabled);
+            }
+            Self::SetModels { provider, models } => {
+                table_mut(document, &["providers", provider])["supported_models"] =
+                    value(models.iter().collect::<Array>());
+            }
+            Self::SetAlias { alias, target } => {
+                table_mut(document, &["aliases"])[alias.as_str()] = value(target);
+            }
+            Self::RemoveAlias { alias } => {
+                table_mut(document, &["aliases"]).remove(alias);
+            }
+            // Edit the array in place, so keys read from the environment
+            // are not written to the file.
+            Self::AddApiKey { key } => {
+                let keys = &mut table_mut(document, &["auth"])["api_keys"];
+                match keys.as_array_mut() {
+                    Some(keys) => keys.push(key.as_str()),
+                    None => *keys = value(Array::from_iter([key.as_str()])),
+                }
+            }
+            Self::RemoveApiKey { key } => {
+                if let Some(keys) = table_mut(document, &["auth"])["api_keys"].as_array_mut() {
+                    keys.retain(|k| k.as_str() != Some(key.as_str()));
+                }
+            }
+        }
+    }
+}
+
+fn provider_mut<'a>(
+    config: &'a mut TopkioConfig,
+    provider: &str,
+) -> Result<&'a mut topkio_primitive::config::ProviderConfig, ApiError> {
+    config
+        .providers
+        .iter_mut()
+        .find(|(name, _)| name.eq_ignore_ascii_case(provider))
+        .map(|(_, config)| config)
+        .ok_or_else(|| ApiError::NotFound(format!("backend {}", provider)))
+}
+
+fn table_mut<'a>(document: &'a mut DocumentMut, path: &[&str]) -> &'a mut Table {
+    let mut table = document.as_table_mut();
+    for key in path {
+        let item = table
+            .entry(key)
+            .or_insert_with(|| Item::Table(Table::new()));
+        if !item.is_table() {
+            *item = Item::Table(Table::new());
+        }
+        table = item.as_table_mut().expect("item is a table");
+    }
+    table
+}
+
+/// Apply `edit` to the running gateway and, if `persist` is set, to the
+/// config file.
+///
+/// Changes that are not persisted are lost on the next reload.
+pub async fn update_config(
+    state: &AppState,
+    edit: ConfigEdit,
+    persist: bool,
+) -> Result<(), ApiError> {
+    let _guard = state.edit_lock.lock().await;
+
+    let current = state.gateway();
+    let mut config = current.config.clone();
+    let edit = edit.named_as_configured(&config)?;
+    edit.apply(&mut config)?;
+
+    let mut backends = current.backends.clone();
+    if let ConfigEdit::SetEnabled {
+        provider,
+        enabled: true,
+    } = &edit
+    {
+        // Providers disabled at startup have no backend yet.
+        if let Entry::Vacant(entry) = backends.entry(provider.to_lowercase()) {
+            let backend = state
+                .registry
+                .create(provider, &config.providers[provider])
+                .await
+                .map_err(|e| ApiError::BackendError(e.to_string()))?;
+            entry.insert(backend);
+        }
+    }
+
+    if persist {
+        persist_edit(&state.options.config_path, &edit)
+            .map_err(|e| ApiError::ConfigError(e.to_string()))?;
+    }
+
+    state.gateway.store(Arc::new(Gateway { backends, config }));
+
+    Ok(())
+}
+
+fn persist_edit(path: &str, edit: &ConfigEdit) -> anyhow::Result<()> {
+    let mut document: DocumentMut = std::fs::read_to_string(path)?.parse()?;
+    edit.apply_to_document(&mut document);
+
+    // Write a sibling file and rename it, so readers (and the config watcher)
+    // never see a partially written config.
+    let tmp = format!("{}.tmp", path);
+    std::fs::write(&tmp, document.to_string())?;
+    std::fs::rename(&tmp, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+[server]
+host = "127.0.0.1"
+port = 3000
+
+[logging]
+file_path = "topkio.log"
+
+[providers.ollama]
+url = "http://localhost:11434"
+"#;
+
+    #[test]
+    fn provider_names_are_case_insensitive() {
+        let mut config: TopkioConfig = toml::from_str(CONFIG).unwrap();
+        let mut document: DocumentMut = CONFIG.parse().unwrap();
+
+        let edit = ConfigEdit::SetEnabled {
+            provider: "Ollama".into(),
+            enabled: false,
+        }
+        .named_as_configured(&config)
+        .unwrap();
+        edit.apply(&mut config).unwrap();
+        edit.apply_to_document(&mut document);
+
+        assert!(!config.providers["ollama"].enabled);
+        assert_eq!(
+            document["providers"]["ollama"]["enabled"].as_bool(),
+            Some(false)
+        );
+        assert!(document["providers"].get("Ollama").is_none());
+
+        let unknown = ConfigEdit::SetModels {
+            provider: "gemini".into(),
+            models: vec![],
+        };
+        assert!(matches!(
+            unknown.named_as_configured(&config),
+            Err(ApiError::NotFound(_))
+        ));
+    }
+}
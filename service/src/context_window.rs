@@ -0,0 +1,140 @@
+//! Rejects or truncates requests that don't fit the model's context window,
+//! instead of letting them fail upstream.
+
+use {
+    crate::ApiError,
+    topkio_primitive::{
+        api::{ChatCompletionRequest, UnifiedLlmApi},
+        config::{ContextOverflow, TopkioConfig},
+        tokens,
+    },
+};
+
+/// Local estimates above this share of the limit are re-counted by the
+/// backend, when it can count tokens.
+const RECOUNT_THRESHOLD: f64 = 0.8;
+
+/// Check that the prompt of `request`, plus `max_tokens`, fits the context
+/// window of `model` on `backend_name`. Models with an unknown context
+/// window are not checked.
+pub async fn enforce(
+    config: &TopkioConfig,
+    backend: &dyn UnifiedLlmApi,
+    backend_name: &str,
+    model: &str,
+    mut request: ChatCompletionRequest,
+) -> Result<ChatCompletionRequest, ApiError> {
+    let Some(window) = config.context_window(backend_name, model) else {
+        return Ok(request);
+    };
+    let limit = window.saturating_sub(request.max_tokens.unwrap_or(0));
+
+    let count = tokens::count_request(model, &request);
+    let mut prompt_tokens = count.tokens;
+    let mut exact = count.exact;
+    if !exact && prompt_tokens as f64 > limit as f64 * RECOUNT_THRESHOLD {
+        match backend.count_tokens(model, &request).await {
+            Ok(Some(tokens)) => {
+                prompt_tokens = tokens;
+                exact = true;
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("Failed to count tokens with {}: {}", backend_name, e),
+        }
+    }
+    if prompt_tokens <= limit {
+        return Ok(request);
+    }
+
+    let exceeded = || {
+        ApiError::ContextLengthExceeded(format!(
+            "the prompt has {}{} tokens, but {}:{} accepts at most {} \
+             (context window of {} minus max_tokens)",
+            if exact { "" } else { "about " },
+            prompt_tokens,
+            backend_name,
+            model,
+            limit,
+            window
+        ))
+    };
+    match config.context.overflow {
+        ContextOverflow::Reject => Err(exceeded()),
+        ContextOverflow::Truncate => {
+            // Leave room for the difference between the local count and the
+            // backend's.
+            let local_limit = (limit as u64 * count.tokens as u64 / prompt_tokens as u64) as u32;
+            match tokens::truncate_history(model, &mut request, local_limit) {
+                true => Ok(request),
+                false => Err(exceeded()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        topkio_primitive::api::{ChatCompletionResponse, Message},
+    };
+
+    /// A backend counting `tokens` tokens in every prompt, when set.
+    struct Counting {
+        tokens: Option<u32>,
+    }
+
+    #[async_trait::async_trait]
+    impl UnifiedLlmApi for Counting {
+        async fn chat_completion(
+            &self,
+            _model: &str,
+            _request: ChatCompletionRequest,
+        ) -> anyhow::Result<ChatCompletionResponse> {
+            unreachable!("only tokens are counted")
+        }
+
+        async fn count_tokens(
+            &self,
+            _model: &str,
+            _request: &ChatCompletionRequest,
+        ) -> anyhow::Result<Option<u32>> {
+            Ok(self.tokens)
+        }
+    }
+
+    async fn rejection(tokens: Option<u32>) -> String {
+        let config: TopkioConfig = toml::from_str(
+            r#"
+[server]
+host = "127.0.0.1"
+port = 3000
+
+[logging]
+file_path = "topkio.log"
+
+[providers.mock]
+url = "http://localhost:1"
+context_windows = { "model" = 100 }
+"#,
+        )
+        .unwrap();
+        let request = ChatCompletionRequest {
+            model: "mock:model".into(),
+            messages: vec![Message::new("user", "word ".repeat(200))],
+            ..Default::default()
+        };
+        match enforce(&config, &Counting { tokens }, "mock", "model", request).await {
+            Err(ApiError::ContextLengthExceeded(message)) => message,
+            result => panic!("unexpected result: {:?}", result.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn estimates_are_only_reported_as_such() {
+        assert!(rejection(None).await.starts_with("the prompt has about "));
+        assert!(rejection(Some(500))
+            .await
+            .starts_with("the prompt has 500 tokens"));
+    }
+}
@@ -1,8 +1,13 @@
 #![allow(dead_code)]
 
-use axum::{
-    http::StatusCode,
-    response::{IntoResponse, Response},
+use {
+    axum::{
+        http::{header, StatusCode},
+        response::{IntoResponse, Response},
+        Json,
+    },
+    serde_json::json,
+    topkio_primitive::error::ProviderError,
 };
 
 #[derive(Debug, thiserror::Error)]
@@ -21,15 +26,193 @@ pub enum ApiError {
 
     #[error("Invalid model format: {0}")]
     InvalidModelFormat(String),
+
+    #[error("Backend unavailable: {0} is drained")]
+    BackendDrained(String),
+
+    /// The backend and the seconds until its circuit lets requests through.
+    #[error("Backend unavailable: the circuit of {0} is open after repeated failures")]
+    CircuitOpen(String, u64),
+
+    #[error("Unauthorized")]
+    Unauthorized,
+
+    #[error("Not found: {0}")]
+    NotFound(String),
+
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Spend budget exhausted: {0}")]
+    SpendBudgetExceeded(String),
+
+    #[error("Token budget exhausted: {0}")]
+    TokenBudgetExceeded(String),
+
+    #[error("Context length exceeded: {0}")]
+    ContextLengthExceeded(String),
+
+    #[error("Storage error: {0}")]
+    StorageError(String),
+
+    #[error("Invalid structured output: {0}")]
+    InvalidStructuredOutput(String),
+
+    #[error(transparent)]
+    Provider(ProviderError),
+}
+
+impl ApiError {
+    /// Classify an error returned by a backend, keeping its upstream status
+    /// when the backend reported one.
+    pub fn from_backend(e: anyhow::Error) -> Self {
+        match e.downcast::<ProviderError>() {
+            Ok(e) => Self::Provider(e),
+            Err(e) => Self::BackendError(format!("{:#}", e)),
+        }
+    }
+
+    /// HTTP status, OpenAI error `type` and `code` of this error.
+    fn parts(&self) -> (StatusCode, &'static str, Option<&'static str>) {
+        match self {
+            Self::BackendNotConfigured(_) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "invalid_request_error",
+                Some("backend_not_configured"),
+            ),
+            Self::BackendDrained(_) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "server_error",
+                Some("backend_drained"),
+            ),
+            Self::CircuitOpen(..) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "server_error",
+                Some("circuit_open"),
+            ),
+            Self::UnsupportedModel(_) | Self::InvalidModelFormat(_) => (
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                Some("model_not_found"),
+            ),
+            Self::BadRequest(_) => (StatusCode::BAD_REQUEST, "invalid_request_error", None),
+            Self::ContextLengthExceeded(_) => (
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                Some("context_length_exceeded"),
+            ),
+            Self::Unauthorized => (
+                StatusCode::UNAUTHORIZED,
+                "authentication_error",
+                Some("invalid_api_key"),
+            ),
+            Self::Forbidden(_) => (StatusCode::FORBIDDEN, "permission_error", None),
+            Self::NotFound(_) => (StatusCode::NOT_FOUND, "not_found_error", None),
+            Self::SpendBudgetExceeded(_) => (
+                StatusCode::PAYMENT_REQUIRED,
+                "insufficient_quota",
+                Some("spend_budget_exceeded"),
+            ),
+            Self::TokenBudgetExceeded(_) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "insufficient_quota",
+                Some("token_budget_exceeded"),
+            ),
+            Self::BackendError(_) => (StatusCode::BAD_GATEWAY, "api_error", None),
+            Self::InvalidStructuredOutput(_) => (
+                StatusCode::BAD_GATEWAY,
+                "api_error",
+                Some("invalid_response_format"),
+            ),
+            Self::ConfigError(_) | Self::StorageError(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, "server_error", None)
+            }
+            Self::Provider(e) => match e {
+                // The gateway's upstream credentials are wrong, not the caller's.
+                ProviderError::Authentication(_) => (
+                    StatusCode::BAD_GATEWAY,
+                    "api_error",
+                    Some("upstream_authentication_failed"),
+                ),
+                ProviderError::RateLimited { .. } => (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    "rate_limit_error",
+                    Some("rate_limit_exceeded"),
+                ),
+                ProviderError::InvalidRequest(_) => {
+                    (StatusCode::BAD_REQUEST, "invalid_request_error", None)
+                }
+                ProviderError::ContextLength(_) => (
+                    StatusCode::BAD_REQUEST,
+                    "invalid_request_error",
+                    Some("context_length_exceeded"),
+                ),
+                ProviderError::ContentFiltered { .. } => (
+                    StatusCode::BAD_REQUEST,
+                    "invalid_request_error",
+                    Some("content_filter"),
+                ),
+                ProviderError::NotFound(_) => (
+                    StatusCode::NOT_FOUND,
+                    "invalid_request_error",
+                    Some("model_not_found"),
+                ),
+                ProviderError::Unavailable(_) => (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "server_error",
+                    Some("upstream_unavailable"),
+                ),
+                ProviderError::Timeout(_) => (
+                    StatusCode::GATEWAY_TIMEOUT,
+                    "server_error",
+                    Some("upstream_timeout"),
+                ),
+                ProviderError::Upstream(_) => (StatusCode::BAD_GATEWAY, "api_error", None),
+            },
+        }
+    }
 }
 
+impl From<rusqlite::Error> for ApiError {
+    fn from(e: rusqlite::Error) -> Self {
+        Self::StorageError(e.to_string())
+    }
+}
+
+/// Errors are answered with an OpenAI-style body:
+/// `{"error": {"message", "type", "param", "code"}}`.
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
-        let status = match self {
-            Self::BackendNotConfigured(_) => StatusCode::SERVICE_UNAVAILABLE,
-            Self::UnsupportedModel(_) => StatusCode::BAD_REQUEST,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        let (status, kind, code) = self.parts();
+
+        let mut error = json!({
+            "message": self.to_string(),
+            "type": kind,
+            "param": null,
+            "code": code,
+        });
+        if let Self::Provider(ProviderError::ContentFiltered {
+            details: Some(details),
+            ..
+        }) = &self
+        {
+            error["details"] = details.clone();
+        }
+
+        let mut response = (status, Json(json!({ "error": error }))).into_response();
+        let retry_after = match self {
+            Self::Provider(ProviderError::RateLimited { retry_after, .. }) => retry_after,
+            Self::CircuitOpen(_, seconds) => Some(seconds),
+            _ => None,
         };
-        (status, self.to_string()).into_response()
+        if let Some(seconds) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, seconds.into());
+        }
+        response
     }
 }
@@ -1,2 +1,11 @@
+pub mod admin;
+pub mod anthropic;
+pub mod caches;
 mod chat_completion;
-pub use chat_completion::handle_chat_completion;
+pub mod completions;
+pub mod count_tokens;
+pub mod models;
+pub mod ollama;
+pub mod usage;
+pub mod virtual_keys;
+pub use chat_completion::{handle_chat_completion, ModelIdentifier};
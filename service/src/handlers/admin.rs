@@ -0,0 +1,258 @@
+use {
+    crate::{
+        circuit::CircuitStatus,
+        config_edit::{update_config, ConfigEdit},
+        handlers::ModelIdentifier,
+        middleware::auth::mask,
+        reload::reload,
+        ApiError, AppState,
+    },
+    axum::{
+        extract::{Path, Query, State},
+        Json,
+    },
+    futures_util::future::join_all,
+    serde::{Deserialize, Serialize},
+    std::{collections::BTreeMap, sync::Arc},
+    tokio::time::{timeout, Duration, Instant},
+};
+
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PersistQuery {
+    /// Also write the change to the config file.
+    #[serde(default)]
+    persist: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackendStatus {
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    enabled: bool,
+    /// Whether a backend was built for this provider.
+    initialized: bool,
+    healthy: bool,
+    error: Option<String>,
+    latency_ms: Option<u64>,
+    /// Failures of requests to the backend, and whether they are let through.
+    circuit: CircuitStatus,
+    models: Vec<String>,
+}
+
+/// `GET /admin/backends`
+pub async fn list_backends(State(state): State<Arc<AppState>>) -> Json<Vec<BackendStatus>> {
+    let gateway = state.gateway();
+
+    let statuses = gateway.config.providers.iter().map(|(name, provider)| {
+        let backend = gateway.backends.get(&name.to_lowercase()).cloned();
+        let circuit = state.circuits.status(
+            gateway.config.circuit_breaker.as_ref(),
+            &name.to_lowercase(),
+        );
+        async move {
+            let mut status = BackendStatus {
+                name: name.clone(),
+                kind: provider.kind.clone(),
+                url: provider.url.clone(),
+                enabled: provider.enabled,
+                initialized: backend.is_some(),
+                healthy: false,
+                error: None,
+                latency_ms: None,
+                circuit,
+                models: provider.supported_models.clone(),
+            };
+            let Some(backend) = backend else {
+                return status;
+            };
+
+            let started = Instant::now();
+            match timeout(HEALTH_CHECK_TIMEOUT, backend.health_check()).await {
+                Ok(Ok(())) => status.healthy = true,
+                Ok(Err(e)) => status.error = Some(e.to_string()),
+                Err(_) => status.error = Some("health check timed out".into()),
+            }
+            status.latency_ms = Some(started.elapsed().as_millis() as u64);
+            status
+        }
+    });
+
+    Json(join_all(statuses).await)
+}
+
+/// `POST /admin/backends/{name}/enable`, also closing its circuit.
+pub async fn enable_backend(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Query(query): Query<PersistQuery>,
+) -> Result<(), ApiError> {
+    let edit = ConfigEdit::SetEnabled {
+        provider: name.clone(),
+        enabled: true,
+    };
+    update_config(&state, edit, query.persist).await?;
+    state.circuits.reset(&name.to_lowercase());
+    Ok(())
+}
+
+/// `POST /admin/backends/{name}/drain`
+///
+/// In-flight requests finish; new ones are rejected until re-enabled.
+pub async fn drain_backend(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Query(query): Query<PersistQuery>,
+) -> Result<(), ApiError> {
+    let edit = ConfigEdit::SetEnabled {
+        provider: name,
+        enabled: false,
+    };
+    update_config(&state, edit, query.persist).await
+}
+
+/// `PUT /admin/backends/{name}/models`
+pub async fn set_backend_models(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Query(query): Query<PersistQuery>,
+    Json(models): Json<Vec<String>>,
+) -> Result<(), ApiError> {
+    let edit = ConfigEdit::SetModels {
+        provider: name,
+        models,
+    };
+    update_config(&state, edit, query.persist).await
+}
+
+#[derive(Debug, Serialize)]
+pub struct Routes {
+    /// Backend name -> the models routed to it.
+    backends: BTreeMap<String, Vec<String>>,
+    aliases: BTreeMap<String, String>,
+}
+
+/// `GET /admin/routes`
+pub async fn list_routes(State(state): State<Arc<AppState>>) -> Json<Routes> {
+    let gateway = state.gateway();
+    let backends = gateway
+        .config
+        .providers
+        .iter()
+        .filter(|(_, provider)| provider.enabled)
+        .map(|(name, provider)| (name.clone(), provider.supported_models.clone()))
+        .collect();
+
+    Json(Routes {
+        backends,
+        aliases: gateway.config.aliases.clone(),
+    })
+}
+
+/// `GET /admin/aliases`
+pub async fn list_aliases(State(state): State<Arc<AppState>>) -> Json<BTreeMap<String, String>> {
+    Json(state.gateway().config.aliases.clone())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AliasTarget {
+    /// `backend:model`, `backend` for its default model, or a bare model name.
+    target: String,
+}
+
+/// `PUT /admin/aliases/{alias}`
+pub async fn set_alias(
+    State(state): State<Arc<AppState>>,
+    Path(alias): Path<String>,
+    Query(query): Query<PersistQuery>,
+    Json(AliasTarget { target }): Json<AliasTarget>,
+) -> Result<(), ApiError> {
+    ModelIdentifier::resolve_target(&state.gateway().config, &target)?;
+
+    update_config(
+        &state,
+        ConfigEdit::SetAlias { alias, target },
+        query.persist,
+    )
+    .await
+}
+
+/// `DELETE /admin/aliases/{alias}`
+pub async fn remove_alias(
+    State(state): State<Arc<AppState>>,
+    Path(alias): Path<String>,
+    Query(query): Query<PersistQuery>,
+) -> Result<(), ApiError> {
+    update_config(&state, ConfigEdit::RemoveAlias { alias }, query.persist).await
+}
+
+/// `GET /admin/keys`, with the keys masked.
+pub async fn list_keys(State(state): State<Arc<AppState>>) -> Json<Vec<String>> {
+    let keys = state
+        .gateway()
+        .config
+        .auth
+        .api_keys
+        .iter()
+        .map(|key| mask(key))
+        .collect();
+    Json(keys)
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct NewKey {
+    /// Generated when not given.
+    key: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedKey {
+    key: String,
+}
+
+/// `POST /admin/keys`
+///
+/// The full key is only returned here.
+pub async fn create_key(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<PersistQuery>,
+    body: Option<Json<NewKey>>,
+) -> Result<Json<CreatedKey>, ApiError> {
+    let key = body
+        .and_then(|Json(body)| body.key)
+        .unwrap_or_else(|| format!("tk-{}", uuid::Uuid::new_v4().simple()));
+    if key.trim().is_empty() {
+        return Err(ApiError::BadRequest("key must not be empty".into()));
+    }
+
+    let edit = ConfigEdit::AddApiKey { key: key.clone() };
+    update_config(&state, edit, query.persist).await?;
+
+    Ok(Json(CreatedKey { key }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevokedKey {
+    key: String,
+}
+
+/// `DELETE /admin/keys`, with the key in the body so it stays out of URLs
+/// (and the access logs that record them).
+pub async fn revoke_key(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<PersistQuery>,
+    Json(body): Json<RevokedKey>,
+) -> Result<(), ApiError> {
+    let edit = ConfigEdit::RemoveApiKey { key: body.key };
+    update_config(&state, edit, query.persist).await
+}
+
+/// `POST /admin/reload`, re-reading the config file like SIGHUP does.
+pub async fn reload_config(State(state): State<Arc<AppState>>) -> Result<(), ApiError> {
+    reload(&state)
+        .await
+        .map_err(|e| ApiError::ConfigError(e.to_string()))
+}
@@ -0,0 +1,109 @@
+//! An Anthropic Messages-compatible API (`/v1/messages`), so tools that only
+//! speak Anthropic's API can use any backend.
+//!
+//! With `"stream": true` the answer is sent as server-sent events while the
+//! backend generates it; backends that cannot stream send it in one piece.
+
+mod protocol;
+
+use {
+    super::chat_completion::{complete_chat, complete_chat_stream},
+    crate::{usage::Caller, virtual_keys::VirtualKey, ApiError, AppState},
+    axum::{
+        body::Body,
+        extract::State,
+        http::{header, response::Parts, HeaderValue, StatusCode},
+        response::{IntoResponse, Response},
+        Extension, Json,
+    },
+    futures_util::{stream, StreamExt},
+    protocol::{MessageStream, MessagesRequest, MessagesResponse},
+    serde_json::Value,
+    std::{convert::Infallible, sync::Arc},
+};
+
+/// An error in Anthropic's format,
+/// `{"type": "error", "error": {"type", "message"}}`, with the status of
+/// the gateway error.
+pub struct AnthropicError(ApiError);
+
+impl From<ApiError> for AnthropicError {
+    fn from(error: ApiError) -> Self {
+        Self(error)
+    }
+}
+
+impl AnthropicError {
+    /// The status of the error and its body.
+    fn into_parts(self) -> (Parts, Value) {
+        let message = self.0.to_string();
+        let (parts, _) = self.0.into_response().into_parts();
+        let kind = match parts.status {
+            StatusCode::BAD_REQUEST => "invalid_request_error",
+            StatusCode::UNAUTHORIZED => "authentication_error",
+            StatusCode::FORBIDDEN => "permission_error",
+            StatusCode::NOT_FOUND => "not_found_error",
+            StatusCode::PAYLOAD_TOO_LARGE => "request_too_large",
+            StatusCode::TOO_MANY_REQUESTS => "rate_limit_error",
+            _ => "api_error",
+        };
+        let body = serde_json::json!({
+            "type": "error",
+            "error": { "type": kind, "message": message },
+        });
+        (parts, body)
+    }
+}
+
+impl IntoResponse for AnthropicError {
+    fn into_response(self) -> Response {
+        let (parts, body) = self.into_parts();
+        Response::from_parts(parts, Body::from(body.to_string()))
+    }
+}
+
+/// `POST /v1/messages`
+///
+/// The message answers for the requested model name.
+pub async fn handle_messages(
+    State(state): State<Arc<AppState>>,
+    virtual_key: Option<Extension<VirtualKey>>,
+    caller: Option<Extension<Caller>>,
+    Json(request): Json<MessagesRequest>,
+) -> Result<Response, AnthropicError> {
+    let model = request.model.clone();
+    let id = format!("msg_{}", uuid::Uuid::new_v4().simple());
+    if !request.stream {
+        let (headers, response) = complete_chat(
+            &state,
+            virtual_key.as_deref(),
+            caller.as_deref(),
+            request.try_into()?,
+        )
+        .await?;
+        let message = MessagesResponse::new(id, model, response);
+        return Ok((headers, Json(message)).into_response());
+    }
+
+    let virtual_key = virtual_key.map(|Extension(key)| key);
+    let caller = caller.map(|Extension(caller)| caller);
+    let (headers, deltas) =
+        complete_chat_stream(state, virtual_key, caller, request.try_into()?).await?;
+    let (mut message, start) = MessageStream::start(id, model);
+    let events = deltas.flat_map(move |delta| {
+        let events = match delta {
+            Ok(delta) => message.next(delta),
+            Err(e) => vec![("error", AnthropicError(e).into_parts().1)],
+        };
+        stream::iter(events)
+    });
+    let body = stream::iter([start])
+        .chain(events)
+        .map(|(event, data)| Ok::<_, Infallible>(format!("event: {}\ndata: {}\n\n", event, data)));
+    let mut response = (headers, Body::from_stream(body)).into_response();
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/event-stream"),
+    );
+    Ok(response)
+}
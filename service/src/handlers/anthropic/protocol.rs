@@ -0,0 +1,735 @@
+//! Anthropic Messages request, response and stream event formats, and their
+//! translation to the gateway's.
+
+use {
+    crate::ApiError,
+    serde::{Deserialize, Serialize},
+    serde_json::Value,
+    topkio_primitive::api::{
+        ChatCompletionDelta, ChatCompletionRequest, ChatCompletionResponse, ContentPart, FileRef,
+        FunctionCall, FunctionDefinition, ImageUrl, Message, MessageContent, Tool, ToolCall,
+        ToolChoice, ToolChoiceMode, Usage,
+    },
+};
+
+#[derive(Debug, Deserialize)]
+pub struct MessagesRequest {
+    pub model: String,
+    pub max_tokens: Option<u32>,
+    #[serde(default)]
+    pub system: Option<SystemPrompt>,
+    pub messages: Vec<InputMessage>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub stop_sequences: Option<Vec<String>>,
+    #[serde(default)]
+    pub tools: Option<Vec<InputTool>>,
+    #[serde(default)]
+    pub tool_choice: Option<InputToolChoice>,
+    #[serde(default)]
+    pub stream: bool,
+}
+
+/// `system` is a string or a list of text blocks.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum SystemPrompt {
+    Text(String),
+    Blocks(Vec<Value>),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InputMessage {
+    pub role: String,
+    pub content: InputContent,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum InputContent {
+    Text(String),
+    Blocks(Vec<InputBlock>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InputBlock {
+    Text {
+        text: String,
+    },
+    Image {
+        source: Source,
+    },
+    /// A PDF.
+    Document {
+        source: Source,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        #[serde(default)]
+        input: Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        /// A string or a list of blocks, of which the text is kept.
+        #[serde(default)]
+        content: Value,
+    },
+    /// Blocks the gateway does not translate (e.g. `thinking`).
+    #[serde(other)]
+    Unsupported,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Source {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+}
+
+impl Source {
+    fn into_url(self) -> String {
+        match self {
+            Self::Base64 { media_type, data } => format!("data:{};base64,{}", media_type, data),
+            Self::Url { url } => url,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InputTool {
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub input_schema: Value,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InputToolChoice {
+    #[serde(flatten)]
+    pub mode: ToolChoiceType,
+    /// Not supported: backends may always call several tools at once.
+    #[serde(default)]
+    pub disable_parallel_tool_use: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ToolChoiceType {
+    Auto,
+    Any,
+    Tool { name: String },
+    None,
+}
+
+/// Text of a `system` prompt or tool result: the string, or the text of
+/// its blocks.
+fn text_of(value: Value) -> String {
+    match value {
+        Value::String(text) => text,
+        Value::Array(blocks) => blocks
+            .iter()
+            .filter_map(|block| block.get("text").and_then(Value::as_str))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+impl TryFrom<MessagesRequest> for ChatCompletionRequest {
+    type Error = ApiError;
+
+    fn try_from(request: MessagesRequest) -> Result<Self, Self::Error> {
+        let tool_choice = match request.tool_choice {
+            Some(InputToolChoice {
+                disable_parallel_tool_use: true,
+                ..
+            }) => {
+                return Err(ApiError::BadRequest(
+                    "disable_parallel_tool_use is not supported".into(),
+                ))
+            }
+            Some(InputToolChoice { mode, .. }) => Some(match mode {
+                ToolChoiceType::Auto => ToolChoice::Mode(ToolChoiceMode::Auto),
+                ToolChoiceType::Any => ToolChoice::Mode(ToolChoiceMode::Required),
+                ToolChoiceType::Tool { name } => ToolChoice::function(name),
+                ToolChoiceType::None => ToolChoice::Mode(ToolChoiceMode::None),
+            }),
+            None => None,
+        };
+
+        let mut messages = vec![];
+        match request.system {
+            Some(SystemPrompt::Text(system)) => messages.push(Message::new("system", system)),
+            Some(SystemPrompt::Blocks(blocks)) => {
+                messages.push(Message::new("system", text_of(Value::Array(blocks))))
+            }
+            None => {}
+        }
+        for message in request.messages {
+            push_message(&mut messages, message);
+        }
+
+        Ok(Self {
+            model: request.model,
+            messages,
+            stream: Some(false),
+            max_tokens: request.max_tokens,
+            temperature: request.temperature,
+            stop: request.stop_sequences,
+            tools: request.tools.map(|tools| {
+                tools
+                    .into_iter()
+                    .map(|tool| Tool {
+                        kind: "function".into(),
+                        function: FunctionDefinition {
+                            name: tool.name,
+                            description: tool.description,
+                            parameters: tool.input_schema,
+                        },
+                    })
+                    .collect()
+            }),
+            tool_choice,
+            ..Default::default()
+        })
+    }
+}
+
+/// Add a Messages turn to `messages`: tool results become `tool` messages,
+/// and tool uses the assistant's tool calls.
+fn push_message(messages: &mut Vec<Message>, message: InputMessage) {
+    let blocks = match message.content {
+        InputContent::Text(text) => {
+            messages.push(Message::new(message.role, text));
+            return;
+        }
+        InputContent::Blocks(blocks) => blocks,
+    };
+
+    let mut parts = vec![];
+    let mut tool_calls = vec![];
+    for block in blocks {
+        match block {
+            InputBlock::Text { text } => parts.push(ContentPart::Text { text }),
+            InputBlock::Image { source } => parts.push(ContentPart::ImageUrl {
+                image_url: ImageUrl {
+                    url: source.into_url(),
+                    detail: None,
+                },
+            }),
+            InputBlock::Document { source } => parts.push(ContentPart::File {
+                file: FileRef {
+                    file_id: None,
+                    file_data: Some(source.into_url()),
+                    filename: None,
+                },
+            }),
+            InputBlock::ToolUse { id, name, input } => tool_calls.push(ToolCall {
+                id,
+                kind: "function".into(),
+                function: FunctionCall {
+                    name,
+                    arguments: input.to_string(),
+                },
+            }),
+            InputBlock::ToolResult {
+                tool_use_id,
+                content,
+            } => {
+                let mut result = Message::new("tool", text_of(content));
+                result.tool_call_id = Some(tool_use_id);
+                messages.push(result);
+            }
+            InputBlock::Unsupported => {}
+        }
+    }
+
+    if parts.is_empty() && tool_calls.is_empty() {
+        return;
+    }
+    let content = match parts.as_slice() {
+        [ContentPart::Text { text }] => MessageContent::Text(text.clone()),
+        _ if parts
+            .iter()
+            .all(|part| matches!(part, ContentPart::Text { .. })) =>
+        {
+            MessageContent::Text(MessageContent::Parts(parts).text())
+        }
+        _ => MessageContent::Parts(parts),
+    };
+    let mut unified = Message::new(message.role, content);
+    unified.tool_calls = (!tool_calls.is_empty()).then_some(tool_calls);
+    messages.push(unified);
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MessagesResponse {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub role: &'static str,
+    pub model: String,
+    pub content: Vec<OutputBlock>,
+    pub stop_reason: Option<String>,
+    pub stop_sequence: Option<String>,
+    pub usage: OutputUsage,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OutputBlock {
+    Text {
+        text: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: Value,
+    },
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct OutputUsage {
+    /// Input tokens not read from the cache.
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<u32>,
+}
+
+impl From<&Usage> for OutputUsage {
+    fn from(usage: &Usage) -> Self {
+        let cached = usage.cached_tokens.unwrap_or(0).min(usage.prompt_tokens);
+        Self {
+            input_tokens: usage.prompt_tokens - cached,
+            output_tokens: usage.completion_tokens,
+            cache_read_input_tokens: usage.cached_tokens,
+        }
+    }
+}
+
+/// The id, name and input of the `tool_use` block of `call`.
+fn tool_use(call: ToolCall) -> (String, String, Value) {
+    // Tool results are matched by id, which not every backend gives.
+    let id = match call.id.is_empty() {
+        true => format!("toolu_{}", uuid::Uuid::new_v4().simple()),
+        false => call.id,
+    };
+    let input = serde_json::from_str(&call.function.arguments)
+        .unwrap_or_else(|_| Value::Object(Default::default()));
+    (id, call.function.name, input)
+}
+
+impl MessagesResponse {
+    /// `response` as a message with the id `id`, answering for `model`.
+    pub fn new(id: String, model: String, response: ChatCompletionResponse) -> Self {
+        let mut content = vec![];
+        let text = response.message.content.text();
+        if !text.is_empty() {
+            content.push(OutputBlock::Text { text });
+        }
+        let tool_calls = response.message.tool_calls.unwrap_or_default();
+        let has_tool_calls = !tool_calls.is_empty();
+        for call in tool_calls {
+            let (id, name, input) = tool_use(call);
+            content.push(OutputBlock::ToolUse { id, name, input });
+        }
+
+        Self {
+            id,
+            kind: "message",
+            role: "assistant",
+            model,
+            content,
+            stop_reason: Some(stop_reason(
+                response.finish_reason.as_deref(),
+                has_tool_calls,
+            )),
+            stop_sequence: None,
+            usage: response
+                .usage
+                .as_ref()
+                .map(OutputUsage::from)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Anthropic `stop_reason` of an OpenAI-style finish reason.
+fn stop_reason(finish_reason: Option<&str>, has_tool_calls: bool) -> String {
+    match finish_reason {
+        Some("length") => "max_tokens",
+        Some("tool_calls") => "tool_use",
+        Some("content_filter") => "refusal",
+        _ if has_tool_calls => "tool_use",
+        _ => "end_turn",
+    }
+    .into()
+}
+
+/// A server-sent event, as `(event type, data)`.
+pub type Event = (&'static str, Value);
+
+/// Anthropic's server-sent events for an answer streamed in pieces.
+pub struct MessageStream {
+    /// Index of the next content block.
+    index: usize,
+    /// Whether the last block is a text block still open.
+    in_text: bool,
+    called_tools: bool,
+}
+
+impl MessageStream {
+    /// The stream of the message `id`, answering for `model`, and its
+    /// `message_start` event.
+    pub fn start(id: String, model: String) -> (Self, Event) {
+        let message = MessagesResponse {
+            id,
+            kind: "message",
+            role: "assistant",
+            model,
+            content: vec![],
+            stop_reason: None,
+            stop_sequence: None,
+            usage: OutputUsage::default(),
+        };
+        let stream = Self {
+            index: 0,
+            in_text: false,
+            called_tools: false,
+        };
+        let event = (
+            "message_start",
+            serde_json::json!({ "type": "message_start", "message": message }),
+        );
+        (stream, event)
+    }
+
+    /// The events of the next piece of the answer. The piece with a finish
+    /// reason ends the message, with its stop reason and usage.
+    pub fn next(&mut self, delta: ChatCompletionDelta) -> Vec<Event> {
+        let mut events = vec![];
+        if !delta.content.is_empty() {
+            if !self.in_text {
+                let empty = OutputBlock::Text {
+                    text: String::new(),
+                };
+                events.push(self.open(empty));
+                self.in_text = true;
+            }
+            let text = serde_json::json!({ "type": "text_delta", "text": delta.content });
+            events.push(self.delta(text));
+        }
+
+        for call in delta.tool_calls {
+            self.close(&mut events);
+            self.called_tools = true;
+            let (id, name, input) = tool_use(call);
+            let empty = OutputBlock::ToolUse {
+                id,
+                name,
+                input: Value::Object(Default::default()),
+            };
+            events.push(self.open(empty));
+            let input = serde_json::json!({ "type": "input_json_delta", "partial_json": input.to_string() });
+            events.push(self.delta(input));
+            self.close_block(&mut events);
+        }
+
+        if let Some(reason) = delta.finish_reason {
+            self.close(&mut events);
+            let usage = delta
+                .usage
+                .as_ref()
+                .map(OutputUsage::from)
+                .unwrap_or_default();
+            events.push((
+                "message_delta",
+                serde_json::json!({
+                    "type": "message_delta",
+                    "delta": {
+                        "stop_reason": stop_reason(Some(&reason), self.called_tools),
+                        "stop_sequence": null,
+                    },
+                    "usage": usage,
+                }),
+            ));
+            events.push((
+                "message_stop",
+                serde_json::json!({ "type": "message_stop" }),
+            ));
+        }
+        events
+    }
+
+    fn open(&mut self, block: OutputBlock) -> Event {
+        self.index += 1;
+        (
+            "content_block_start",
+            serde_json::json!({ "type": "content_block_start", "index": self.index - 1, "content_block": block }),
+        )
+    }
+
+    fn delta(&self, delta: Value) -> Event {
+        (
+            "content_block_delta",
+            serde_json::json!({ "type": "content_block_delta", "index": self.index - 1, "delta": delta }),
+        )
+    }
+
+    /// Close the text block, if one is open.
+    fn close(&mut self, events: &mut Vec<Event>) {
+        if std::mem::take(&mut self.in_text) {
+            self.close_block(events);
+        }
+    }
+
+    fn close_block(&self, events: &mut Vec<Event>) {
+        events.push((
+            "content_block_stop",
+            serde_json::json!({ "type": "content_block_stop", "index": self.index - 1 }),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translate(request: Value) -> Result<ChatCompletionRequest, ApiError> {
+        serde_json::from_value::<MessagesRequest>(request)
+            .unwrap()
+            .try_into()
+    }
+
+    #[test]
+    fn translates_messages_and_tools() {
+        let request = translate(serde_json::json!({
+            "model": "ollama:llama3",
+            "max_tokens": 256,
+            "system": [{"type": "text", "text": "Be brief."}],
+            "messages": [
+                {"role": "user", "content": [
+                    {"type": "text", "text": "What is this?"},
+                    {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo="}},
+                    {"type": "document", "source": {"type": "url", "url": "https://example.com/a.pdf"}}
+                ]},
+                {"role": "assistant", "content": [
+                    {"type": "thinking", "thinking": "..."},
+                    {"type": "text", "text": "Let me check."},
+                    {"type": "tool_use", "id": "toolu_1", "name": "lookup", "input": {"q": "cat"}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_1", "content": [{"type": "text", "text": "A cat."}]}
+                ]}
+            ],
+            "tools": [{"name": "lookup", "input_schema": {"type": "object"}}]
+        }))
+        .unwrap();
+
+        assert_eq!(request.max_tokens, Some(256));
+        let messages = serde_json::to_value(&request.messages).unwrap();
+        assert_eq!(
+            messages,
+            serde_json::json!([
+                {"role": "system", "content": "Be brief."},
+                {"role": "user", "content": [
+                    {"type": "text", "text": "What is this?"},
+                    {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0KGgo="}},
+                    {"type": "file", "file": {"file_data": "https://example.com/a.pdf"}}
+                ]},
+                {"role": "assistant", "content": "Let me check.", "tool_calls": [
+                    {"id": "toolu_1", "type": "function", "function": {"name": "lookup", "arguments": "{\"q\":\"cat\"}"}}
+                ]},
+                {"role": "tool", "content": "A cat.", "tool_call_id": "toolu_1"}
+            ])
+        );
+        let tools = request.tools.unwrap();
+        assert_eq!(tools[0].function.name, "lookup");
+        assert_eq!(
+            tools[0].function.parameters,
+            serde_json::json!({"type": "object"})
+        );
+    }
+
+    #[test]
+    fn translates_sampling_options_and_tool_choice() {
+        let request = |tool_choice: Value| {
+            translate(serde_json::json!({
+                "model": "ollama:llama3",
+                "messages": [{"role": "user", "content": "Hi"}],
+                "temperature": 0.7,
+                "stop_sequences": ["END"],
+                "tool_choice": tool_choice
+            }))
+        };
+
+        let translated = request(serde_json::json!({"type": "tool", "name": "lookup"})).unwrap();
+        assert_eq!(translated.temperature, Some(0.7));
+        assert_eq!(translated.stop, Some(vec!["END".to_string()]));
+        assert_eq!(translated.tool_choice, Some(ToolChoice::function("lookup")));
+
+        let modes = [
+            ("auto", ToolChoiceMode::Auto),
+            ("any", ToolChoiceMode::Required),
+            ("none", ToolChoiceMode::None),
+        ];
+        for (kind, mode) in modes {
+            let translated = request(serde_json::json!({"type": kind})).unwrap();
+            assert_eq!(translated.tool_choice, Some(ToolChoice::Mode(mode)));
+        }
+
+        assert!(matches!(
+            request(serde_json::json!({"type": "auto", "disable_parallel_tool_use": true})),
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+
+    fn response(text: &str, tool_calls: Vec<ToolCall>, finish_reason: &str) -> MessagesResponse {
+        let mut message = Message::new("assistant", text);
+        message.tool_calls = (!tool_calls.is_empty()).then_some(tool_calls);
+        let mut response = ChatCompletionResponse::new(message);
+        response.finish_reason = Some(finish_reason.into());
+        let mut usage = Usage::new(100, 20);
+        usage.cached_tokens = Some(60);
+        response.usage = Some(usage);
+        MessagesResponse::new("msg_1".into(), "claude-alias".into(), response)
+    }
+
+    fn call(id: &str) -> ToolCall {
+        ToolCall {
+            id: id.into(),
+            kind: "function".into(),
+            function: FunctionCall {
+                name: "lookup".into(),
+                arguments: r#"{"q":"cat"}"#.into(),
+            },
+        }
+    }
+
+    #[test]
+    fn translates_responses() {
+        let message = response("", vec![call("")], "stop");
+        assert_eq!(message.stop_reason.as_deref(), Some("tool_use"));
+        assert_eq!(message.content.len(), 1);
+        assert!(matches!(
+            &message.content[0],
+            OutputBlock::ToolUse { id, input, .. }
+                if id.starts_with("toolu_") && input == &serde_json::json!({"q": "cat"})
+        ));
+        assert_eq!(message.usage.input_tokens, 40);
+        assert_eq!(message.usage.cache_read_input_tokens, Some(60));
+
+        assert_eq!(
+            response("Hi", vec![], "length").stop_reason.as_deref(),
+            Some("max_tokens")
+        );
+        assert_eq!(
+            response("", vec![], "content_filter")
+                .stop_reason
+                .as_deref(),
+            Some("refusal")
+        );
+        assert_eq!(
+            response("Hi", vec![], "stop").stop_reason.as_deref(),
+            Some("end_turn")
+        );
+    }
+
+    fn stream(deltas: Vec<ChatCompletionDelta>) -> Vec<Event> {
+        let (mut stream, start) = MessageStream::start("msg_1".into(), "claude-alias".into());
+        let mut events = vec![start];
+        for delta in deltas {
+            events.extend(stream.next(delta));
+        }
+        events
+    }
+
+    #[test]
+    fn streams_pieces_as_they_come() {
+        let text = |content: &str| ChatCompletionDelta {
+            content: content.into(),
+            ..Default::default()
+        };
+        let mut usage = Usage::new(100, 20);
+        usage.cached_tokens = Some(60);
+        let events = stream(vec![
+            text("Let me"),
+            text(" check."),
+            ChatCompletionDelta {
+                tool_calls: vec![call("toolu_1")],
+                ..Default::default()
+            },
+            ChatCompletionDelta {
+                finish_reason: Some("stop".into()),
+                usage: Some(usage),
+                ..Default::default()
+            },
+        ]);
+        let names: Vec<_> = events.iter().map(|(name, _)| *name).collect();
+        assert_eq!(
+            names,
+            [
+                "message_start",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop",
+            ]
+        );
+        for (name, data) in &events {
+            assert_eq!(data["type"], *name);
+        }
+
+        let start = &events[0].1["message"];
+        assert_eq!(start["content"], serde_json::json!([]));
+        assert_eq!(start["stop_reason"], Value::Null);
+        assert_eq!(start["usage"]["output_tokens"], 0);
+
+        assert_eq!(
+            events[1].1["content_block"],
+            serde_json::json!({"type": "text", "text": ""})
+        );
+        assert_eq!(
+            events[3].1["delta"],
+            serde_json::json!({"type": "text_delta", "text": " check."})
+        );
+        assert_eq!(
+            events[5].1["content_block"],
+            serde_json::json!({"type": "tool_use", "id": "toolu_1", "name": "lookup", "input": {}})
+        );
+        assert_eq!(events[6].1["index"], 1);
+        assert_eq!(
+            events[6].1["delta"],
+            serde_json::json!({"type": "input_json_delta", "partial_json": "{\"q\":\"cat\"}"})
+        );
+        assert_eq!(
+            events[8].1["delta"],
+            serde_json::json!({"stop_reason": "tool_use", "stop_sequence": null})
+        );
+        assert_eq!(
+            events[8].1["usage"],
+            serde_json::json!({"input_tokens": 40, "output_tokens": 20, "cache_read_input_tokens": 60})
+        );
+    }
+
+    #[test]
+    fn streams_an_empty_message() {
+        let names: Vec<_> = stream(vec![ChatCompletionDelta {
+            finish_reason: Some("length".into()),
+            ..Default::default()
+        }])
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+        assert_eq!(names, ["message_start", "message_delta", "message_stop"]);
+    }
+}
@@ -0,0 +1,169 @@
+use {
+    super::chat_completion::check_access,
+    crate::{handlers::ModelIdentifier, virtual_keys::VirtualKey, ApiError, AppState},
+    axum::{
+        extract::{Path, Query, State},
+        http::StatusCode,
+        Extension, Json,
+    },
+    serde::{Deserialize, Serialize},
+    std::sync::Arc,
+    topkio_primitive::api::{ContextCache, CreateCacheRequest},
+};
+
+#[derive(Debug, Serialize)]
+pub struct BackendCache {
+    backend: String,
+    #[serde(flatten)]
+    cache: ContextCache,
+}
+
+/// Caches hold conversations of every model, so keys limited to some models
+/// may create caches but not list or delete them.
+fn check_unscoped(virtual_key: Option<&VirtualKey>) -> Result<(), ApiError> {
+    match virtual_key {
+        Some(key) if !key.limits.models.is_empty() => Err(ApiError::Forbidden(format!(
+            "key {} is limited to some models and may not manage caches",
+            key.name
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// `GET /v1/caches`
+///
+/// The caches of every backend with context caching.
+pub async fn list_caches(
+    State(state): State<Arc<AppState>>,
+    virtual_key: Option<Extension<VirtualKey>>,
+) -> Result<Json<Vec<BackendCache>>, ApiError> {
+    check_unscoped(virtual_key.as_deref())?;
+    let gateway = state.gateway();
+    let mut backends: Vec<_> = gateway.backends.iter().collect();
+    backends.sort_by_key(|(backend, _)| *backend);
+
+    let mut caches = vec![];
+    for (backend, api) in backends {
+        if !api.supports_caching() {
+            continue;
+        }
+        let backend_caches = api.list_caches().await.map_err(ApiError::from_backend)?;
+        caches.extend(backend_caches.into_iter().map(|cache| BackendCache {
+            backend: backend.clone(),
+            cache,
+        }));
+    }
+    Ok(Json(caches))
+}
+
+/// `POST /v1/caches`
+///
+/// Cache messages for `model`, resolved like in chat requests.
+pub async fn create_cache(
+    State(state): State<Arc<AppState>>,
+    virtual_key: Option<Extension<VirtualKey>>,
+    Json(request): Json<CreateCacheRequest>,
+) -> Result<(StatusCode, Json<BackendCache>), ApiError> {
+    let gateway = state.gateway();
+    let model_id = ModelIdentifier::resolve(&gateway.config, &request.model)?;
+    check_access(&state, &gateway.config, virtual_key.as_deref(), &model_id).await?;
+
+    let backend = gateway
+        .backends
+        .get(&model_id.backend)
+        .ok_or_else(|| ApiError::BackendNotConfigured(model_id.backend.clone()))?;
+    let cache = backend
+        .create_cache(&model_id.model_name, request)
+        .await
+        .map_err(ApiError::from_backend)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(BackendCache {
+            backend: model_id.backend,
+            cache,
+        }),
+    ))
+}
+
+/// `DELETE /v1/caches/{backend}/{id}`
+///
+/// `id` is the last segment of the cache name.
+pub async fn delete_cache(
+    State(state): State<Arc<AppState>>,
+    virtual_key: Option<Extension<VirtualKey>>,
+    Path((backend, id)): Path<(String, String)>,
+) -> Result<StatusCode, ApiError> {
+    check_unscoped(virtual_key.as_deref())?;
+    let gateway = state.gateway();
+    let api = gateway
+        .backends
+        .get(&backend.to_lowercase())
+        .ok_or_else(|| ApiError::BackendNotConfigured(backend.clone()))?;
+    api.delete_cache(&id)
+        .await
+        .map_err(ApiError::from_backend)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct FlushQuery {
+    /// Only flush the caches of this backend.
+    backend: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FlushedCaches {
+    backend: String,
+    deleted: usize,
+    /// Caches that could not be listed or deleted.
+    errors: Vec<String>,
+}
+
+/// `POST /admin/caches/flush`
+///
+/// Delete every context cache of the backends with context caching, or of
+/// `?backend=` only.
+pub async fn flush_caches(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<FlushQuery>,
+) -> Result<Json<Vec<FlushedCaches>>, ApiError> {
+    let gateway = state.gateway();
+    let filter = query.backend.map(|backend| backend.to_lowercase());
+    if let Some(backend) = &filter {
+        if !gateway.backends.contains_key(backend) {
+            return Err(ApiError::BackendNotConfigured(backend.clone()));
+        }
+    }
+
+    let mut backends: Vec<_> = gateway
+        .backends
+        .iter()
+        .filter(|(backend, api)| {
+            api.supports_caching() && filter.as_ref().is_none_or(|filter| filter == *backend)
+        })
+        .collect();
+    backends.sort_by_key(|(backend, _)| *backend);
+
+    let mut flushed = vec![];
+    for (backend, api) in backends {
+        let mut result = FlushedCaches {
+            backend: backend.clone(),
+            deleted: 0,
+            errors: vec![],
+        };
+        match api.list_caches().await {
+            Ok(caches) => {
+                for cache in caches {
+                    match api.delete_cache(&cache.name).await {
+                        Ok(()) => result.deleted += 1,
+                        Err(e) => result.errors.push(format!("{}: {}", cache.name, e)),
+                    }
+                }
+            }
+            Err(e) => result.errors.push(e.to_string()),
+        }
+        flushed.push(result);
+    }
+    Ok(Json(flushed))
+}
@@ -1,11 +1,31 @@
 use {
-    crate::{ApiError, AppState},
+    crate::{
+        context_window, structured_output, usage::Caller, virtual_keys::VirtualKey, ApiError,
+        AppState, Gateway,
+    },
     axum::extract::State,
-    axum::Json,
+    axum::{
+        http::{HeaderMap, HeaderValue},
+        Extension, Json,
+    },
+    futures_util::{
+        stream::{self, BoxStream},
+        StreamExt,
+    },
     std::sync::Arc,
-    topkio_primitive::api::{ChatCompletionRequest, ChatCompletionResponse},
+    topkio_primitive::{
+        api::{
+            ChatCompletionDelta, ChatCompletionRequest, ChatCompletionResponse, ResponseFormat,
+            Usage,
+        },
+        config::TopkioConfig,
+    },
 };
 
+/// Pieces of a streamed chat completion. The last one, and only it, has a
+/// finish reason, along with the usage.
+pub(crate) type ChatStream = BoxStream<'static, Result<ChatCompletionDelta, ApiError>>;
+
 #[derive(Debug)]
 pub struct ModelIdentifier {
     pub backend: String,
@@ -13,50 +33,524 @@ pub struct ModelIdentifier {
 }
 
 impl ModelIdentifier {
-    pub fn parse(model_str: &str) -> Result<Self, ApiError> {
-        let (backend, model_name) = model_str
-            .split_once(':')
-            .ok_or_else(|| ApiError::InvalidModelFormat(model_str.to_string()))?;
+    /// Resolve a requested model, which may be:
+    ///
+    /// - an alias from `[aliases]`,
+    /// - `backend:model_name`,
+    /// - `backend:` or `backend`, for the backend's default `model`,
+    /// - a bare model name listed in a backend's `supported_models`.
+    pub fn resolve(config: &TopkioConfig, requested: &str) -> Result<Self, ApiError> {
+        let requested = requested.trim();
+        let target = config
+            .aliases
+            .get(requested)
+            .map(String::as_str)
+            .unwrap_or(requested);
+        Self::resolve_target(config, target)
+    }
 
-        if backend.is_empty() || model_name.is_empty() {
-            return Err(ApiError::InvalidModelFormat(model_str.to_string()));
-        }
+    /// Resolve `target` like `resolve`, without looking up aliases.
+    pub fn resolve_target(config: &TopkioConfig, target: &str) -> Result<Self, ApiError> {
+        let provider = |name: &str| {
+            config
+                .providers
+                .iter()
+                .find(|(provider, _)| provider.eq_ignore_ascii_case(name))
+                .map(|(_, provider)| provider)
+        };
+        let (backend, model_name) = match target.split_once(':') {
+            Some((backend, model_name)) if provider(backend).is_some() => {
+                (backend, model_name.trim())
+            }
+            _ if provider(target).is_some() => (target, ""),
+            // Not a backend, so possibly a bare model name such as `llama3.2:3b`.
+            _ => return Self::find_supporting(config, target),
+        };
+
+        let model_name = match model_name.is_empty() {
+            true => provider(backend)
+                .and_then(|p| p.model.clone())
+                .ok_or_else(|| {
+                    ApiError::InvalidModelFormat(format!(
+                        "{} (backend {} has no default model)",
+                        target, backend
+                    ))
+                })?,
+            false => model_name.to_string(),
+        };
 
         Ok(Self {
-            backend: backend.to_lowercase(),           // Normalize backend name
-            model_name: model_name.trim().to_string(), // Trim whitespace
+            backend: backend.to_lowercase(),
+            model_name,
         })
     }
+
+    /// Find a backend listing `model_name` in its `supported_models`,
+    /// preferring enabled ones.
+    fn find_supporting(config: &TopkioConfig, model_name: &str) -> Result<Self, ApiError> {
+        config
+            .providers
+            .iter()
+            .filter(|(_, provider)| provider.supported_models.iter().any(|m| m == model_name))
+            .min_by_key(|(_, provider)| !provider.enabled)
+            .map(|(backend, _)| Self {
+                backend: backend.to_lowercase(),
+                model_name: model_name.to_string(),
+            })
+            .ok_or_else(|| match model_name.split_once(':') {
+                Some((backend, _)) => ApiError::BackendNotConfigured(backend.to_string()),
+                None => ApiError::UnsupportedModel(model_name.to_string()),
+            })
+    }
 }
 
 pub async fn handle_chat_completion(
     State(state): State<Arc<AppState>>,
+    virtual_key: Option<Extension<VirtualKey>>,
+    caller: Option<Extension<Caller>>,
     Json(request): Json<ChatCompletionRequest>,
-) -> Result<Json<ChatCompletionResponse>, ApiError> {
-    let model_id = ModelIdentifier::parse(&request.model)?;
+) -> Result<(HeaderMap, Json<ChatCompletionResponse>), ApiError> {
+    let (headers, response) =
+        complete_chat(&state, virtual_key.as_deref(), caller.as_deref(), request).await?;
+    Ok((headers, Json(response)))
+}
+
+/// Send a chat completion through the gateway: resolve its model, check
+/// access, fit it in the context window and record its usage.
+pub(crate) async fn complete_chat(
+    state: &AppState,
+    virtual_key: Option<&VirtualKey>,
+    caller: Option<&Caller>,
+    mut request: ChatCompletionRequest,
+) -> Result<(HeaderMap, ChatCompletionResponse), ApiError> {
+    let gateway = state.gateway();
+    let model_id = ModelIdentifier::resolve(&gateway.config, &request.model)?;
     println!(
         "Received chat completion request for model: {}",
         request.model
     );
 
-    let backend_name = model_id.backend;
-    let model_name = model_id.model_name;
-
-    let backend = state
+    request.model = format!("{}:{}", model_id.backend, model_id.model_name);
+    check_access(state, &gateway.config, virtual_key, &model_id).await?;
+    let backend = gateway
         .backends
-        .get(&backend_name)
-        .ok_or_else(|| ApiError::BackendNotConfigured(backend_name.to_string()))?;
+        .get(&model_id.backend)
+        .ok_or_else(|| ApiError::BackendNotConfigured(model_id.backend.clone()))?;
 
-    // if let Some(supported) = &state.config.backends[&backend_name].supported_models {
+    // if let Some(supported) = &gateway.config.backends[&backend_name].supported_models {
     //     if !supported.contains(&model_name.to_string()) {
     //         return Err(ApiError::UnsupportedModel(model_name.to_string()));
     //     }
     // }
 
-    let response = backend
-        .chat_completion(&model_name, request.messages, request.stream)
+    let request = context_window::enforce(
+        &gateway.config,
+        backend.as_ref(),
+        &model_id.backend,
+        &model_id.model_name,
+        request,
+    )
+    .await?;
+
+    let retries = gateway.config.structured_output.retries;
+    let (response, usage) =
+        structured_output::complete(backend.as_ref(), &model_id.model_name, request, retries).await;
+    state.circuits.record(
+        gateway.config.circuit_breaker.as_ref(),
+        &model_id.backend,
+        response.as_ref().err(),
+    );
+
+    let headers = record_usage(
+        state,
+        &gateway.config,
+        virtual_key,
+        caller,
+        &model_id,
+        usage.as_ref(),
+    )
+    .await;
+    Ok((headers, response?))
+}
+
+/// Stream a chat completion through the gateway, like `complete_chat`. The
+/// usage is recorded when the stream ends.
+///
+/// Answers the gateway checks or assembles (`response_format`, emulated
+/// `n`) are completed first and sent as one piece, with their cost header.
+pub(crate) async fn complete_chat_stream(
+    state: Arc<AppState>,
+    virtual_key: Option<VirtualKey>,
+    caller: Option<Caller>,
+    mut request: ChatCompletionRequest,
+) -> Result<(HeaderMap, ChatStream), ApiError> {
+    let formatted = !matches!(request.response_format, None | Some(ResponseFormat::Text));
+    if formatted || request.n.unwrap_or(1) > 1 {
+        let (headers, response) =
+            complete_chat(&state, virtual_key.as_ref(), caller.as_ref(), request).await?;
+        let mut delta = ChatCompletionDelta::from(response);
+        delta.finish_reason.get_or_insert_with(|| "stop".into());
+        return Ok((headers, stream::iter([Ok(delta)]).boxed()));
+    }
+
+    let gateway = state.gateway();
+    let model_id = ModelIdentifier::resolve(&gateway.config, &request.model)?;
+    request.model = format!("{}:{}", model_id.backend, model_id.model_name);
+    check_access(&state, &gateway.config, virtual_key.as_ref(), &model_id).await?;
+    let backend = gateway
+        .backends
+        .get(&model_id.backend)
+        .cloned()
+        .ok_or_else(|| ApiError::BackendNotConfigured(model_id.backend.clone()))?;
+    let request = context_window::enforce(
+        &gateway.config,
+        backend.as_ref(),
+        &model_id.backend,
+        &model_id.model_name,
+        request,
+    )
+    .await?;
+
+    let deltas = match backend
+        .chat_completion_stream(&model_id.model_name, request)
         .await
-        .map_err(|e| ApiError::BackendError(e.to_string()))?;
+    {
+        Ok(deltas) => deltas,
+        Err(e) => {
+            let error = ApiError::from_backend(e);
+            let breaker = gateway.config.circuit_breaker.as_ref();
+            state
+                .circuits
+                .record(breaker, &model_id.backend, Some(&error));
+            return Err(error);
+        }
+    };
+
+    let ending = StreamEnding {
+        state,
+        gateway,
+        virtual_key,
+        caller,
+        model_id,
+        finish_reason: None,
+        usage: None,
+        called_tools: false,
+    };
+    let stream = stream::unfold(
+        (deltas, Some(ending)),
+        |(mut deltas, mut ending)| async move {
+            loop {
+                let end = ending.as_mut()?;
+                match deltas.next().await {
+                    Some(Ok(mut delta)) => {
+                        end.called_tools |= !delta.tool_calls.is_empty();
+                        if let Some(reason) = delta.finish_reason.take() {
+                            end.finish_reason = Some(reason);
+                        }
+                        if let Some(usage) = delta.usage.take() {
+                            end.usage = Some(usage);
+                        }
+                        if !delta.content.is_empty() || !delta.tool_calls.is_empty() {
+                            return Some((Ok(delta), (deltas, ending)));
+                        }
+                    }
+                    Some(Err(e)) => {
+                        let error = ApiError::from_backend(e);
+                        ending.take()?.finish(Some(&error)).await;
+                        return Some((Err(error), (deltas, None)));
+                    }
+                    None => {
+                        let last = ending.take()?.finish(None).await;
+                        return Some((Ok(last), (deltas, None)));
+                    }
+                }
+            }
+        },
+    );
+    Ok((HeaderMap::new(), stream.boxed()))
+}
+
+/// What is left to do when a streamed chat completion ends.
+struct StreamEnding {
+    state: Arc<AppState>,
+    gateway: Arc<Gateway>,
+    virtual_key: Option<VirtualKey>,
+    caller: Option<Caller>,
+    model_id: ModelIdentifier,
+    finish_reason: Option<String>,
+    usage: Option<Usage>,
+    called_tools: bool,
+}
+
+impl StreamEnding {
+    /// Record the outcome and usage of the stream, and make its last piece.
+    async fn finish(self, error: Option<&ApiError>) -> ChatCompletionDelta {
+        let config = &self.gateway.config;
+        self.state.circuits.record(
+            config.circuit_breaker.as_ref(),
+            &self.model_id.backend,
+            error,
+        );
+        record_usage(
+            &self.state,
+            config,
+            self.virtual_key.as_ref(),
+            self.caller.as_ref(),
+            &self.model_id,
+            self.usage.as_ref(),
+        )
+        .await;
+
+        // Some backends report calls streamed before the end as `stop`.
+        let finish_reason = match self.finish_reason {
+            Some(reason) if reason != "stop" || !self.called_tools => reason,
+            _ if self.called_tools => "tool_calls".into(),
+            _ => "stop".into(),
+        };
+        ChatCompletionDelta {
+            finish_reason: Some(finish_reason),
+            usage: self.usage,
+            ..Default::default()
+        }
+    }
+}
+
+/// Reject requests to drained backends or backends whose circuit is open,
+/// and requests a virtual key may not make or has no budget left for.
+pub(crate) async fn check_access(
+    state: &AppState,
+    config: &TopkioConfig,
+    virtual_key: Option<&VirtualKey>,
+    model_id: &ModelIdentifier,
+) -> Result<(), ApiError> {
+    let drained = config
+        .providers
+        .iter()
+        .any(|(name, provider)| name.eq_ignore_ascii_case(&model_id.backend) && !provider.enabled);
+    if drained {
+        return Err(ApiError::BackendDrained(model_id.backend.clone()));
+    }
+    state
+        .circuits
+        .check(config.circuit_breaker.as_ref(), &model_id.backend)?;
+
+    if let (Some(key), Some(keys)) = (virtual_key, &state.keys) {
+        if !key.allows(&model_id.backend, &model_id.model_name) {
+            return Err(ApiError::Forbidden(format!(
+                "key {} may not use {}:{}",
+                key.name, model_id.backend, model_id.model_name
+            )));
+        }
+        keys.check_budget(key).await?;
+    }
+    Ok(())
+}
+
+/// Record the usage of a request against its key and in the usage log.
+///
+/// Returns the `x-topkio-cost` header when the model has a price.
+pub(crate) async fn record_usage(
+    state: &AppState,
+    config: &TopkioConfig,
+    virtual_key: Option<&VirtualKey>,
+    caller: Option<&Caller>,
+    model_id: &ModelIdentifier,
+    usage: Option<&Usage>,
+) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let Some(usage) = usage else {
+        return headers;
+    };
+    let (backend_name, model_name) = (&model_id.backend, &model_id.model_name);
+
+    let cost = config
+        .price(backend_name, model_name)
+        .map(|price| price.cost(usage));
+    if let Some(cost) = cost {
+        let value = HeaderValue::from_str(&format!("{:.6}", cost)).expect("valid header");
+        headers.insert("x-topkio-cost", value);
+    }
+
+    // Don't fail the request over bookkeeping.
+    if let (Some(key), Some(keys)) = (virtual_key, &state.keys) {
+        if let Err(e) = keys.record_usage(&key.id, usage, cost.unwrap_or(0.0)).await {
+            eprintln!("Failed to record usage of key {}: {}", key.name, e);
+        }
+    }
+    if let Some(log) = &state.usage {
+        let caller = caller.cloned().unwrap_or_default();
+        if let Err(e) = log.record(&caller, backend_name, model_name, usage, cost) {
+            eprintln!("Failed to record usage: {}", e);
+        }
+    }
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            providers::default_registry,
+            usage::{GroupBy, UsageFilter, UsageLog},
+            virtual_keys::{KeyLimits, KeyStore},
+            Gateway, Options,
+        },
+        arc_swap::ArcSwap,
+        axum::{routing::post, Router},
+        std::{collections::HashMap, path::Path},
+        tokio::sync::Mutex,
+        topkio_ollama::OllamaBackend,
+        topkio_primitive::api::Message,
+    };
+
+    /// A mock Ollama server answering every chat with "Hi there", 1000 prompt
+    /// and 500 completion tokens, in two chunks when streaming.
+    async fn ollama() -> String {
+        let chat = |Json(body): Json<serde_json::Value>| async move {
+            let chunk = |content: &str| {
+                serde_json::json!({
+                    "model": "llama3.2",
+                    "created_at": "2025-01-31T12:00:00.000000Z",
+                    "message": {"role": "assistant", "content": content},
+                    "done": false
+                })
+            };
+            let mut last = chunk(" there");
+            last["done"] = true.into();
+            last["done_reason"] = "stop".into();
+            last["prompt_eval_count"] = 1000.into();
+            last["eval_count"] = 500.into();
+
+            if body["stream"] == true {
+                format!("{}\n{}\n", chunk("Hi"), last)
+            } else {
+                last["message"]["content"] = "Hi there".into();
+                last.to_string()
+            }
+        };
+        let app = Router::new().route("/api/chat", post(chat));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        url
+    }
 
-    Ok(Json(response))
+    /// A gateway with an Ollama backend priced at $1 per million input
+    /// tokens and $2 per million output tokens.
+    async fn state() -> AppState {
+        let url = ollama().await;
+        let config: TopkioConfig = toml::from_str(&format!(
+            r#"
+            [server]
+            host = "127.0.0.1"
+            port = 3000
+            [logging]
+            file_path = "topkio.log"
+            [providers.ollama]
+            url = "{url}"
+            [pricing]
+            "ollama:*" = {{ input = 1.0, output = 2.0 }}
+            "#
+        ))
+        .unwrap();
+        let backend: Arc<dyn topkio_primitive::api::UnifiedLlmApi> =
+            Arc::new(OllamaBackend::new(url));
+
+        AppState {
+            gateway: ArcSwap::from_pointee(Gateway {
+                backends: HashMap::from([("ollama".to_string(), backend)]),
+                config,
+            }),
+            registry: default_registry(),
+            options: Options::default(),
+            edit_lock: Mutex::new(()),
+            keys: Some(KeyStore::open(Path::new(":memory:")).unwrap()),
+            usage: Some(UsageLog::open(Path::new(":memory:")).unwrap()),
+            circuits: Default::default(),
+        }
+    }
+
+    fn request() -> ChatCompletionRequest {
+        ChatCompletionRequest {
+            model: "ollama:llama3.2".into(),
+            messages: vec![Message::new("user", "Hello")],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn ollama_chats_are_priced_and_logged() {
+        let state = state().await;
+        let (headers, response) = complete_chat(&state, None, None, request()).await.unwrap();
+
+        assert_eq!(response.finish_reason.as_deref(), Some("stop"));
+        // 1000 * $1 + 500 * $2, per million tokens.
+        assert_eq!(headers["x-topkio-cost"], "0.002000");
+        let rows = state
+            .usage
+            .as_ref()
+            .unwrap()
+            .report(GroupBy::Model, &UsageFilter::default())
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].group, "ollama:llama3.2");
+        assert_eq!(
+            (rows[0].prompt_tokens, rows[0].completion_tokens),
+            (1000, 500)
+        );
+    }
+
+    #[tokio::test]
+    async fn ollama_chats_use_up_key_budgets() {
+        let state = state().await;
+        let keys = state.keys.as_ref().unwrap();
+        let limits = KeyLimits {
+            daily_token_budget: Some(2000),
+            daily_spend_budget: Some(0.003),
+            ..Default::default()
+        };
+        let (key, _) = keys.create("team-a", limits).await.unwrap();
+
+        complete_chat(&state, Some(&key), None, request())
+            .await
+            .unwrap();
+        let usage = keys.usage(&key.id).await.unwrap();
+        assert_eq!(usage.daily_tokens, 1500);
+        assert!((usage.daily_spend - 0.002).abs() < 1e-9);
+
+        // The second chat takes the key over both budgets.
+        complete_chat(&state, Some(&key), None, request())
+            .await
+            .unwrap();
+        assert!(matches!(
+            complete_chat(&state, Some(&key), None, request()).await,
+            Err(ApiError::TokenBudgetExceeded(_) | ApiError::SpendBudgetExceeded(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn ollama_chats_stream_and_are_logged() {
+        let state = Arc::new(state().await);
+        let (_, stream) = complete_chat_stream(state.clone(), None, None, request())
+            .await
+            .unwrap();
+        let deltas: Vec<_> = stream.map(Result::unwrap).collect().await;
+
+        let content: Vec<_> = deltas.iter().map(|delta| delta.content.as_str()).collect();
+        assert_eq!(content, ["Hi", " there", ""]);
+        let last = deltas.last().unwrap();
+        assert_eq!(last.finish_reason.as_deref(), Some("stop"));
+        assert_eq!(last.usage.as_ref().unwrap().total_tokens, 1500);
+        let rows = state
+            .usage
+            .as_ref()
+            .unwrap()
+            .report(GroupBy::Model, &UsageFilter::default())
+            .unwrap();
+        assert_eq!(
+            (rows[0].prompt_tokens, rows[0].completion_tokens),
+            (1000, 500)
+        );
+    }
 }
@@ -0,0 +1,266 @@
+use {
+    super::chat_completion::{check_access, record_usage},
+    crate::{
+        context_window, handlers::ModelIdentifier, usage::Caller, virtual_keys::VirtualKey,
+        ApiError, AppState,
+    },
+    axum::{
+        extract::State,
+        http::{HeaderMap, HeaderValue},
+        Extension, Json,
+    },
+    futures_util::future::join_all,
+    serde::{Deserialize, Serialize},
+    std::{
+        sync::Arc,
+        time::{SystemTime, UNIX_EPOCH},
+    },
+    topkio_primitive::api::{
+        ChatCompletionRequest, CompletionRequest, CompletionResponse, Message, Usage,
+    },
+};
+
+/// Prompts of a single request, at most.
+const MAX_PROMPTS: usize = 16;
+
+/// Body of `/v1/completions`, which takes one prompt or several.
+#[derive(Debug, Deserialize)]
+pub struct TextCompletionRequest {
+    prompt: Prompt,
+    #[serde(default)]
+    stream: bool,
+    #[serde(flatten)]
+    request: CompletionRequest,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Prompt {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// A text completion in OpenAI's `text_completion` format.
+#[derive(Debug, Serialize)]
+pub struct TextCompletion {
+    id: String,
+    object: &'static str,
+    created: u64,
+    /// `backend:model_name` the request resolved to.
+    model: String,
+    choices: Vec<TextChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<Usage>,
+    /// Ollama's `context`, to continue from in the next request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context: Option<Vec<i64>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TextChoice {
+    index: u32,
+    text: String,
+    finish_reason: Option<String>,
+}
+
+/// `POST /v1/completions`
+///
+/// Plain text completion of a prompt, with `suffix` for fill-in-the-middle
+/// on backends that support it. Several prompts are completed separately,
+/// one choice each. Responses are not streamed.
+pub async fn handle_completion(
+    State(state): State<Arc<AppState>>,
+    virtual_key: Option<Extension<VirtualKey>>,
+    caller: Option<Extension<Caller>>,
+    Json(body): Json<TextCompletionRequest>,
+) -> Result<(HeaderMap, Json<TextCompletion>), ApiError> {
+    if body.stream {
+        return Err(ApiError::BadRequest(
+            "streaming is not supported for text completions".into(),
+        ));
+    }
+    let prompts = match body.prompt {
+        Prompt::One(prompt) => vec![prompt],
+        Prompt::Many(prompts) if prompts.is_empty() => {
+            return Err(ApiError::BadRequest("prompt must not be empty".into()))
+        }
+        Prompt::Many(prompts) if prompts.len() > MAX_PROMPTS => {
+            return Err(ApiError::BadRequest(format!(
+                "prompt may have at most {} entries",
+                MAX_PROMPTS
+            )))
+        }
+        Prompt::Many(prompts) => prompts,
+    };
+
+    let completions = prompts.into_iter().map(|prompt| {
+        let request = CompletionRequest {
+            prompt,
+            ..body.request.clone()
+        };
+        complete_text(&state, virtual_key.as_deref(), caller.as_deref(), request)
+    });
+    let mut headers = vec![];
+    let mut choices = vec![];
+    let mut usage: Option<Usage> = None;
+    let (mut model, mut context) = (String::new(), None);
+    for (result, index) in join_all(completions).await.into_iter().zip(0..) {
+        let (response_headers, model_id, response) = result?;
+        headers.push(response_headers);
+        if let Some(response_usage) = &response.usage {
+            *usage.get_or_insert_with(Usage::default) += response_usage;
+        }
+        model = format!("{}:{}", model_id.backend, model_id.model_name);
+        context = response.context;
+        choices.push(TextChoice {
+            index,
+            text: response.text,
+            finish_reason: response.finish_reason,
+        });
+    }
+
+    // Ollama's context only continues a single prompt.
+    if choices.len() > 1 {
+        context = None;
+    }
+    Ok((
+        add_costs(headers),
+        Json(TextCompletion {
+            id: format!("cmpl-{}", uuid::Uuid::new_v4().simple()),
+            object: "text_completion",
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default(),
+            model,
+            choices,
+            usage,
+            context,
+        }),
+    ))
+}
+
+/// The headers of several completions, with their costs added up.
+fn add_costs(all: Vec<HeaderMap>) -> HeaderMap {
+    let costs: Vec<f64> = all
+        .iter()
+        .filter_map(|headers| headers.get("x-topkio-cost")?.to_str().ok()?.parse().ok())
+        .collect();
+    let mut headers = all.into_iter().next().unwrap_or_default();
+    if costs.len() > 1 {
+        let total = format!("{:.6}", costs.iter().sum::<f64>());
+        headers.insert(
+            "x-topkio-cost",
+            HeaderValue::from_str(&total).expect("valid header"),
+        );
+    }
+    headers
+}
+
+/// Send a text completion through the gateway, like `complete_chat`.
+///
+/// Returns the model the request resolved to with the response.
+pub(crate) async fn complete_text(
+    state: &AppState,
+    virtual_key: Option<&VirtualKey>,
+    caller: Option<&Caller>,
+    mut request: CompletionRequest,
+) -> Result<(HeaderMap, ModelIdentifier, CompletionResponse), ApiError> {
+    let gateway = state.gateway();
+    let model_id = ModelIdentifier::resolve(&gateway.config, &request.model)?;
+
+    request.model = format!("{}:{}", model_id.backend, model_id.model_name);
+    check_access(state, &gateway.config, virtual_key, &model_id).await?;
+    let backend = gateway
+        .backends
+        .get(&model_id.backend)
+        .ok_or_else(|| ApiError::BackendNotConfigured(model_id.backend.clone()))?;
+
+    // Checked as a single message, which is never truncated.
+    let prompt = match &request.suffix {
+        Some(suffix) => format!("{}{}", request.prompt, suffix),
+        None => request.prompt.clone(),
+    };
+    let as_chat = ChatCompletionRequest {
+        model: request.model.clone(),
+        messages: vec![Message::new("user", prompt)],
+        max_tokens: request.max_tokens,
+        ..Default::default()
+    };
+    context_window::enforce(
+        &gateway.config,
+        backend.as_ref(),
+        &model_id.backend,
+        &model_id.model_name,
+        as_chat,
+    )
+    .await?;
+
+    let response = backend
+        .completion(&model_id.model_name, request)
+        .await
+        .map_err(ApiError::from_backend);
+    state.circuits.record(
+        gateway.config.circuit_breaker.as_ref(),
+        &model_id.backend,
+        response.as_ref().err(),
+    );
+
+    let headers = record_usage(
+        state,
+        &gateway.config,
+        virtual_key,
+        caller,
+        &model_id,
+        response.as_ref().ok().and_then(|r| r.usage.as_ref()),
+    )
+    .await;
+    Ok((headers, model_id, response?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: serde_json::Value) -> TextCompletionRequest {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn accepts_one_prompt_or_several() {
+        let body = parse(serde_json::json!({
+            "model": "ollama:qwen2.5-coder",
+            "prompt": "def fib(n):",
+            "stop": "\n\n",
+            "max_tokens": 64
+        }));
+        assert!(matches!(body.prompt, Prompt::One(ref prompt) if prompt == "def fib(n):"));
+        assert!(!body.stream);
+        assert_eq!(body.request.model, "ollama:qwen2.5-coder");
+        assert_eq!(body.request.stop, Some(vec!["\n\n".to_string()]));
+        assert_eq!(body.request.max_tokens, Some(64));
+
+        let body = parse(serde_json::json!({
+            "model": "ollama:qwen2.5-coder",
+            "prompt": ["def fib(n):", "def fact(n):"],
+            "stream": true
+        }));
+        assert!(matches!(body.prompt, Prompt::Many(ref prompts) if prompts.len() == 2));
+        assert!(body.stream);
+    }
+
+    #[test]
+    fn adds_up_costs() {
+        let cost = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-topkio-cost", HeaderValue::from_str(value).unwrap());
+            headers
+        };
+        let headers = add_costs(vec![cost("0.000100"), cost("0.000250")]);
+        assert_eq!(headers["x-topkio-cost"], "0.000350");
+
+        let headers = add_costs(vec![cost("0.000100")]);
+        assert_eq!(headers["x-topkio-cost"], "0.000100");
+        assert!(add_costs(vec![HeaderMap::new()]).is_empty());
+    }
+}
@@ -0,0 +1,59 @@
+use {
+    super::chat_completion::check_access,
+    crate::{handlers::ModelIdentifier, virtual_keys::VirtualKey, ApiError, AppState},
+    axum::{extract::State, Extension, Json},
+    serde::Serialize,
+    std::sync::Arc,
+    topkio_primitive::{api::ChatCompletionRequest, tokens},
+};
+
+#[derive(Debug, Serialize)]
+pub struct TokenCount {
+    /// `backend:model_name` the request resolves to.
+    model: String,
+    prompt_tokens: u32,
+    /// Whether the count comes from the model's tokenizer, not an estimate.
+    exact: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context_window: Option<u32>,
+}
+
+/// `POST /v1/count_tokens`
+///
+/// Count the prompt tokens of a chat completion request without sending it:
+/// with the backend when it can count tokens (e.g. Gemini's `countTokens`),
+/// else with a local tokenizer or estimate.
+pub async fn handle_count_tokens(
+    State(state): State<Arc<AppState>>,
+    virtual_key: Option<Extension<VirtualKey>>,
+    Json(request): Json<ChatCompletionRequest>,
+) -> Result<Json<TokenCount>, ApiError> {
+    let gateway = state.gateway();
+    let model_id = ModelIdentifier::resolve(&gateway.config, &request.model)?;
+    check_access(&state, &gateway.config, virtual_key.as_deref(), &model_id).await?;
+
+    let backend = gateway
+        .backends
+        .get(&model_id.backend)
+        .ok_or_else(|| ApiError::BackendNotConfigured(model_id.backend.clone()))?;
+    let (prompt_tokens, exact) = match backend
+        .count_tokens(&model_id.model_name, &request)
+        .await
+        .map_err(ApiError::from_backend)?
+    {
+        Some(tokens) => (tokens, true),
+        None => {
+            let count = tokens::count_request(&model_id.model_name, &request);
+            (count.tokens, count.exact)
+        }
+    };
+
+    Ok(Json(TokenCount {
+        context_window: gateway
+            .config
+            .context_window(&model_id.backend, &model_id.model_name),
+        model: format!("{}:{}", model_id.backend, model_id.model_name),
+        prompt_tokens,
+        exact,
+    }))
+}
@@ -0,0 +1,151 @@
+use {
+    crate::{ApiError, AppState},
+    axum::{
+        body::Body,
+        extract::{Path, State},
+        http::{header, StatusCode},
+        response::Response,
+        Json,
+    },
+    futures_util::StreamExt,
+    serde::Deserialize,
+    std::{convert::Infallible, sync::Arc},
+    topkio_primitive::api::{ModelDetails, RunningModel, UnifiedLlmApi},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct PullRequest {
+    model: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoadRequest {
+    model: String,
+    /// E.g. `10m`, or `-1` to keep the model loaded.
+    keep_alive: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnloadRequest {
+    model: String,
+}
+
+/// The backend `name`, when it manages its models.
+fn backend(state: &AppState, name: &str) -> Result<Arc<dyn UnifiedLlmApi>, ApiError> {
+    let backend = state
+        .gateway()
+        .backends
+        .get(&name.to_lowercase())
+        .cloned()
+        .ok_or_else(|| ApiError::BackendNotConfigured(name.to_string()))?;
+    if backend.model_manager().is_none() {
+        return Err(ApiError::BadRequest(format!(
+            "backend {} does not manage its models",
+            name
+        )));
+    }
+    Ok(backend)
+}
+
+/// `POST /admin/backends/{name}/pull`
+///
+/// Download a model, streaming progress as newline-delimited JSON. A failed
+/// download ends with an `{"error": ...}` line.
+pub async fn pull_model(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Json(request): Json<PullRequest>,
+) -> Result<Response, ApiError> {
+    let backend = backend(&state, &name)?;
+    let manager = backend.model_manager().expect("checked by backend()");
+    let progress = manager
+        .pull_model(&request.model)
+        .await
+        .map_err(ApiError::from_backend)?;
+
+    let lines = progress.map(|step| {
+        let line = match step {
+            Ok(step) => serde_json::to_string(&step),
+            Err(e) => serde_json::to_string(&serde_json::json!({ "error": e.to_string() })),
+        };
+        Ok::<_, Infallible>(format!("{}\n", line.unwrap_or_default()))
+    });
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/x-ndjson")
+        .body(Body::from_stream(lines))
+        .expect("valid response"))
+}
+
+/// `GET /admin/backends/{name}/models/{model}`
+pub async fn show_model(
+    State(state): State<Arc<AppState>>,
+    Path((name, model)): Path<(String, String)>,
+) -> Result<Json<ModelDetails>, ApiError> {
+    let backend = backend(&state, &name)?;
+    let manager = backend.model_manager().expect("checked by backend()");
+    let details = manager
+        .show_model(&model)
+        .await
+        .map_err(ApiError::from_backend)?;
+    Ok(Json(details))
+}
+
+/// `DELETE /admin/backends/{name}/models/{model}`
+pub async fn delete_model(
+    State(state): State<Arc<AppState>>,
+    Path((name, model)): Path<(String, String)>,
+) -> Result<StatusCode, ApiError> {
+    let backend = backend(&state, &name)?;
+    let manager = backend.model_manager().expect("checked by backend()");
+    manager
+        .delete_model(&model)
+        .await
+        .map_err(ApiError::from_backend)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `GET /admin/backends/{name}/running`
+pub async fn running_models(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Result<Json<Vec<RunningModel>>, ApiError> {
+    let backend = backend(&state, &name)?;
+    let manager = backend.model_manager().expect("checked by backend()");
+    let models = manager
+        .running_models()
+        .await
+        .map_err(ApiError::from_backend)?;
+    Ok(Json(models))
+}
+
+/// `POST /admin/backends/{name}/load`
+///
+/// Load a model now, e.g. before traffic arrives.
+pub async fn load_model(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Json(request): Json<LoadRequest>,
+) -> Result<(), ApiError> {
+    let backend = backend(&state, &name)?;
+    let manager = backend.model_manager().expect("checked by backend()");
+    let keep_alive = request.keep_alive.as_deref().unwrap_or("5m");
+    manager
+        .keep_alive(&request.model, keep_alive)
+        .await
+        .map_err(ApiError::from_backend)
+}
+
+/// `POST /admin/backends/{name}/unload`
+pub async fn unload_model(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Json(request): Json<UnloadRequest>,
+) -> Result<(), ApiError> {
+    let backend = backend(&state, &name)?;
+    let manager = backend.model_manager().expect("checked by backend()");
+    manager
+        .keep_alive(&request.model, "0")
+        .await
+        .map_err(ApiError::from_backend)
+}
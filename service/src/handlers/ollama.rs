@@ -0,0 +1,371 @@
+//! An Ollama-compatible API (`/api/chat`, `/api/generate`, `/api/tags`,
+//! `/api/embed`), so tools that only speak Ollama can use any backend.
+//!
+//! Chats stream newline-delimited JSON objects as the backend generates,
+//! the final `done` one holding the counts. Backends that cannot stream, and
+//! raw generation, answer in a single `done` object.
+
+mod protocol;
+
+use {
+    super::{
+        chat_completion::{
+            check_access, complete_chat, complete_chat_stream, record_usage, ChatStream,
+        },
+        completions::complete_text,
+        ModelIdentifier,
+    },
+    crate::{usage::Caller, virtual_keys::VirtualKey, ApiError, AppState},
+    axum::{
+        body::Body,
+        extract::State,
+        http::{header, HeaderMap, HeaderValue},
+        response::{IntoResponse, Response},
+        Extension, Json,
+    },
+    futures_util::{future::join_all, StreamExt},
+    protocol::{
+        timestamp, ChatRequest, ChatResponse, EmbedInput, EmbedRequest, EmbedResponse,
+        GenerateRequest, GenerateResponse, ResponseMessage, Stats, Tag, TagDetails, TagsResponse,
+    },
+    serde::Serialize,
+    sha2::{Digest, Sha256},
+    std::{
+        collections::BTreeMap,
+        convert::Infallible,
+        sync::Arc,
+        time::{Duration, Instant, SystemTime},
+    },
+    tokio::time::timeout,
+    topkio_primitive::api::{ChatCompletionDelta, Message},
+};
+
+/// Backends not listing their models within this time are left out of
+/// `/api/tags`.
+const LIST_MODELS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An error in Ollama's format, `{"error": "..."}`, with the status of the
+/// gateway error.
+pub struct OllamaError(ApiError);
+
+impl From<ApiError> for OllamaError {
+    fn from(error: ApiError) -> Self {
+        Self(error)
+    }
+}
+
+impl IntoResponse for OllamaError {
+    fn into_response(self) -> Response {
+        let message = self.0.to_string();
+        let (parts, _) = self.0.into_response().into_parts();
+        let body = serde_json::json!({ "error": message }).to_string();
+        Response::from_parts(parts, Body::from(body))
+    }
+}
+
+/// The final response object, as a single line of newline-delimited JSON
+/// when the client asked for a stream of an answer made in one piece, else
+/// as JSON.
+fn respond<T: Serialize>(headers: HeaderMap, stream: bool, object: T) -> Response {
+    if !stream {
+        return (headers, Json(object)).into_response();
+    }
+
+    let body = serde_json::to_string(&object).unwrap_or_default() + "\n";
+    let mut response = (headers, body).into_response();
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/x-ndjson"),
+    );
+    response
+}
+
+/// One newline-delimited JSON object per piece of `deltas`, made by
+/// `object`. A failure ends the stream with an `{"error": ...}` line, as
+/// Ollama does.
+fn stream_objects<T, F>(headers: HeaderMap, deltas: ChatStream, mut object: F) -> Response
+where
+    T: Serialize,
+    F: FnMut(ChatCompletionDelta) -> T + Send + 'static,
+{
+    let lines = deltas.map(move |delta| {
+        let line = match delta {
+            Ok(delta) => serde_json::to_string(&object(delta)),
+            Err(e) => serde_json::to_string(&serde_json::json!({ "error": e.to_string() })),
+        };
+        Ok::<_, Infallible>(format!("{}\n", line.unwrap_or_default()))
+    });
+    let mut response = (headers, Body::from_stream(lines)).into_response();
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/x-ndjson"),
+    );
+    response
+}
+
+/// The counts of the last piece of a stream, which has the finish reason.
+fn stream_stats(delta: &ChatCompletionDelta, started: Instant) -> Option<Stats> {
+    delta.finish_reason.as_ref().map(|reason| {
+        Stats::new(
+            Some(reason),
+            delta.usage.as_ref(),
+            started.elapsed().as_nanos() as u64,
+        )
+    })
+}
+
+/// `POST /api/chat`
+pub async fn handle_chat(
+    State(state): State<Arc<AppState>>,
+    virtual_key: Option<Extension<VirtualKey>>,
+    caller: Option<Extension<Caller>>,
+    Json(request): Json<ChatRequest>,
+) -> Result<Response, OllamaError> {
+    let started = Instant::now();
+    let (model, stream) = (request.model.clone(), request.stream);
+    if stream {
+        let virtual_key = virtual_key.map(|Extension(key)| key);
+        let caller = caller.map(|Extension(caller)| caller);
+        let (headers, deltas) =
+            complete_chat_stream(state, virtual_key, caller, request.into()).await?;
+        return Ok(stream_objects(headers, deltas, move |delta| {
+            let stats = stream_stats(&delta, started);
+            let mut message = Message::new("assistant", delta.content);
+            message.tool_calls = (!delta.tool_calls.is_empty()).then_some(delta.tool_calls);
+            ChatResponse {
+                model: model.clone(),
+                created_at: timestamp(SystemTime::now()),
+                message: ResponseMessage::from(message),
+                done: stats.is_some(),
+                stats,
+            }
+        }));
+    }
+
+    let (headers, response) = complete_chat(
+        &state,
+        virtual_key.as_deref(),
+        caller.as_deref(),
+        request.into(),
+    )
+    .await?;
+
+    let created_at = timestamp(SystemTime::now());
+    let stats = Stats::new(
+        response.finish_reason.as_deref(),
+        response.usage.as_ref(),
+        started.elapsed().as_nanos() as u64,
+    );
+    let response = ChatResponse {
+        model,
+        created_at,
+        message: ResponseMessage::from(response.message),
+        done: true,
+        stats: Some(stats),
+    };
+    Ok(respond(headers, stream, response))
+}
+
+/// `POST /api/generate`
+///
+/// Requests with `suffix`, `raw` or `context` need a backend with text
+/// completion; others are sent as a chat turn to any backend.
+pub async fn handle_generate(
+    State(state): State<Arc<AppState>>,
+    virtual_key: Option<Extension<VirtualKey>>,
+    caller: Option<Extension<Caller>>,
+    Json(request): Json<GenerateRequest>,
+) -> Result<Response, OllamaError> {
+    let started = Instant::now();
+    let (model, stream) = (request.model.clone(), request.stream);
+    let created_at = timestamp(SystemTime::now());
+
+    // Ollama loads the model when the prompt is empty; there is nothing to load.
+    if request.prompt.is_empty() && request.suffix.is_none() {
+        ModelIdentifier::resolve(&state.gateway().config, &model)?;
+        let response = GenerateResponse {
+            model,
+            created_at,
+            response: String::new(),
+            done: true,
+            context: None,
+            stats: None,
+        };
+        return Ok(respond(HeaderMap::new(), false, response));
+    }
+
+    if stream && !request.is_raw() {
+        let virtual_key = virtual_key.map(|Extension(key)| key);
+        let caller = caller.map(|Extension(caller)| caller);
+        let (headers, deltas) =
+            complete_chat_stream(state, virtual_key, caller, request.into()).await?;
+        return Ok(stream_objects(headers, deltas, move |delta| {
+            let stats = stream_stats(&delta, started);
+            GenerateResponse {
+                model: model.clone(),
+                created_at: timestamp(SystemTime::now()),
+                response: delta.content,
+                done: stats.is_some(),
+                context: None,
+                stats,
+            }
+        }));
+    }
+
+    let virtual_key = virtual_key.as_deref();
+    let caller = caller.as_deref();
+    let (headers, text, finish_reason, usage, context) = match request.is_raw() {
+        true => {
+            let (headers, _, response) =
+                complete_text(&state, virtual_key, caller, request.into()).await?;
+            let context = response.context;
+            (
+                headers,
+                response.text,
+                response.finish_reason,
+                response.usage,
+                context,
+            )
+        }
+        false => {
+            let (headers, response) =
+                complete_chat(&state, virtual_key, caller, request.into()).await?;
+            (
+                headers,
+                response.message.content.text(),
+                response.finish_reason,
+                response.usage,
+                None,
+            )
+        }
+    };
+
+    let stats = Stats::new(
+        finish_reason.as_deref(),
+        usage.as_ref(),
+        started.elapsed().as_nanos() as u64,
+    );
+    let response = GenerateResponse {
+        model,
+        created_at,
+        response: text,
+        done: true,
+        context,
+        stats: Some(stats),
+    };
+    Ok(respond(headers, stream, response))
+}
+
+/// `GET /api/tags`
+///
+/// The models of every enabled backend as `backend:model`, and the aliases.
+pub async fn handle_tags(State(state): State<Arc<AppState>>) -> Json<TagsResponse> {
+    let gateway = state.gateway();
+
+    let listings = gateway
+        .config
+        .providers
+        .iter()
+        .filter(|(_, provider)| provider.enabled)
+        .map(|(name, provider)| {
+            let backend = gateway.backends.get(&name.to_lowercase()).cloned();
+            async move {
+                let mut models: Vec<String> = provider
+                    .supported_models
+                    .iter()
+                    .filter(|model| !model.contains('*'))
+                    .cloned()
+                    .collect();
+                if let Some(backend) = backend {
+                    match timeout(LIST_MODELS_TIMEOUT, backend.get_models()).await {
+                        Ok(Ok(reported)) => models.extend(reported.into_iter().map(|m| m.id)),
+                        Ok(Err(e)) => eprintln!("{}: failed to list models: {}", name, e),
+                        Err(_) => eprintln!("{}: listing models timed out", name),
+                    }
+                }
+                (name, provider.kind.clone(), models)
+            }
+        });
+
+    // Model name -> family.
+    let mut names = BTreeMap::new();
+    for (name, kind, models) in join_all(listings).await {
+        for model in models {
+            names.insert(format!("{}:{}", name, model), kind.clone());
+        }
+    }
+    for alias in gateway.config.aliases.keys() {
+        names.insert(alias.clone(), "alias".into());
+    }
+
+    let modified_at = timestamp(SystemTime::now());
+    let models = names
+        .into_iter()
+        .map(|(name, family)| Tag {
+            model: name.clone(),
+            modified_at: modified_at.clone(),
+            size: 0,
+            digest: Sha256::digest(name.as_bytes())
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+            details: TagDetails {
+                format: String::new(),
+                family,
+                parameter_size: String::new(),
+                quantization_level: String::new(),
+            },
+            name,
+        })
+        .collect();
+    Json(TagsResponse { models })
+}
+
+/// `POST /api/embed`
+pub async fn handle_embed(
+    State(state): State<Arc<AppState>>,
+    virtual_key: Option<Extension<VirtualKey>>,
+    caller: Option<Extension<Caller>>,
+    Json(request): Json<EmbedRequest>,
+) -> Result<(HeaderMap, Json<EmbedResponse>), OllamaError> {
+    let gateway = state.gateway();
+    let model_id = ModelIdentifier::resolve(&gateway.config, &request.model)?;
+    check_access(&state, &gateway.config, virtual_key.as_deref(), &model_id).await?;
+    let backend = gateway
+        .backends
+        .get(&model_id.backend)
+        .ok_or_else(|| ApiError::BackendNotConfigured(model_id.backend.clone()))?;
+
+    let input = match request.input {
+        EmbedInput::One(input) => vec![input],
+        EmbedInput::Many(input) => input,
+    };
+    let response = backend
+        .embed(&model_id.model_name, input)
+        .await
+        .map_err(ApiError::from_backend);
+    state.circuits.record(
+        gateway.config.circuit_breaker.as_ref(),
+        &model_id.backend,
+        response.as_ref().err(),
+    );
+    let headers = record_usage(
+        &state,
+        &gateway.config,
+        virtual_key.as_deref(),
+        caller.as_deref(),
+        &model_id,
+        response.as_ref().ok().and_then(|r| r.usage.as_ref()),
+    )
+    .await;
+    let response = response?;
+
+    Ok((
+        headers,
+        Json(EmbedResponse {
+            model: request.model,
+            embeddings: response.embeddings,
+            prompt_eval_count: response.usage.map(|usage| usage.prompt_tokens),
+        }),
+    ))
+}
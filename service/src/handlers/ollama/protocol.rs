@@ -0,0 +1,478 @@
+//! Ollama's request and response formats, and their translation to the
+//! gateway's.
+
+use {
+    serde::{Deserialize, Serialize},
+    serde_json::Value,
+    std::time::{SystemTime, UNIX_EPOCH},
+    topkio_primitive::api::{
+        ChatCompletionRequest, CompletionRequest, ContentPart, FunctionCall, ImageUrl,
+        JsonSchemaFormat, Message, MessageContent, ResponseFormat, Tool, ToolCall, Usage,
+    },
+};
+
+#[derive(Debug, Deserialize)]
+pub struct ChatRequest {
+    pub model: String,
+    #[serde(default)]
+    pub messages: Vec<OllamaMessage>,
+    #[serde(default)]
+    pub tools: Option<Vec<Tool>>,
+    /// `"json"`, or a JSON Schema the answer must match.
+    #[serde(default)]
+    pub format: Option<Value>,
+    #[serde(default)]
+    pub options: ModelOptions,
+    #[serde(default = "default_stream")]
+    pub stream: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OllamaMessage {
+    pub role: String,
+    #[serde(default)]
+    pub content: String,
+    /// Base64-encoded images.
+    #[serde(default)]
+    pub images: Vec<String>,
+    #[serde(default)]
+    pub tool_calls: Vec<OllamaToolCall>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OllamaToolCall {
+    pub function: OllamaFunctionCall,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OllamaFunctionCall {
+    pub name: String,
+    /// Arguments as a JSON object, not a string as in OpenAI's format.
+    #[serde(default)]
+    pub arguments: Value,
+}
+
+/// The generation options the gateway passes on; others are ignored.
+#[derive(Debug, Default, Deserialize)]
+pub struct ModelOptions {
+    /// Maximum number of tokens to generate; negative for no limit.
+    pub num_predict: Option<i64>,
+    pub temperature: Option<f32>,
+    pub stop: Option<Vec<String>>,
+}
+
+impl ModelOptions {
+    fn max_tokens(&self) -> Option<u32> {
+        self.num_predict
+            .and_then(|tokens| u32::try_from(tokens).ok())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GenerateRequest {
+    pub model: String,
+    #[serde(default)]
+    pub prompt: String,
+    pub suffix: Option<String>,
+    pub system: Option<String>,
+    #[serde(default)]
+    pub images: Vec<String>,
+    #[serde(default)]
+    pub format: Option<Value>,
+    #[serde(default)]
+    pub options: ModelOptions,
+    #[serde(default = "default_stream")]
+    pub stream: bool,
+    pub raw: Option<bool>,
+    pub context: Option<Vec<i64>>,
+}
+
+impl GenerateRequest {
+    /// Whether the request needs a raw completion rather than a chat turn.
+    pub fn is_raw(&self) -> bool {
+        self.suffix.is_some() || self.raw == Some(true) || self.context.is_some()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmbedRequest {
+    pub model: String,
+    pub input: EmbedInput,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum EmbedInput {
+    One(String),
+    Many(Vec<String>),
+}
+
+// Ollama streams unless told otherwise.
+fn default_stream() -> bool {
+    true
+}
+
+impl From<ChatRequest> for ChatCompletionRequest {
+    fn from(request: ChatRequest) -> Self {
+        Self {
+            model: request.model,
+            messages: request.messages.into_iter().map(Message::from).collect(),
+            stream: Some(false),
+            max_tokens: request.options.max_tokens(),
+            temperature: request.options.temperature,
+            stop: request.options.stop,
+            tools: request.tools,
+            response_format: request.format.and_then(response_format),
+            ..Default::default()
+        }
+    }
+}
+
+/// A generate request as a chat turn, for backends without raw completion.
+impl From<GenerateRequest> for ChatCompletionRequest {
+    fn from(request: GenerateRequest) -> Self {
+        let mut messages = vec![];
+        if let Some(system) = request.system {
+            messages.push(Message::new("system", system));
+        }
+        messages.push(Message::from(OllamaMessage {
+            role: "user".into(),
+            content: request.prompt,
+            images: request.images,
+            tool_calls: vec![],
+        }));
+
+        Self {
+            model: request.model,
+            messages,
+            stream: Some(false),
+            max_tokens: request.options.max_tokens(),
+            temperature: request.options.temperature,
+            stop: request.options.stop,
+            response_format: request.format.and_then(response_format),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<GenerateRequest> for CompletionRequest {
+    fn from(request: GenerateRequest) -> Self {
+        Self {
+            max_tokens: request.options.max_tokens(),
+            temperature: request.options.temperature,
+            stop: request.options.stop,
+            model: request.model,
+            prompt: request.prompt,
+            suffix: request.suffix,
+            raw: request.raw,
+            context: request.context,
+        }
+    }
+}
+
+impl From<OllamaMessage> for Message {
+    fn from(message: OllamaMessage) -> Self {
+        let content = match message.images.is_empty() {
+            true => MessageContent::Text(message.content),
+            false => {
+                let mut parts = vec![ContentPart::Text {
+                    text: message.content,
+                }];
+                parts.extend(
+                    message
+                        .images
+                        .into_iter()
+                        .map(|data| ContentPart::ImageUrl {
+                            image_url: ImageUrl {
+                                url: format!("data:{};base64,{}", image_type(&data), data),
+                                detail: None,
+                            },
+                        }),
+                );
+                MessageContent::Parts(parts)
+            }
+        };
+        let tool_calls = message
+            .tool_calls
+            .into_iter()
+            .map(|call| ToolCall {
+                id: String::new(),
+                kind: "function".into(),
+                function: FunctionCall {
+                    name: call.function.name,
+                    arguments: call.function.arguments.to_string(),
+                },
+            })
+            .collect::<Vec<_>>();
+
+        Self {
+            role: message.role,
+            content,
+            tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+            tool_call_id: None,
+        }
+    }
+}
+
+fn response_format(format: Value) -> Option<ResponseFormat> {
+    match format {
+        Value::String(format) if format == "json" => Some(ResponseFormat::JsonObject),
+        schema @ Value::Object(_) => Some(ResponseFormat::JsonSchema {
+            json_schema: JsonSchemaFormat {
+                name: "response".into(),
+                description: None,
+                schema,
+                strict: None,
+            },
+        }),
+        _ => None,
+    }
+}
+
+/// MIME type of a base64-encoded image, from its first bytes. Ollama
+/// images carry no type.
+fn image_type(data: &str) -> &'static str {
+    match data {
+        _ if data.starts_with("/9j/") => "image/jpeg",
+        _ if data.starts_with("R0lGOD") => "image/gif",
+        _ if data.starts_with("UklGR") => "image/webp",
+        _ => "image/png",
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChatResponse {
+    pub model: String,
+    pub created_at: String,
+    pub message: ResponseMessage,
+    pub done: bool,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<Stats>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResponseMessage {
+    pub role: String,
+    pub content: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<OllamaToolCall>,
+}
+
+impl From<Message> for ResponseMessage {
+    fn from(message: Message) -> Self {
+        Self {
+            role: message.role,
+            content: message.content.text(),
+            tool_calls: message
+                .tool_calls
+                .unwrap_or_default()
+                .into_iter()
+                .map(|call| OllamaToolCall {
+                    function: OllamaFunctionCall {
+                        arguments: serde_json::from_str(&call.function.arguments)
+                            .unwrap_or(Value::String(call.function.arguments)),
+                        name: call.function.name,
+                    },
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct GenerateResponse {
+    pub model: String,
+    pub created_at: String,
+    pub response: String,
+    pub done: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<Vec<i64>>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<Stats>,
+}
+
+/// Fields of the last object of a response.
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub done_reason: String,
+    /// Nanoseconds spent on the request.
+    pub total_duration: u64,
+    pub prompt_eval_count: u32,
+    pub eval_count: u32,
+}
+
+impl Stats {
+    /// Ollama reports tool calls as `stop`.
+    pub fn new(finish_reason: Option<&str>, usage: Option<&Usage>, total_duration: u64) -> Self {
+        let done_reason = match finish_reason {
+            Some("tool_calls") | None => "stop",
+            Some(reason) => reason,
+        };
+        Self {
+            done_reason: done_reason.into(),
+            total_duration,
+            prompt_eval_count: usage.map_or(0, |usage| usage.prompt_tokens),
+            eval_count: usage.map_or(0, |usage| usage.completion_tokens),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagsResponse {
+    pub models: Vec<Tag>,
+}
+
+/// A model in `/api/tags`.
+#[derive(Debug, Serialize)]
+pub struct Tag {
+    pub name: String,
+    pub model: String,
+    pub modified_at: String,
+    pub size: u64,
+    pub digest: String,
+    pub details: TagDetails,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagDetails {
+    pub format: String,
+    /// The provider type, e.g. `gemini`.
+    pub family: String,
+    pub parameter_size: String,
+    pub quantization_level: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmbedResponse {
+    pub model: String,
+    pub embeddings: Vec<Vec<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_eval_count: Option<u32>,
+}
+
+/// RFC 3339 UTC timestamp of `time`, e.g. `2025-01-31T12:00:00.000000Z`.
+pub fn timestamp(time: SystemTime) -> String {
+    let elapsed = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = elapsed.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    let time_of_day = seconds % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day % 3600 / 60,
+        time_of_day % 60,
+        elapsed.subsec_micros()
+    )
+}
+
+/// Date of a number of days since 1970-01-01, in the proleptic Gregorian
+/// calendar (Howard Hinnant's `civil_from_days`).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_options_of_chat_requests() {
+        let request: ChatRequest = serde_json::from_value(serde_json::json!({
+            "model": "gemini:gemini-2.0-flash",
+            "messages": [{"role": "user", "content": "Hi"}],
+            "format": "json",
+            "options": {"num_predict": 32, "temperature": 0.5, "stop": ["\n\n"], "top_k": 40}
+        }))
+        .unwrap();
+        assert!(request.stream);
+
+        let request = ChatCompletionRequest::from(request);
+        assert_eq!(request.max_tokens, Some(32));
+        assert_eq!(request.temperature, Some(0.5));
+        assert_eq!(request.stop, Some(vec!["\n\n".to_string()]));
+        assert!(matches!(
+            request.response_format,
+            Some(ResponseFormat::JsonObject)
+        ));
+    }
+
+    #[test]
+    fn passes_options_of_generate_requests() {
+        let request = || -> GenerateRequest {
+            serde_json::from_value(serde_json::json!({
+                "model": "ollama:llama3",
+                "prompt": "Why is the sky blue?",
+                "system": "Be brief.",
+                "stream": false,
+                "options": {"num_predict": -1, "temperature": 0.1, "stop": ["."]}
+            }))
+            .unwrap()
+        };
+        assert!(!request().is_raw());
+
+        let chat = ChatCompletionRequest::from(request());
+        assert_eq!(chat.messages.len(), 2);
+        assert_eq!(chat.messages[0].role, "system");
+        assert_eq!(chat.max_tokens, None);
+        assert_eq!(chat.temperature, Some(0.1));
+        assert_eq!(chat.stop, Some(vec![".".to_string()]));
+
+        let completion = CompletionRequest::from(request());
+        assert_eq!(completion.prompt, "Why is the sky blue?");
+        assert_eq!(completion.temperature, Some(0.1));
+        assert_eq!(completion.stop, Some(vec![".".to_string()]));
+    }
+
+    #[test]
+    fn translates_images_and_tool_calls() {
+        let message = Message::from(OllamaMessage {
+            role: "assistant".into(),
+            content: "Look".into(),
+            images: vec!["/9j/4AAQ".into()],
+            tool_calls: vec![OllamaToolCall {
+                function: OllamaFunctionCall {
+                    name: "get_weather".into(),
+                    arguments: serde_json::json!({"city": "Paris"}),
+                },
+            }],
+        });
+        assert_eq!(
+            serde_json::to_value(&message.content).unwrap(),
+            serde_json::json!([
+                {"type": "text", "text": "Look"},
+                {"type": "image_url", "image_url": {"url": "data:image/jpeg;base64,/9j/4AAQ"}}
+            ])
+        );
+        let calls = message.tool_calls.clone().unwrap();
+        assert_eq!(calls[0].function.arguments, r#"{"city":"Paris"}"#);
+
+        let response = ResponseMessage::from(message);
+        assert_eq!(
+            response.tool_calls[0].function.arguments,
+            serde_json::json!({"city": "Paris"})
+        );
+    }
+
+    #[test]
+    fn formats_timestamps() {
+        let time = UNIX_EPOCH + std::time::Duration::from_micros(1_709_210_096_123_456);
+        assert_eq!(timestamp(time), "2024-02-29T12:34:56.123456Z");
+    }
+}
@@ -0,0 +1,95 @@
+use {
+    crate::{
+        usage::{GroupBy, UsageFilter},
+        virtual_keys::VirtualKey,
+        ApiError, AppState,
+    },
+    axum::{
+        extract::{Query, State},
+        http::header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+        response::{IntoResponse, Response},
+        Extension, Json,
+    },
+    serde::Deserialize,
+    std::sync::Arc,
+};
+
+/// Written by `csv` from the `UsageRow` fields, except when there are no rows.
+const CSV_HEADER: [&str; 6] = [
+    "group",
+    "requests",
+    "prompt_tokens",
+    "completion_tokens",
+    "cached_tokens",
+    "cost",
+];
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UsageQuery {
+    /// First day included, e.g. `2025-06-01`.
+    from: Option<String>,
+    /// Last day included.
+    to: Option<String>,
+    #[serde(default)]
+    group_by: GroupBy,
+    #[serde(default)]
+    format: ReportFormat,
+}
+
+/// `GET /v1/usage`
+///
+/// Callers using a virtual key only see their own usage.
+pub async fn handle_usage(
+    State(state): State<Arc<AppState>>,
+    virtual_key: Option<Extension<VirtualKey>>,
+    Query(query): Query<UsageQuery>,
+) -> Result<Response, ApiError> {
+    let log = state
+        .usage
+        .as_ref()
+        .ok_or_else(|| ApiError::NotFound("usage reporting is not enabled".into()))?;
+
+    let filter = UsageFilter {
+        from: query.from,
+        to: query.to,
+        key_id: virtual_key.map(|Extension(key)| key.id),
+    };
+    let rows = log.report(query.group_by, &filter)?;
+
+    match query.format {
+        ReportFormat::Json => Ok(Json(rows).into_response()),
+        ReportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            if rows.is_empty() {
+                writer
+                    .write_record(CSV_HEADER)
+                    .map_err(|e| ApiError::StorageError(e.to_string()))?;
+            }
+            for row in &rows {
+                writer
+                    .serialize(row)
+                    .map_err(|e| ApiError::StorageError(e.to_string()))?;
+            }
+            let csv = writer
+                .into_inner()
+                .map_err(|e| ApiError::StorageError(e.to_string()))?;
+
+            Ok((
+                [
+                    (CONTENT_TYPE, "text/csv"),
+                    (CONTENT_DISPOSITION, "attachment; filename=\"usage.csv\""),
+                ],
+                csv,
+            )
+                .into_response())
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use {
+    crate::{
+        virtual_keys::{KeyLimits, KeyStore, KeyUsage, VirtualKey},
+        ApiError, AppState,
+    },
+    axum::{
+        extract::{Path, State},
+        Json,
+    },
+    serde::{Deserialize, Serialize},
+    std::sync::Arc,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct NewVirtualKey {
+    name: String,
+    #[serde(flatten)]
+    limits: KeyLimits,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedVirtualKey {
+    #[serde(flatten)]
+    key: VirtualKey,
+    /// Only returned on creation.
+    secret: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VirtualKeyStatus {
+    #[serde(flatten)]
+    key: VirtualKey,
+    usage: KeyUsage,
+}
+
+fn store(state: &AppState) -> Result<&KeyStore, ApiError> {
+    state
+        .keys
+        .as_ref()
+        .ok_or_else(|| ApiError::NotFound("virtual keys are not enabled".into()))
+}
+
+/// `GET /admin/virtual-keys`
+pub async fn list_virtual_keys(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<VirtualKeyStatus>>, ApiError> {
+    let store = store(&state)?;
+    let mut keys = vec![];
+    for key in store.list().await? {
+        let usage = store.usage(&key.id).await?;
+        keys.push(VirtualKeyStatus { key, usage });
+    }
+    Ok(Json(keys))
+}
+
+/// `POST /admin/virtual-keys`
+pub async fn create_virtual_key(
+    State(state): State<Arc<AppState>>,
+    Json(new): Json<NewVirtualKey>,
+) -> Result<Json<CreatedVirtualKey>, ApiError> {
+    if new.name.trim().is_empty() {
+        return Err(ApiError::BadRequest("name must not be empty".into()));
+    }
+    let (key, secret) = store(&state)?.create(&new.name, new.limits).await?;
+    Ok(Json(CreatedVirtualKey { key, secret }))
+}
+
+/// `GET /admin/virtual-keys/{id}`
+pub async fn get_virtual_key(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<VirtualKeyStatus>, ApiError> {
+    let store = store(&state)?;
+    let key = store
+        .get(&id)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("virtual key {}", id)))?;
+    let usage = store.usage(&id).await?;
+    Ok(Json(VirtualKeyStatus { key, usage }))
+}
+
+/// `DELETE /admin/virtual-keys/{id}`
+pub async fn delete_virtual_key(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<(), ApiError> {
+    match store(&state)?.delete(&id).await? {
+        true => Ok(()),
+        false => Err(ApiError::NotFound(format!("virtual key {}", id))),
+    }
+}
@@ -0,0 +1,355 @@
+mod candidates;
+mod circuit;
+mod config_edit;
+mod context_window;
+mod error;
+use error::ApiError;
+mod handlers;
+mod middleware;
+mod providers;
+mod reload;
+mod shutdown;
+mod structured_output;
+mod usage;
+mod virtual_keys;
+
+pub use {providers::default_registry, topkio_primitive::registry::ProviderRegistry};
+
+use {
+    crate::shutdown::{shutdown_signal, ShutdownConfig},
+    anyhow::Result,
+    arc_swap::ArcSwap,
+    axum::{
+        middleware::from_fn_with_state,
+        routing::{get, post, put},
+        Router,
+    },
+    circuit::Circuits,
+    handlers::{admin, handle_chat_completion, ModelIdentifier},
+    std::{
+        collections::{BTreeMap, HashMap},
+        sync::Arc,
+    },
+    tokio::sync::Mutex,
+    topkio_primitive::{
+        api::{ChatCompletionRequest, Message, ModelInfo, UnifiedLlmApi},
+        config::TopkioConfig,
+        error::ConfigError,
+    },
+    usage::UsageLog,
+    virtual_keys::KeyStore,
+};
+
+pub const DEFAULT_CONFIG_PATH: &str = "topkio.toml";
+
+/// Where to load the config from, and settings overriding it.
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub config_path: String,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            config_path: DEFAULT_CONFIG_PATH.to_string(),
+            host: None,
+            port: None,
+        }
+    }
+}
+
+impl Options {
+    /// Load the config with the overrides applied, and validate it.
+    pub fn load_config(&self, registry: &ProviderRegistry) -> Result<TopkioConfig, ConfigError> {
+        let mut server = toml::Table::new();
+        if let Some(host) = &self.host {
+            server.insert("host".into(), host.clone().into());
+        }
+        if let Some(port) = self.port {
+            server.insert("port".into(), i64::from(port).into());
+        }
+        let mut overrides = toml::Table::new();
+        if !server.is_empty() {
+            overrides.insert("server".into(), server.into());
+        }
+        TopkioConfig::check_overridden(&self.config_path, registry, overrides)
+    }
+}
+
+/// Config and the backends built from it, swapped as a whole on reload.
+struct Gateway {
+    backends: HashMap<String, Arc<dyn UnifiedLlmApi>>,
+    config: TopkioConfig,
+}
+
+struct AppState {
+    /// Requests load the current gateway once and keep it until they finish,
+    /// so a reload never affects in-flight requests.
+    gateway: ArcSwap<Gateway>,
+    registry: ProviderRegistry,
+    options: Options,
+    /// Serializes admin edits and reloads, so none of them is lost.
+    edit_lock: Mutex<()>,
+    keys: Option<KeyStore>,
+    usage: Option<UsageLog>,
+    circuits: Circuits,
+}
+
+impl AppState {
+    fn gateway(&self) -> Arc<Gateway> {
+        self.gateway.load_full()
+    }
+}
+
+/// Validate the config, printing every problem found.
+///
+/// Returns whether the config is valid.
+pub fn check_config(registry: &ProviderRegistry, options: &Options) -> bool {
+    match options.load_config(registry) {
+        Ok(config) => {
+            println!(
+                "{}: OK ({} providers: {})",
+                options.config_path,
+                config.providers.len(),
+                config
+                    .providers
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            true
+        }
+        Err(e) => {
+            eprintln!("{}: {}", options.config_path, e);
+            false
+        }
+    }
+}
+
+/// Print the models of every configured backend as `backend:model`, with
+/// their token limits when the backend reports them.
+///
+/// Lists the configured `supported_models` together with the models the
+/// backend itself reports.
+pub async fn list_models(registry: &ProviderRegistry, options: &Options) -> Result<()> {
+    let config = options.load_config(registry)?;
+    let backends = registry.build(&config.providers).await?;
+
+    for (name, provider) in &config.providers {
+        let mut models: BTreeMap<String, Option<ModelInfo>> = provider
+            .supported_models
+            .iter()
+            .map(|model| (model.clone(), None))
+            .collect();
+        if let Some(backend) = backends.get(&name.to_lowercase()) {
+            match backend.get_models().await {
+                Ok(reported) => models.extend(
+                    reported
+                        .into_iter()
+                        .map(|info| (info.id.clone(), Some(info))),
+                ),
+                Err(e) => eprintln!("{}: failed to list models: {}", name, e),
+            }
+        }
+
+        for (model, info) in models {
+            match info.and_then(|info| Some((info.input_token_limit?, info.output_token_limit?))) {
+                Some((input, output)) => {
+                    println!(
+                        "{}:{} (input {}, output {} tokens)",
+                        name, model, input, output
+                    )
+                }
+                None => println!("{}:{}", name, model),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Send a one-shot prompt to `model` (`backend:model_name`, an alias, ...)
+/// and print the answer.
+pub async fn chat(
+    registry: &ProviderRegistry,
+    options: &Options,
+    model: &str,
+    prompt: &str,
+    system: Option<&str>,
+) -> Result<()> {
+    let config = options.load_config(registry)?;
+    let model_id = ModelIdentifier::resolve(&config, model)?;
+    let provider = config
+        .providers
+        .iter()
+        .find(|(name, _)| name.to_lowercase() == model_id.backend)
+        .map(|(_, provider)| provider)
+        .ok_or_else(|| ApiError::BackendNotConfigured(model_id.backend.clone()))?;
+    let backend = registry.create(&model_id.backend, provider).await?;
+
+    let mut messages = vec![];
+    if let Some(system) = system {
+        messages.push(Message::new("system", system));
+    }
+    messages.push(Message::new("user", prompt));
+
+    let request = ChatCompletionRequest {
+        model: format!("{}:{}", model_id.backend, model_id.model_name),
+        messages,
+        stream: Some(false),
+        ..Default::default()
+    };
+    let response = backend
+        .chat_completion(&model_id.model_name, request)
+        .await?;
+    println!("{}", response.message.content);
+
+    Ok(())
+}
+
+/// Start the gateway, building backends with the factories in `registry`.
+///
+/// Downstream crates can add their own provider types before starting:
+///
+/// ```ignore
+/// let mut registry = topkio_service::default_registry();
+/// registry.register("my-llm", MyFactory);
+/// topkio_service::start(registry, topkio_service::Options::default()).await?;
+/// ```
+pub async fn start(registry: ProviderRegistry, options: Options) -> Result<()> {
+    println!("Starting Topkio Gateway...");
+
+    let config = options.load_config(&registry)?;
+    let backends = registry.build(&config.providers).await?;
+    println!("Backends initialized: {:?}", backends.keys());
+
+    let server = &config.server;
+    let addr = format!("{}:{}", server.host, server.port);
+    let shutdown_config = ShutdownConfig {
+        graceful_timeout: tokio::time::Duration::from_secs(server.graceful_shutdown_seconds),
+        enable_ctrl_c: true,
+        enable_signal: false,
+        enable_custom: server.enable_custom_shutdown,
+    };
+    let watch_config = server.watch_config;
+    let keys = config
+        .virtual_keys
+        .as_ref()
+        .map(|keys| KeyStore::open(&keys.database))
+        .transpose()
+        .map_err(|e| anyhow::anyhow!("Failed to open the virtual key database: {}", e))?;
+    let usage = config
+        .usage
+        .as_ref()
+        .map(|usage| UsageLog::open(&usage.database))
+        .transpose()
+        .map_err(|e| anyhow::anyhow!("Failed to open the usage database: {}", e))?;
+
+    let app_state = Arc::new(AppState {
+        gateway: ArcSwap::from_pointee(Gateway { backends, config }),
+        registry,
+        options,
+        edit_lock: Mutex::new(()),
+        keys,
+        usage,
+        circuits: Circuits::default(),
+    });
+    reload::spawn(app_state.clone(), watch_config)?;
+
+    let api = Router::new()
+        .route("/chat/completions", post(handle_chat_completion))
+        .route(
+            "/v1/completions",
+            post(handlers::completions::handle_completion),
+        )
+        .route("/api/chat", post(handlers::ollama::handle_chat))
+        .route("/api/generate", post(handlers::ollama::handle_generate))
+        .route("/api/tags", get(handlers::ollama::handle_tags))
+        .route("/api/embed", post(handlers::ollama::handle_embed))
+        .route("/v1/messages", post(handlers::anthropic::handle_messages))
+        .route("/v1/usage", get(handlers::usage::handle_usage))
+        .route(
+            "/v1/count_tokens",
+            post(handlers::count_tokens::handle_count_tokens),
+        )
+        .route(
+            "/v1/caches",
+            get(handlers::caches::list_caches).post(handlers::caches::create_cache),
+        )
+        .route(
+            "/v1/caches/{backend}/{id}",
+            axum::routing::delete(handlers::caches::delete_cache),
+        )
+        .layer(from_fn_with_state(
+            app_state.clone(),
+            middleware::auth_middleware,
+        ));
+
+    let admin = Router::new()
+        .route("/backends", get(admin::list_backends))
+        .route("/backends/{name}/enable", post(admin::enable_backend))
+        .route("/backends/{name}/drain", post(admin::drain_backend))
+        .route("/backends/{name}/models", put(admin::set_backend_models))
+        .route("/backends/{name}/pull", post(handlers::models::pull_model))
+        .route(
+            "/backends/{name}/models/{*model}",
+            get(handlers::models::show_model).delete(handlers::models::delete_model),
+        )
+        .route(
+            "/backends/{name}/running",
+            get(handlers::models::running_models),
+        )
+        .route("/backends/{name}/load", post(handlers::models::load_model))
+        .route(
+            "/backends/{name}/unload",
+            post(handlers::models::unload_model),
+        )
+        .route("/routes", get(admin::list_routes))
+        .route("/aliases", get(admin::list_aliases))
+        .route(
+            "/aliases/{alias}",
+            put(admin::set_alias).delete(admin::remove_alias),
+        )
+        .route(
+            "/keys",
+            get(admin::list_keys)
+                .post(admin::create_key)
+                .delete(admin::revoke_key),
+        )
+        .route("/reload", post(admin::reload_config))
+        .route("/caches/flush", post(handlers::caches::flush_caches))
+        .route(
+            "/virtual-keys",
+            get(handlers::virtual_keys::list_virtual_keys)
+                .post(handlers::virtual_keys::create_virtual_key),
+        )
+        .route(
+            "/virtual-keys/{id}",
+            get(handlers::virtual_keys::get_virtual_key)
+                .delete(handlers::virtual_keys::delete_virtual_key),
+        )
+        .layer(from_fn_with_state(
+            app_state.clone(),
+            middleware::admin_middleware,
+        ));
+
+    let app = Router::new()
+        .merge(api)
+        .nest("/admin", admin)
+        .with_state(app_state.clone());
+
+    // Create TCP listener with configurable options
+    let listener = tokio::net::TcpListener::bind(&addr)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to bind to address {}: {}", addr, e))?;
+
+    println!("Server running on http://{} (Press CTRL+C to stop)", addr);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal(shutdown_config))
+        .await?;
+
+    Ok(())
+}
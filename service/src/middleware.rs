@@ -1,5 +1,5 @@
-// pub mod auth;
+pub mod auth;
 // pub mod validation;
 
-// pub use auth::auth_middleware;
+pub use auth::{admin_middleware, auth_middleware};
 // pub use validation::ValidatedJson;
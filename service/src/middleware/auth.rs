@@ -1,35 +1,141 @@
-use axum::{
-    http::Request,
-    middleware::Next,
-    response::Response,
-    extract::State,
-    Json,
+use {
+    crate::{usage::Caller, ApiError, AppState},
+    axum::{
+        extract::{Request, State},
+        http::header::AUTHORIZATION,
+        middleware::Next,
+        response::Response,
+    },
+    sha2::{Digest, Sha256},
+    std::sync::Arc,
 };
-use serde::Deserialize;
 
-#[derive(Deserialize)]
-pub struct AuthParams {
-    pub token: String,
+fn bearer_token(req: &Request) -> Option<&str> {
+    req.headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// The key of a client: a bearer token, or the `x-api-key` header sent by
+/// Anthropic clients.
+fn client_key(req: &Request) -> Option<&str> {
+    bearer_token(req).or_else(|| {
+        req.headers()
+            .get("x-api-key")
+            .and_then(|h| h.to_str().ok())
+            .map(str::trim)
+    })
 }
 
-pub async fn auth_middleware<B>(
-    State(app_state): State<AppState>,
-    mut req: Request<B>,
-    next: Next<B>,
+/// Require one of `auth.api_keys` or a virtual key when any is configured.
+///
+/// Authenticated requests carry a `Caller` extension, and those made with a
+/// virtual key also the `VirtualKey`.
+pub async fn auth_middleware(
+    State(state): State<Arc<AppState>>,
+    mut req: Request,
+    next: Next,
 ) -> Result<Response, ApiError> {
-    // Extract token from headers
-    let token = req.headers()
-        .get("Authorization")
-        .and_then(|h| h.to_str().ok())
-        .ok_or(ApiError::Unauthorized)?;
+    let gateway = state.gateway();
+    let api_keys = &gateway.config.auth.api_keys;
 
-    // Validate token (example using app state)
-    if !app_state.valid_tokens.contains(token) {
-        return Err(ApiError::Unauthorized);
+    if api_keys.is_empty() && state.keys.is_none() {
+        return Ok(next.run(req).await);
     }
 
-    // Add user data to request extensions
-    req.extensions_mut().insert(User { id: 123 });
+    let token = client_key(&req).ok_or(ApiError::Unauthorized)?;
+    // Compare with every key, so the timing does not tell which one matched.
+    let is_api_key = api_keys
+        .iter()
+        .fold(false, |found, key| found | constant_time_eq(key, token));
+    let caller = match is_api_key {
+        true => Caller {
+            key_name: Some(mask(token)),
+            ..Default::default()
+        },
+        false => {
+            let key = match &state.keys {
+                Some(keys) => keys.authenticate(token).await?,
+                None => None,
+            }
+            .ok_or(ApiError::Unauthorized)?;
+            let caller = Caller {
+                key_id: Some(key.id.clone()),
+                key_name: Some(key.name.clone()),
+                team: key.limits.tags.get("team").cloned(),
+            };
+            req.extensions_mut().insert(key);
+            caller
+        }
+    };
+    req.extensions_mut().insert(caller);
 
     Ok(next.run(req).await)
-}
\ No newline at end of file
+}
+
+/// Compare secrets in a time that depends on neither their content nor their
+/// lengths, by comparing their digests.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (Sha256::digest(a), Sha256::digest(b));
+    a.iter()
+        .zip(b.iter())
+        .fold(0, |diff, (x, y)| diff | (x ^ y))
+        == 0
+}
+
+/// Hide all but the last characters of a key.
+pub fn mask(key: &str) -> String {
+    let visible: String = key
+        .chars()
+        .rev()
+        .take(4)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .collect();
+    match key.chars().count() > 8 {
+        true => format!("****{}", visible),
+        false => "****".into(),
+    }
+}
+
+/// Require `admin.api_key`; the admin API does not exist without it.
+pub async fn admin_middleware(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let gateway = state.gateway();
+    let admin = gateway
+        .config
+        .admin
+        .as_ref()
+        .ok_or_else(|| ApiError::NotFound(req.uri().path().to_string()))?;
+
+    if !bearer_token(&req).is_some_and(|token| constant_time_eq(token, &admin.api_key)) {
+        return Err(ApiError::Unauthorized);
+    }
+
+    Ok(next.run(req).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_time_eq_compares_content() {
+        assert!(constant_time_eq("sk-admin", "sk-admin"));
+        assert!(!constant_time_eq("sk-admin", "sk-admiN"));
+        assert!(!constant_time_eq("sk-admin", "sk-admin2"));
+        assert!(!constant_time_eq("", "sk-admin"));
+    }
+
+    #[test]
+    fn mask_hides_short_keys() {
+        assert_eq!(mask("sk-1234567890"), "****7890");
+        assert_eq!(mask("short"), "****");
+    }
+}
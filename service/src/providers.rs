@@ -0,0 +1,463 @@
+use {
+    anyhow::Result,
+    std::{sync::Arc, time::Duration},
+    topkio_anthropic::AnthropicBackend,
+    topkio_google::{GeminiAuth, GeminiBackend, ServiceAccount, ServiceAccountKey},
+    topkio_ollama::OllamaBackend,
+    topkio_openai::{AzureOpenAiBackend, AzureResource, OpenAiBackend},
+    topkio_primitive::{
+        api::UnifiedLlmApi,
+        config::{
+            check_url, AzureProviderConfig, GeminiProviderConfig, OllamaProviderConfig,
+            ProviderConfig,
+        },
+        error::ConfigIssue,
+        registry::{ProviderFactory, ProviderRegistry},
+    },
+};
+
+/// Registry with every provider type shipped with topkio.
+pub fn default_registry() -> ProviderRegistry {
+    let mut registry = ProviderRegistry::new();
+    registry
+        .register("ollama", OllamaFactory)
+        .register("gemini", GeminiFactory)
+        .register("anthropic", AnthropicFactory)
+        .register("openai", OpenAiFactory)
+        .register("deepseek", OpenAiFactory)
+        .register("azure", AzureFactory);
+    registry
+}
+
+fn required_api_key(name: &str, config: &ProviderConfig) -> Result<String> {
+    match check_api_key(name, config) {
+        Some(issue) => Err(anyhow::anyhow!("{}", issue)),
+        None => Ok(config.api_key.clone().unwrap_or_default()),
+    }
+}
+
+/// Check that the instance `name` has an `api_key`, which must not be
+/// blank (e.g. an unset `${VAR:-}`).
+fn check_api_key(name: &str, config: &ProviderConfig) -> Option<ConfigIssue> {
+    let path = format!("providers.{}.api_key", name);
+    match config.api_key.as_deref().map(str::trim) {
+        Some("") => Some(ConfigIssue::new(path, "must not be empty")),
+        Some(_) => None,
+        None => Some(ConfigIssue::new(path, "required")),
+    }
+}
+
+pub struct OllamaFactory;
+
+#[async_trait::async_trait]
+impl ProviderFactory for OllamaFactory {
+    async fn create(&self, name: &str, config: &ProviderConfig) -> Result<Arc<dyn UnifiedLlmApi>> {
+        let ollama_cfg: OllamaProviderConfig = config.options()?;
+        let auto_pull = match ollama_cfg.auto_pull {
+            true => config.supported_models.clone(),
+            false => vec![],
+        };
+        let backend = OllamaBackend::new(config.url.clone()).with_auto_pull(auto_pull);
+        backend.health_check().await?;
+        println!("Ollama backend {} initialized", name);
+
+        Ok(Arc::new(backend))
+    }
+
+    fn validate(&self, name: &str, config: &ProviderConfig) -> Vec<ConfigIssue> {
+        let mut issues: Vec<ConfigIssue> =
+            check_url(&format!("providers.{}.url", name), &config.url)
+                .into_iter()
+                .collect();
+        match config.options::<OllamaProviderConfig>() {
+            Ok(ollama_cfg) if ollama_cfg.auto_pull && config.supported_models.is_empty() => {
+                issues.push(ConfigIssue::new(
+                    format!("providers.{}.auto_pull", name),
+                    "only pulls models listed in supported_models, which is empty",
+                ));
+            }
+            Ok(_) => {}
+            Err(e) => issues.push(ConfigIssue::new(
+                format!("providers.{}", name),
+                e.to_string(),
+            )),
+        }
+        issues
+    }
+}
+
+/// Blocking thresholds accepted by Gemini safety settings.
+const SAFETY_THRESHOLDS: &[&str] = &[
+    "HARM_BLOCK_THRESHOLD_UNSPECIFIED",
+    "BLOCK_LOW_AND_ABOVE",
+    "BLOCK_MEDIUM_AND_ABOVE",
+    "BLOCK_ONLY_HIGH",
+    "BLOCK_NONE",
+    "OFF",
+];
+
+/// The Gemini API, or Gemini on Vertex AI when `project` is set.
+pub struct GeminiFactory;
+
+impl GeminiFactory {
+    /// The service-account key of the provider: `credentials`, else the file
+    /// named by `GOOGLE_APPLICATION_CREDENTIALS` on Vertex AI.
+    fn service_account_key(
+        name: &str,
+        gemini_cfg: &GeminiProviderConfig,
+    ) -> Result<Option<ServiceAccountKey>> {
+        let credentials = match (&gemini_cfg.credentials, &gemini_cfg.project) {
+            (Some(credentials), _) => credentials.clone(),
+            (None, Some(_)) => {
+                let path = std::env::var("GOOGLE_APPLICATION_CREDENTIALS").map_err(|_| {
+                    anyhow::anyhow!(
+                        "providers.{}.credentials or GOOGLE_APPLICATION_CREDENTIALS is required for Vertex AI",
+                        name
+                    )
+                })?;
+                std::fs::read_to_string(&path)
+                    .map_err(|e| anyhow::anyhow!("cannot read {}: {}", path, e))?
+            }
+            (None, None) => return Ok(None),
+        };
+
+        ServiceAccountKey::from_json(&credentials).map(Some)
+    }
+}
+
+#[async_trait::async_trait]
+impl ProviderFactory for GeminiFactory {
+    async fn create(&self, name: &str, config: &ProviderConfig) -> Result<Arc<dyn UnifiedLlmApi>> {
+        let gemini_cfg: GeminiProviderConfig = config.options()?;
+
+        let auth = match Self::service_account_key(name, &gemini_cfg)? {
+            Some(key) => GeminiAuth::ServiceAccount(ServiceAccount::new(key)),
+            None => GeminiAuth::ApiKey(required_api_key(name, config)?),
+        };
+        let backend = match &gemini_cfg.project {
+            Some(project) if config.url.is_empty() => {
+                GeminiBackend::vertex(project, &gemini_cfg.location, auth)
+            }
+            _ => GeminiBackend::with_auth(config.url.clone(), auth),
+        }
+        .with_safety_settings(gemini_cfg.safety_settings);
+        let backend = match gemini_cfg.auto_cache_min_tokens {
+            Some(min_tokens) => {
+                backend.with_auto_cache(min_tokens, Duration::from_secs(gemini_cfg.cache_ttl))
+            }
+            None => backend,
+        };
+
+        Ok(Arc::new(backend))
+    }
+
+    fn validate(&self, name: &str, config: &ProviderConfig) -> Vec<ConfigIssue> {
+        let gemini_cfg: GeminiProviderConfig = match config.options() {
+            Ok(gemini_cfg) => gemini_cfg,
+            Err(e) => {
+                return vec![ConfigIssue::new(
+                    format!("providers.{}", name),
+                    e.to_string(),
+                )]
+            }
+        };
+
+        let mut issues = vec![];
+        // Vertex AI URLs are derived from the project unless given.
+        if gemini_cfg.project.is_none() || !config.url.is_empty() {
+            issues.extend(check_url(&format!("providers.{}.url", name), &config.url));
+        }
+        for (model, settings) in &gemini_cfg.safety_settings {
+            for (category, threshold) in settings {
+                let path = format!("providers.{}.safety_settings.{}.{}", name, model, category);
+                if !category.starts_with("HARM_CATEGORY_") {
+                    issues.push(ConfigIssue::new(&path, "unknown harm category"));
+                } else if !SAFETY_THRESHOLDS.contains(&threshold.as_str()) {
+                    issues.push(ConfigIssue::new(
+                        &path,
+                        format!("threshold must be one of {}", SAFETY_THRESHOLDS.join(", ")),
+                    ));
+                }
+            }
+        }
+        // Resolve the credentials like `create`, so a missing or malformed
+        // GOOGLE_APPLICATION_CREDENTIALS file fails the check too.
+        match Self::service_account_key(name, &gemini_cfg) {
+            Ok(Some(_)) => {}
+            Ok(None) => issues.extend(check_api_key(name, config)),
+            Err(e) => issues.push(ConfigIssue::new(
+                format!("providers.{}.credentials", name),
+                e.to_string(),
+            )),
+        }
+        if gemini_cfg.cache_ttl == 0 {
+            issues.push(ConfigIssue::new(
+                format!("providers.{}.cache_ttl", name),
+                "must be at least 1 second",
+            ));
+        }
+        issues
+    }
+}
+
+pub struct AnthropicFactory;
+
+#[async_trait::async_trait]
+impl ProviderFactory for AnthropicFactory {
+    async fn create(&self, name: &str, config: &ProviderConfig) -> Result<Arc<dyn UnifiedLlmApi>> {
+        let backend = AnthropicBackend::new(config.url.clone(), required_api_key(name, config)?);
+
+        Ok(Arc::new(backend))
+    }
+
+    fn validate(&self, name: &str, config: &ProviderConfig) -> Vec<ConfigIssue> {
+        let mut issues: Vec<ConfigIssue> =
+            check_url(&format!("providers.{}.url", name), &config.url)
+                .into_iter()
+                .collect();
+        issues.extend(config.unknown_options(name));
+        issues.extend(check_api_key(name, config));
+        issues
+    }
+}
+
+/// OpenAI and OpenAI-compatible APIs (e.g. DeepSeek).
+pub struct OpenAiFactory;
+
+impl OpenAiFactory {
+    /// The API key of the instance `name`. OpenAI itself may also take it
+    /// from `OPENAI_API_KEY`.
+    fn api_key(name: &str, config: &ProviderConfig) -> Result<String, ConfigIssue> {
+        let Some(issue) = check_api_key(name, config) else {
+            return Ok(config.api_key.clone().unwrap_or_default());
+        };
+        if config.kind != "openai" {
+            return Err(issue);
+        }
+        match std::env::var("OPENAI_API_KEY") {
+            Ok(api_key) if !api_key.trim().is_empty() => Ok(api_key),
+            _ => Err(ConfigIssue::new(
+                issue.path,
+                format!(
+                    "required and not empty, or OPENAI_API_KEY must be set for the provider {}",
+                    name
+                ),
+            )),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ProviderFactory for OpenAiFactory {
+    async fn create(&self, name: &str, config: &ProviderConfig) -> Result<Arc<dyn UnifiedLlmApi>> {
+        let api_key = Self::api_key(name, config).map_err(|issue| anyhow::anyhow!("{}", issue))?;
+
+        Ok(Arc::new(OpenAiBackend::new(config.url.clone(), api_key)))
+    }
+
+    fn validate(&self, name: &str, config: &ProviderConfig) -> Vec<ConfigIssue> {
+        let mut issues: Vec<ConfigIssue> =
+            check_url(&format!("providers.{}.url", name), &config.url)
+                .into_iter()
+                .collect();
+        issues.extend(config.unknown_options(name));
+        issues.extend(Self::api_key(name, config).err());
+        issues
+    }
+}
+
+pub struct AzureFactory;
+
+#[async_trait::async_trait]
+impl ProviderFactory for AzureFactory {
+    async fn create(&self, _name: &str, config: &ProviderConfig) -> Result<Arc<dyn UnifiedLlmApi>> {
+        let azure_cfg: AzureProviderConfig = config.options()?;
+        let resources = azure_cfg
+            .resources
+            .into_iter()
+            .map(|resource| AzureResource {
+                url: resource.url,
+                api_key: resource.api_key,
+                deployments: resource.deployments,
+            })
+            .collect();
+
+        Ok(Arc::new(AzureOpenAiBackend::new(
+            azure_cfg.api_version,
+            resources,
+        )))
+    }
+
+    fn validate(&self, name: &str, config: &ProviderConfig) -> Vec<ConfigIssue> {
+        let azure_cfg: AzureProviderConfig = match config.options() {
+            Ok(azure_cfg) => azure_cfg,
+            Err(e) => {
+                return vec![ConfigIssue::new(
+                    format!("providers.{}", name),
+                    e.to_string(),
+                )]
+            }
+        };
+
+        let mut issues = vec![];
+        if azure_cfg.resources.is_empty() {
+            issues.push(ConfigIssue::new(
+                format!("providers.{}.resources", name),
+                "at least one resource is required",
+            ));
+        }
+        for (i, resource) in azure_cfg.resources.iter().enumerate() {
+            let path = format!("providers.{}.resources[{}]", name, i);
+            issues.extend(check_url(&format!("{}.url", path), &resource.url));
+            if resource.api_key.trim().is_empty() {
+                issues.push(ConfigIssue::new(
+                    format!("{}.api_key", path),
+                    "must not be empty",
+                ));
+            }
+        }
+        issues
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        topkio_primitive::{config::TopkioConfig, error::ConfigError},
+    };
+
+    /// Paths of the issues `check-config` reports for `providers`.
+    fn issues(test: &str, providers: &str) -> Vec<String> {
+        let source = format!(
+            "[server]\nhost = \"127.0.0.1\"\nport = 3000\n[logging]\nfile_path = \"topkio.log\"\n{}",
+            providers
+        );
+        let path =
+            std::env::temp_dir().join(format!("topkio-{}-{}.toml", test, std::process::id()));
+        std::fs::write(&path, source).unwrap();
+        let result = TopkioConfig::check(path.to_str().unwrap(), &default_registry());
+        std::fs::remove_file(&path).unwrap();
+
+        match result {
+            Ok(_) => vec![],
+            Err(ConfigError::Invalid(issues)) => issues.into_iter().map(|i| i.path).collect(),
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn rejects_empty_api_keys() {
+        std::env::remove_var("TOPKIO_TEST_UNSET_KEY");
+        let providers = r#"
+[providers.gemini]
+url = "https://generativelanguage.googleapis.com/v1beta/models"
+api_key = "${TOPKIO_TEST_UNSET_KEY:-}"
+
+[providers.anthropic]
+url = "https://api.anthropic.com"
+api_key = "  "
+
+[providers.deepseek]
+url = "https://api.deepseek.com"
+api_key = ""
+
+[providers.azure]
+[[providers.azure.resources]]
+url = "https://my-eastus.openai.azure.com"
+api_key = ""
+"#;
+        assert_eq!(
+            issues("empty-keys", providers),
+            [
+                "providers.gemini.api_key",
+                "providers.anthropic.api_key",
+                "providers.deepseek.api_key",
+                "providers.azure.resources[0].api_key",
+            ]
+        );
+    }
+
+    #[test]
+    fn requires_api_keys_unless_gemini_uses_a_service_account() {
+        let providers = r#"
+[providers.anthropic]
+url = "https://api.anthropic.com"
+
+[providers.vertex]
+type = "gemini"
+project = "my-project"
+credentials = "{}"
+"#;
+        let found = issues("missing-keys", providers);
+        assert!(found.contains(&"providers.anthropic.api_key".to_string()));
+        assert!(!found.contains(&"providers.vertex.api_key".to_string()));
+    }
+
+    #[test]
+    fn checks_application_default_credentials_of_vertex() {
+        let providers = r#"
+[providers.vertex]
+type = "gemini"
+project = "my-project"
+"#;
+        let path = std::env::temp_dir().join(format!("topkio-adc-{}.json", std::process::id()));
+        std::env::set_var("GOOGLE_APPLICATION_CREDENTIALS", &path);
+        assert_eq!(
+            issues("adc-missing", providers),
+            ["providers.vertex.credentials"]
+        );
+
+        std::fs::write(&path, r#"{"client_email": "a@b.iam.gserviceaccount.com"}"#).unwrap();
+        let found = issues("adc-invalid", providers);
+        std::fs::remove_file(&path).unwrap();
+        std::env::remove_var("GOOGLE_APPLICATION_CREDENTIALS");
+        assert_eq!(found, ["providers.vertex.credentials"]);
+    }
+
+    #[tokio::test]
+    async fn openai_keys_may_come_from_the_environment() {
+        let providers = r#"
+[providers.openai]
+type = "openai"
+url = "https://api.openai.com/v1"
+api_key = " "
+"#;
+        std::env::remove_var("OPENAI_API_KEY");
+        assert_eq!(
+            issues("openai-key", providers),
+            ["providers.openai.api_key"]
+        );
+        let config: TopkioConfig = toml::from_str(&format!(
+            "[server]\nhost = \"127.0.0.1\"\nport = 3000\n[logging]\nfile_path = \"topkio.log\"\n{}",
+            providers
+        ))
+        .unwrap();
+        let error = OpenAiFactory
+            .create("openai", &config.providers["openai"])
+            .await
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("OPENAI_API_KEY"), "{}", error);
+        assert!(error.to_string().contains("providers.openai"), "{}", error);
+
+        std::env::set_var("OPENAI_API_KEY", "sk-test");
+        let found = issues("openai-env-key", providers);
+        std::env::remove_var("OPENAI_API_KEY");
+        assert!(found.is_empty(), "{:?}", found);
+    }
+
+    #[test]
+    fn accepts_configured_api_keys() {
+        let providers = r#"
+[providers.gemini]
+url = "https://generativelanguage.googleapis.com/v1beta/models"
+api_key = "AIza-test"
+
+[providers.anthropic]
+url = "https://api.anthropic.com"
+api_key = "sk-ant-test"
+"#;
+        assert!(issues("keys", providers).is_empty());
+    }
+}
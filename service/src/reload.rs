@@ -0,0 +1,106 @@
+use {
+    crate::{AppState, Gateway},
+    anyhow::Result,
+    notify::{RecursiveMode, Watcher},
+    std::{path::Path, sync::Arc},
+    tokio::{sync::mpsc, time::Duration},
+    topkio_primitive::config::TopkioConfig,
+};
+
+/// Editors often write a file in several steps; wait for them to settle.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Re-validate the config file and swap in the new config and backends.
+///
+/// Nothing changes when the new config is invalid or a backend fails to
+/// initialize, so the gateway keeps serving with the previous config.
+pub async fn reload(state: &AppState) -> Result<()> {
+    let _guard = state.edit_lock.lock().await;
+    let config = state.options.load_config(&state.registry)?;
+    let backends = state.registry.build(&config.providers).await?;
+
+    let current = state.gateway();
+    let (old, new) = (&current.config.server, &config.server);
+    if (&old.host, old.port) != (&new.host, new.port) {
+        println!("server.host and server.port changes take effect after a restart");
+    }
+    let databases = |config: &TopkioConfig| {
+        (
+            config.virtual_keys.clone().map(|keys| keys.database),
+            config.usage.clone().map(|usage| usage.database),
+        )
+    };
+    if databases(&current.config) != databases(&config) {
+        println!("virtual_keys and usage changes take effect after a restart");
+    }
+
+    println!("Config reloaded, backends: {:?}", backends.keys());
+    state.gateway.store(Arc::new(Gateway { backends, config }));
+
+    Ok(())
+}
+
+/// Reload on SIGHUP and, when `watch` is set, whenever the config file changes.
+pub fn spawn(state: Arc<AppState>, watch: bool) -> Result<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<()>();
+
+    // Watch the directory rather than the file, since editors and config
+    // management tools usually replace the file instead of writing to it.
+    let watcher = match watch {
+        true => {
+            let path = Path::new(&state.options.config_path).canonicalize()?;
+            let file_name = path.file_name().map(|name| name.to_os_string());
+            let dir = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+
+            let tx = tx.clone();
+            let mut watcher =
+                notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                    let Ok(event) = event else {
+                        return;
+                    };
+                    let changed = event.kind.is_create() || event.kind.is_modify();
+                    if changed
+                        && event
+                            .paths
+                            .iter()
+                            .any(|p| p.file_name() == file_name.as_deref())
+                    {
+                        let _ = tx.send(());
+                    }
+                })?;
+            watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+            Some(watcher)
+        }
+        false => None,
+    };
+
+    #[cfg(unix)]
+    {
+        let mut sighup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            while sighup.recv().await.is_some() {
+                println!("Received SIGHUP, reloading config...");
+                if tx.send(()).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    tokio::spawn(async move {
+        // Keep the watcher alive as long as the reload task runs.
+        let _watcher = watcher;
+
+        while rx.recv().await.is_some() {
+            tokio::time::sleep(DEBOUNCE).await;
+            while rx.try_recv().is_ok() {}
+
+            if let Err(e) = reload(&state).await {
+                eprintln!("Config reload failed, keeping the current config: {}", e);
+            }
+        }
+    });
+
+    Ok(())
+}
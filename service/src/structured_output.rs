@@ -0,0 +1,332 @@
+//! Checks answers to requests with a `response_format`, asking the model
+//! again when an answer is not valid JSON or does not match the schema.
+
+use {
+    crate::{candidates, ApiError},
+    jsonschema::Validator,
+    topkio_primitive::api::{
+        ChatCompletionRequest, ChatCompletionResponse, Message, ResponseFormat, UnifiedLlmApi,
+        Usage,
+    },
+};
+
+/// Schema errors reported back to the model, at most.
+const MAX_REPORTED_ERRORS: usize = 5;
+
+/// Complete `request`, retrying up to `retries` times while the answer does
+/// not have the requested format.
+///
+/// Returns the usage of every attempt alongside the result, so failed
+/// attempts are accounted for too. The response carries the same total.
+pub async fn complete(
+    backend: &dyn UnifiedLlmApi,
+    model: &str,
+    mut request: ChatCompletionRequest,
+    retries: u32,
+) -> (Result<ChatCompletionResponse, ApiError>, Option<Usage>) {
+    let format = match request.response_format.clone() {
+        Some(ResponseFormat::Text) | None => {
+            return candidates::complete(backend, model, request).await;
+        }
+        Some(format) => format,
+    };
+
+    let validator = match &format {
+        ResponseFormat::JsonSchema { json_schema } => {
+            match jsonschema::validator_for(&json_schema.schema) {
+                Ok(validator) => Some(validator),
+                Err(e) => {
+                    let message = format!("invalid response_format schema: {}", e);
+                    return (Err(ApiError::BadRequest(message)), None);
+                }
+            }
+        }
+        _ => None,
+    };
+
+    let mut usage: Option<Usage> = None;
+    let mut attempt = 0;
+    loop {
+        let (result, attempt_usage) = candidates::complete(backend, model, request.clone()).await;
+        if let Some(attempt_usage) = &attempt_usage {
+            *usage.get_or_insert_with(Usage::default) += attempt_usage;
+        }
+        let mut response = match result {
+            Ok(response) => response,
+            Err(e) => return (Err(e), usage),
+        };
+
+        match check_candidates(&mut response, validator.as_ref()) {
+            Ok(()) => {
+                response.usage = usage.clone();
+                return (Ok(response), usage);
+            }
+            Err(problem) if attempt < retries => {
+                attempt += 1;
+                eprintln!(
+                    "Retrying {} ({}/{}): {}",
+                    request.model, attempt, retries, problem
+                );
+                request.messages.push(response.message);
+                request.messages.push(Message::new(
+                    "user",
+                    format!(
+                        "Your reply is not valid: {}. Reply again with only the corrected JSON.",
+                        problem
+                    ),
+                ));
+            }
+            Err(problem) => return (Err(ApiError::InvalidStructuredOutput(problem)), usage),
+        }
+    }
+}
+
+/// Check every candidate of `response`. With several, all of them are asked
+/// for again when one is invalid, and `response.message` is set to that one.
+fn check_candidates(
+    response: &mut ChatCompletionResponse,
+    validator: Option<&Validator>,
+) -> Result<(), String> {
+    if response.choices.is_empty() {
+        return check(&mut response.message, validator);
+    }
+
+    for choice in &mut response.choices {
+        if let Err(problem) = check(&mut choice.message, validator) {
+            response.message = choice.message.clone();
+            return Err(problem);
+        }
+    }
+    response.message = response.choices[0].message.clone();
+    Ok(())
+}
+
+/// Check that `message` is a JSON object, or matches `validator`. JSON
+/// wrapped in a Markdown code block is unwrapped.
+fn check(message: &mut Message, validator: Option<&Validator>) -> Result<(), String> {
+    // A tool call is not the final answer.
+    if message
+        .tool_calls
+        .as_ref()
+        .is_some_and(|calls| !calls.is_empty())
+    {
+        return Ok(());
+    }
+
+    let text = message.content.text();
+    let (value, unwrapped) = match serde_json::from_str::<serde_json::Value>(&text) {
+        Ok(value) => (value, None),
+        Err(e) => {
+            let not_json = || format!("the reply is not JSON ({})", e);
+            let inner = strip_code_block(&text).ok_or_else(not_json)?;
+            let value = serde_json::from_str(inner).map_err(|_| not_json())?;
+            (value, Some(inner))
+        }
+    };
+
+    match validator {
+        Some(validator) => {
+            let errors: Vec<String> = validator
+                .iter_errors(&value)
+                .take(MAX_REPORTED_ERRORS)
+                .map(|error| match error.instance_path().as_str() {
+                    "" => error.to_string(),
+                    path => format!("{} at {}", error, path),
+                })
+                .collect();
+            if !errors.is_empty() {
+                return Err(format!(
+                    "the JSON does not match the schema: {}",
+                    errors.join("; ")
+                ));
+            }
+        }
+        None if !value.is_object() => return Err("the reply is not a JSON object".into()),
+        None => {}
+    }
+
+    if let Some(json) = unwrapped {
+        message.content = json.to_string().into();
+    }
+    Ok(())
+}
+
+/// The body of a reply consisting of a single Markdown code block.
+fn strip_code_block(text: &str) -> Option<&str> {
+    let body = text.trim().strip_prefix("```")?.strip_suffix("```")?;
+    // Drop the info string, e.g. `json`.
+    let (_, body) = body.split_once('\n')?;
+    Some(body.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::{collections::VecDeque, sync::Mutex},
+        topkio_primitive::api::{Choice, JsonSchemaFormat},
+    };
+
+    /// A backend answering with `replies` in order, one per call.
+    struct Scripted {
+        replies: Mutex<VecDeque<&'static str>>,
+        requests: Mutex<Vec<ChatCompletionRequest>>,
+    }
+
+    impl Scripted {
+        fn new(replies: &[&'static str]) -> Self {
+            Self {
+                replies: Mutex::new(replies.iter().copied().collect()),
+                requests: Mutex::new(vec![]),
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl UnifiedLlmApi for Scripted {
+        async fn chat_completion(
+            &self,
+            _model: &str,
+            request: ChatCompletionRequest,
+        ) -> anyhow::Result<ChatCompletionResponse> {
+            self.requests.lock().unwrap().push(request);
+            let reply = self.replies.lock().unwrap().pop_front().expect("a reply");
+            let choice = Choice {
+                index: 0,
+                message: Message::new("assistant", reply),
+                finish_reason: Some("stop".into()),
+                logprobs: None,
+                extensions: None,
+            };
+            Ok(
+                ChatCompletionResponse::from_choices(vec![choice], Some(Usage::new(10, 5)))
+                    .unwrap(),
+            )
+        }
+    }
+
+    fn schema_request(n: Option<u32>) -> ChatCompletionRequest {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {"answer": {"type": "integer"}},
+            "required": ["answer"]
+        });
+        ChatCompletionRequest {
+            model: "mock:model".into(),
+            messages: vec![Message::new("user", "What is 6 x 7?")],
+            response_format: Some(ResponseFormat::JsonSchema {
+                json_schema: serde_json::from_value::<JsonSchemaFormat>(serde_json::json!({
+                    "name": "answer",
+                    "schema": schema
+                }))
+                .unwrap(),
+            }),
+            n,
+            ..Default::default()
+        }
+    }
+
+    fn validator() -> Validator {
+        jsonschema::validator_for(&serde_json::json!({
+            "type": "object",
+            "required": ["answer"]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn strips_single_code_blocks() {
+        assert_eq!(
+            strip_code_block("```json\n{\"a\": 1}\n```"),
+            Some("{\"a\": 1}")
+        );
+        assert_eq!(strip_code_block("  ```\n{}\n```  "), Some("{}"));
+        assert_eq!(strip_code_block("{\"a\": 1}"), None);
+        assert_eq!(strip_code_block("```{}```"), None);
+        assert_eq!(strip_code_block("Here:\n```json\n{}\n```"), None);
+    }
+
+    #[test]
+    fn checks_json_objects_and_schemas() {
+        let mut message = Message::new("assistant", "```json\n{\"answer\": 42}\n```");
+        assert_eq!(check(&mut message, Some(&validator())), Ok(()));
+        assert_eq!(message.content.text(), "{\"answer\": 42}");
+
+        let mut message = Message::new("assistant", "{\"other\": 1}");
+        let problem = check(&mut message, Some(&validator())).unwrap_err();
+        assert!(problem.contains("does not match the schema"), "{}", problem);
+        assert!(check(&mut message, None).is_ok());
+
+        let mut message = Message::new("assistant", "[1, 2]");
+        assert_eq!(
+            check(&mut message, None),
+            Err("the reply is not a JSON object".into())
+        );
+
+        let mut message = Message::new("assistant", "forty-two");
+        assert!(check(&mut message, None)
+            .unwrap_err()
+            .starts_with("the reply is not JSON"));
+    }
+
+    #[test]
+    fn tool_calls_are_not_checked() {
+        let mut message = Message::new("assistant", "");
+        message.tool_calls = Some(vec![serde_json::from_value(serde_json::json!({
+            "id": "call_1",
+            "type": "function",
+            "function": {"name": "multiply", "arguments": "{}"}
+        }))
+        .unwrap()]);
+        assert!(check(&mut message, Some(&validator())).is_ok());
+    }
+
+    #[tokio::test]
+    async fn retries_until_the_reply_is_valid() {
+        let backend = Scripted::new(&["forty-two", "{\"answer\": \"42\"}", "{\"answer\": 42}"]);
+        let (result, usage) = complete(&backend, "model", schema_request(None), 2).await;
+
+        let response = result.unwrap();
+        assert_eq!(response.message.content.text(), "{\"answer\": 42}");
+        assert_eq!(usage.unwrap().prompt_tokens, 30);
+        assert_eq!(response.usage.unwrap().completion_tokens, 15);
+
+        let requests = backend.requests.lock().unwrap();
+        let retry = &requests[2].messages;
+        assert_eq!(retry.len(), 5);
+        assert_eq!(retry[3].content.text(), "{\"answer\": \"42\"}");
+        assert!(retry[4]
+            .content
+            .text()
+            .starts_with("Your reply is not valid"));
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_retries() {
+        let backend = Scripted::new(&["no", "still no"]);
+        let (result, usage) = complete(&backend, "model", schema_request(None), 1).await;
+
+        assert!(matches!(result, Err(ApiError::InvalidStructuredOutput(_))));
+        assert_eq!(usage.unwrap().prompt_tokens, 20);
+    }
+
+    #[tokio::test]
+    async fn checks_every_candidate() {
+        let backend = Scripted::new(&[
+            "{\"answer\": 42}",
+            "not JSON",
+            "{\"answer\": 42}",
+            "```json\n{\"answer\": 42}\n```",
+        ]);
+        let (result, _) = complete(&backend, "model", schema_request(Some(2)), 1).await;
+
+        let response = result.unwrap();
+        assert_eq!(response.choices.len(), 2);
+        for choice in &response.choices {
+            assert_eq!(choice.message.content.text(), "{\"answer\": 42}");
+        }
+        let requests = backend.requests.lock().unwrap();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[2].messages[1].content.text(), "not JSON");
+    }
+}
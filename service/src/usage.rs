@@ -0,0 +1,167 @@
+//! Log of every request with its token counts and cost, aggregated into the
+//! `/v1/usage` report.
+
+use {
+    crate::ApiError,
+    rusqlite::{params, Connection},
+    serde::{Deserialize, Serialize},
+    std::{path::Path, sync::Mutex},
+    topkio_primitive::api::Usage,
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS usage_log (
+    id INTEGER PRIMARY KEY,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    key_id TEXT,
+    key_name TEXT,
+    team TEXT,
+    backend TEXT NOT NULL,
+    model TEXT NOT NULL,
+    prompt_tokens INTEGER NOT NULL,
+    completion_tokens INTEGER NOT NULL,
+    cached_tokens INTEGER NOT NULL,
+    cost REAL
+);
+CREATE INDEX IF NOT EXISTS usage_log_created_at ON usage_log (created_at);
+";
+
+/// Who made a request.
+#[derive(Debug, Clone, Default)]
+pub struct Caller {
+    /// Id of the virtual key used, if any.
+    pub key_id: Option<String>,
+    /// Virtual key name, or the masked static key.
+    pub key_name: Option<String>,
+    pub team: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    Key,
+    Team,
+    #[default]
+    Model,
+    Day,
+}
+
+impl GroupBy {
+    fn column(self) -> &'static str {
+        match self {
+            Self::Key => "COALESCE(key_name, '')",
+            Self::Team => "COALESCE(team, '')",
+            Self::Model => "backend || ':' || model",
+            Self::Day => "date(created_at)",
+        }
+    }
+}
+
+/// Restricts a report to a date range (inclusive, UTC) and optionally to
+/// one virtual key.
+#[derive(Debug, Clone, Default)]
+pub struct UsageFilter {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub key_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageRow {
+    pub group: String,
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cached_tokens: u64,
+    /// USD, counting only requests to priced models.
+    pub cost: f64,
+}
+
+pub struct UsageLog {
+    conn: Mutex<Connection>,
+}
+
+impl UsageLog {
+    /// Open (or create) the usage database at `path`.
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn record(
+        &self,
+        caller: &Caller,
+        backend: &str,
+        model: &str,
+        usage: &Usage,
+        cost: Option<f64>,
+    ) -> Result<(), ApiError> {
+        self.conn().execute(
+            "INSERT INTO usage_log (key_id, key_name, team, backend, model, prompt_tokens, \
+             completion_tokens, cached_tokens, cost) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                caller.key_id,
+                caller.key_name,
+                caller.team,
+                backend,
+                model,
+                usage.prompt_tokens,
+                usage.completion_tokens,
+                usage.cached_tokens.unwrap_or(0),
+                cost,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Totals per `group_by` value, largest cost first.
+    pub fn report(
+        &self,
+        group_by: GroupBy,
+        filter: &UsageFilter,
+    ) -> Result<Vec<UsageRow>, ApiError> {
+        let conn = self.conn();
+
+        for date in [&filter.from, &filter.to].into_iter().flatten() {
+            let valid: Option<String> =
+                conn.query_row("SELECT date(?1)", [date], |row| row.get(0))?;
+            if valid.is_none() {
+                return Err(ApiError::BadRequest(format!("invalid date \"{}\"", date)));
+            }
+        }
+
+        let mut statement = conn.prepare(&format!(
+            "SELECT {} AS grp, COUNT(*), SUM(prompt_tokens), SUM(completion_tokens), \
+                 SUM(cached_tokens), COALESCE(SUM(cost), 0) \
+             FROM usage_log \
+             WHERE (?1 IS NULL OR date(created_at) >= date(?1)) \
+                 AND (?2 IS NULL OR date(created_at) <= date(?2)) \
+                 AND (?3 IS NULL OR key_id = ?3) \
+             GROUP BY grp \
+             ORDER BY 6 DESC, grp",
+            group_by.column()
+        ))?;
+        let rows = statement
+            .query_map(params![filter.from, filter.to, filter.key_id], |row| {
+                Ok(UsageRow {
+                    group: row.get(0)?,
+                    requests: row.get::<_, i64>(1)? as u64,
+                    prompt_tokens: row.get::<_, i64>(2)? as u64,
+                    completion_tokens: row.get::<_, i64>(3)? as u64,
+                    cached_tokens: row.get::<_, i64>(4)? as u64,
+                    cost: row.get(5)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(rows)
+    }
+}
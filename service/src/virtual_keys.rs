@@ -0,0 +1,428 @@
+//! Virtual API keys: gateway keys handed to teams, each limited to some
+//! models and carrying its own token and spend budgets.
+//!
+//! Keys are stored hashed in SQLite together with their daily usage. Queries
+//! run on the blocking thread pool, since they hold the connection lock.
+
+use {
+    crate::ApiError,
+    rusqlite::{params, types::Type, Connection, OptionalExtension, Row},
+    serde::{de::DeserializeOwned, Deserialize, Serialize},
+    sha2::{Digest, Sha256},
+    std::{
+        collections::BTreeMap,
+        path::Path,
+        sync::{Arc, Mutex},
+    },
+    topkio_primitive::api::Usage,
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS virtual_keys (
+    id TEXT PRIMARY KEY,
+    key_hash TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    models TEXT NOT NULL,
+    daily_token_budget INTEGER,
+    monthly_token_budget INTEGER,
+    daily_spend_budget REAL,
+    monthly_spend_budget REAL,
+    expires_at TEXT,
+    tags TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+CREATE TABLE IF NOT EXISTS virtual_key_usage (
+    key_id TEXT NOT NULL REFERENCES virtual_keys(id) ON DELETE CASCADE,
+    day TEXT NOT NULL,
+    prompt_tokens INTEGER NOT NULL DEFAULT 0,
+    completion_tokens INTEGER NOT NULL DEFAULT 0,
+    spend REAL NOT NULL DEFAULT 0,
+    PRIMARY KEY (key_id, day)
+);
+";
+
+const KEY_COLUMNS: &str = "id, name, models, daily_token_budget, monthly_token_budget, \
+     daily_spend_budget, monthly_spend_budget, expires_at, tags, created_at";
+
+/// What a virtual key may do. Budgets are per UTC day and calendar month.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyLimits {
+    /// Allowed `backend:model`, `backend:*` or `backend` entries. Empty
+    /// allows every model.
+    #[serde(default)]
+    pub models: Vec<String>,
+    pub daily_token_budget: Option<u64>,
+    pub monthly_token_budget: Option<u64>,
+    pub daily_spend_budget: Option<f64>,
+    pub monthly_spend_budget: Option<f64>,
+    /// UTC date or datetime, e.g. `2025-12-31` or `2025-12-31 18:00:00`.
+    pub expires_at: Option<String>,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VirtualKey {
+    pub id: String,
+    pub name: String,
+    #[serde(flatten)]
+    pub limits: KeyLimits,
+    pub created_at: String,
+}
+
+impl VirtualKey {
+    /// Whether this key may use `model` on `backend`.
+    pub fn allows(&self, backend: &str, model: &str) -> bool {
+        let models = &self.limits.models;
+        models.is_empty()
+            || models.iter().any(|allowed| {
+                let (allowed_backend, allowed_model) =
+                    allowed.split_once(':').unwrap_or((allowed, "*"));
+                allowed_backend.eq_ignore_ascii_case(backend)
+                    && (allowed_model == "*" || allowed_model == model)
+            })
+    }
+
+    /// Fails on a malformed `models` or `tags` column rather than reading it
+    /// as empty, which would allow every model.
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        fn json<T: DeserializeOwned>(row: &Row, column: &str) -> rusqlite::Result<T> {
+            let value: String = row.get(column)?;
+            serde_json::from_str(&value).map_err(|e| {
+                let index = row.as_ref().column_index(column).unwrap_or_default();
+                rusqlite::Error::FromSqlConversionFailure(index, Type::Text, e.into())
+            })
+        }
+        Ok(Self {
+            id: row.get("id")?,
+            name: row.get("name")?,
+            limits: KeyLimits {
+                models: json(row, "models")?,
+                daily_token_budget: row
+                    .get::<_, Option<i64>>("daily_token_budget")?
+                    .map(|v| v as u64),
+                monthly_token_budget: row
+                    .get::<_, Option<i64>>("monthly_token_budget")?
+                    .map(|v| v as u64),
+                daily_spend_budget: row.get("daily_spend_budget")?,
+                monthly_spend_budget: row.get("monthly_spend_budget")?,
+                expires_at: row.get("expires_at")?,
+                tags: json(row, "tags")?,
+            },
+            created_at: row.get("created_at")?,
+        })
+    }
+}
+
+/// Usage of a key in the current UTC day and month.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct KeyUsage {
+    pub daily_tokens: u64,
+    pub monthly_tokens: u64,
+    pub daily_spend: f64,
+    pub monthly_spend: f64,
+}
+
+pub struct KeyStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl KeyStore {
+    /// Open (or create) the key database at `path`.
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        conn.execute_batch(SCHEMA)?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Run `query` on the connection without blocking the runtime.
+    async fn run<T, F>(&self, query: F) -> Result<T, ApiError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T, ApiError> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || query(&conn.lock().unwrap_or_else(|e| e.into_inner())))
+            .await
+            .map_err(|e| ApiError::StorageError(e.to_string()))?
+    }
+
+    /// Create a key, returning it together with its secret. The secret is
+    /// only stored hashed and cannot be retrieved later.
+    pub async fn create(
+        &self,
+        name: &str,
+        mut limits: KeyLimits,
+    ) -> Result<(VirtualKey, String), ApiError> {
+        let name = name.to_string();
+        self.run(move |conn| {
+            if let Some(expires_at) = &limits.expires_at {
+                let normalized: Option<String> =
+                    conn.query_row("SELECT datetime(?1)", [expires_at], |row| row.get(0))?;
+                limits.expires_at = Some(normalized.ok_or_else(|| {
+                    ApiError::BadRequest(format!("invalid expires_at \"{}\"", expires_at))
+                })?);
+            }
+
+            let id = uuid::Uuid::new_v4().simple().to_string();
+            let secret = format!("tk-{}", uuid::Uuid::new_v4().simple());
+            conn.execute(
+                "INSERT INTO virtual_keys (id, key_hash, name, models, daily_token_budget, \
+                 monthly_token_budget, daily_spend_budget, monthly_spend_budget, expires_at, tags) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                params![
+                    id,
+                    hash(&secret),
+                    name,
+                    serde_json::to_string(&limits.models).expect("models serialize"),
+                    limits.daily_token_budget.map(|v| v as i64),
+                    limits.monthly_token_budget.map(|v| v as i64),
+                    limits.daily_spend_budget,
+                    limits.monthly_spend_budget,
+                    limits.expires_at,
+                    serde_json::to_string(&limits.tags).expect("tags serialize"),
+                ],
+            )?;
+
+            let key = get(conn, &id)?
+                .ok_or_else(|| ApiError::StorageError("created key not found".into()))?;
+            Ok((key, secret))
+        })
+        .await
+    }
+
+    pub async fn list(&self) -> Result<Vec<VirtualKey>, ApiError> {
+        self.run(|conn| {
+            let mut statement = conn.prepare(&format!(
+                "SELECT {} FROM virtual_keys ORDER BY created_at, id",
+                KEY_COLUMNS
+            ))?;
+            let keys = statement
+                .query_map([], VirtualKey::from_row)?
+                .collect::<Result<_, _>>()?;
+            Ok(keys)
+        })
+        .await
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<VirtualKey>, ApiError> {
+        let id = id.to_string();
+        self.run(move |conn| get(conn, &id)).await
+    }
+
+    /// Delete a key and its usage. Returns whether it existed.
+    pub async fn delete(&self, id: &str) -> Result<bool, ApiError> {
+        let id = id.to_string();
+        self.run(move |conn| {
+            let deleted = conn.execute("DELETE FROM virtual_keys WHERE id = ?1", [id])?;
+            Ok(deleted > 0)
+        })
+        .await
+    }
+
+    /// Find the unexpired key with this secret.
+    pub async fn authenticate(&self, secret: &str) -> Result<Option<VirtualKey>, ApiError> {
+        let key_hash = hash(secret);
+        self.run(move |conn| {
+            let key = conn
+                .query_row(
+                    &format!(
+                        "SELECT {} FROM virtual_keys WHERE key_hash = ?1 \
+                         AND (expires_at IS NULL OR expires_at > datetime('now'))",
+                        KEY_COLUMNS
+                    ),
+                    [key_hash],
+                    VirtualKey::from_row,
+                )
+                .optional()?;
+            Ok(key)
+        })
+        .await
+    }
+
+    pub async fn usage(&self, id: &str) -> Result<KeyUsage, ApiError> {
+        let id = id.to_string();
+        self.run(move |conn| usage(conn, &id)).await
+    }
+
+    /// Reject the request when any budget of `key` is used up.
+    pub async fn check_budget(&self, key: &VirtualKey) -> Result<(), ApiError> {
+        let limits = &key.limits;
+        let usage = self.usage(&key.id).await?;
+
+        let exhausted = |used: f64, budget: Option<f64>| budget.is_some_and(|b| used >= b);
+        if exhausted(usage.daily_spend, limits.daily_spend_budget) {
+            return Err(ApiError::SpendBudgetExceeded(format!(
+                "daily budget of key {}",
+                key.name
+            )));
+        }
+        if exhausted(usage.monthly_spend, limits.monthly_spend_budget) {
+            return Err(ApiError::SpendBudgetExceeded(format!(
+                "monthly budget of key {}",
+                key.name
+            )));
+        }
+        if exhausted(
+            usage.daily_tokens as f64,
+            limits.daily_token_budget.map(|b| b as f64),
+        ) {
+            return Err(ApiError::TokenBudgetExceeded(format!(
+                "daily budget of key {}",
+                key.name
+            )));
+        }
+        if exhausted(
+            usage.monthly_tokens as f64,
+            limits.monthly_token_budget.map(|b| b as f64),
+        ) {
+            return Err(ApiError::TokenBudgetExceeded(format!(
+                "monthly budget of key {}",
+                key.name
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Add the usage of a finished request to today's totals of `key_id`.
+    pub async fn record_usage(
+        &self,
+        key_id: &str,
+        usage: &Usage,
+        spend: f64,
+    ) -> Result<(), ApiError> {
+        let key_id = key_id.to_string();
+        let (prompt_tokens, completion_tokens) = (usage.prompt_tokens, usage.completion_tokens);
+        self.run(move |conn| {
+            conn.execute(
+                "INSERT INTO virtual_key_usage (key_id, day, prompt_tokens, completion_tokens, spend) \
+                 VALUES (?1, date('now'), ?2, ?3, ?4) \
+                 ON CONFLICT (key_id, day) DO UPDATE SET \
+                     prompt_tokens = prompt_tokens + excluded.prompt_tokens, \
+                     completion_tokens = completion_tokens + excluded.completion_tokens, \
+                     spend = spend + excluded.spend",
+                params![key_id, prompt_tokens, completion_tokens, spend],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+fn get(conn: &Connection, id: &str) -> Result<Option<VirtualKey>, ApiError> {
+    let key = conn
+        .query_row(
+            &format!("SELECT {} FROM virtual_keys WHERE id = ?1", KEY_COLUMNS),
+            [id],
+            VirtualKey::from_row,
+        )
+        .optional()?;
+    Ok(key)
+}
+
+fn usage(conn: &Connection, id: &str) -> Result<KeyUsage, ApiError> {
+    let usage = conn.query_row(
+        "SELECT \
+             COALESCE(SUM(CASE WHEN day = date('now') THEN prompt_tokens + completion_tokens END), 0), \
+             COALESCE(SUM(prompt_tokens + completion_tokens), 0), \
+             COALESCE(SUM(CASE WHEN day = date('now') THEN spend END), 0), \
+             COALESCE(SUM(spend), 0) \
+         FROM virtual_key_usage \
+         WHERE key_id = ?1 AND day >= date('now', 'start of month')",
+        [id],
+        |row| {
+            Ok(KeyUsage {
+                daily_tokens: row.get::<_, i64>(0)? as u64,
+                monthly_tokens: row.get::<_, i64>(1)? as u64,
+                daily_spend: row.get(2)?,
+                monthly_spend: row.get(3)?,
+            })
+        },
+    )?;
+    Ok(usage)
+}
+
+fn hash(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> KeyStore {
+        KeyStore::open(Path::new(":memory:")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn authenticates_and_tracks_budgets() {
+        let store = store();
+        let limits = KeyLimits {
+            models: vec!["ollama:llama3.2".into()],
+            daily_token_budget: Some(100),
+            ..Default::default()
+        };
+        let (key, secret) = store.create("team-a", limits).await.unwrap();
+        assert!(store.authenticate("tk-wrong").await.unwrap().is_none());
+        let found = store.authenticate(&secret).await.unwrap().unwrap();
+        assert_eq!(found.id, key.id);
+        assert!(found.allows("ollama", "llama3.2"));
+        assert!(!found.allows("gemini", "gemini-2.0-flash"));
+
+        store.check_budget(&found).await.unwrap();
+        store
+            .record_usage(&key.id, &Usage::new(60, 40), 0.0)
+            .await
+            .unwrap();
+        assert_eq!(store.usage(&key.id).await.unwrap().daily_tokens, 100);
+        assert!(matches!(
+            store.check_budget(&found).await,
+            Err(ApiError::TokenBudgetExceeded(_))
+        ));
+
+        assert!(store.delete(&key.id).await.unwrap());
+        assert!(store.authenticate(&secret).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn malformed_scope_fails_closed() {
+        let store = store();
+        let (key, secret) = store.create("team-a", KeyLimits::default()).await.unwrap();
+        store
+            .conn
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE virtual_keys SET models = 'ollama:*' WHERE id = ?1",
+                [&key.id],
+            )
+            .unwrap();
+
+        assert!(matches!(
+            store.authenticate(&secret).await,
+            Err(ApiError::StorageError(_))
+        ));
+        assert!(store.list().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_expiry() {
+        let limits = KeyLimits {
+            expires_at: Some("next week".into()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            store().create("team-a", limits).await,
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+}